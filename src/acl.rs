@@ -0,0 +1,119 @@
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+use anyhow::{anyhow, bail};
+
+/// An address prefix such as `192.0.2.0/24` or `2001:db8::/32`; a bare
+/// address is a prefix of full length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => matches(&net.octets(), &addr.octets(), self.prefix),
+            (IpAddr::V6(net), IpAddr::V6(addr)) => matches(&net.octets(), &addr.octets(), self.prefix),
+            // IPv4 clients of a dual-stack socket show up as ::ffff:a.b.c.d
+            (IpAddr::V4(_), IpAddr::V6(addr)) => addr.to_ipv4_mapped().is_some_and(|a| self.contains(IpAddr::V4(a))),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+/// Whether the first `prefix` bits of `net` and `addr` agree.
+fn matches(net: &[u8], addr: &[u8], prefix: u8) -> bool {
+    let (bytes, bits) = (prefix as usize / 8, prefix % 8);
+    net[..bytes] == addr[..bytes] && (bits == 0 || (net[bytes] ^ addr[bytes]) >> (8 - bits) == 0)
+}
+
+impl FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = s.split_once('/').map_or((s, None), |(a, p)| (a, Some(p)));
+        let addr = addr.parse::<IpAddr>().map_err(|_| anyhow!("bad address {:?}", addr))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().ok().filter(|&p| p <= bits).ok_or_else(|| anyhow!("bad prefix length {:?}", p))?,
+            None => bits,
+        };
+        let octets = match addr {
+            IpAddr::V4(a) => a.octets().to_vec(),
+            IpAddr::V6(a) => a.octets().to_vec(),
+        };
+        if (prefix as usize..octets.len() * 8).any(|bit| octets[bit / 8] & (0x80 >> (bit % 8)) != 0) {
+            bail!("{} has host bits set", s)
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Which clients we answer. A client in `deny` is refused; otherwise it is
+/// answered if `allow` is empty or contains it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    pub allow: Vec<Network>,
+    pub deny: Vec<Network>,
+}
+
+impl Acl {
+    pub fn permits(&self, client: IpAddr) -> bool {
+        !self.deny.iter().any(|n| n.contains(client))
+            && (self.allow.is_empty() || self.allow.iter().any(|n| n.contains(client)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use crate::acl::{Acl, Network};
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_networks() {
+        assert_eq!("10.0.0.0/8".parse::<Network>().unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!("::1".parse::<Network>().unwrap().to_string(), "::1/128");
+        assert_eq!("192.0.2.7".parse::<Network>().unwrap().to_string(), "192.0.2.7/32");
+        assert_eq!("10.0.0.1/8".parse::<Network>().unwrap_err().to_string(), "10.0.0.1/8 has host bits set");
+        assert_eq!("10.0.0.0/33".parse::<Network>().unwrap_err().to_string(), "bad prefix length \"33\"");
+        assert!("example.com".parse::<Network>().is_err());
+    }
+
+    #[test]
+    fn matches_prefixes() {
+        let net = "192.0.2.128/25".parse::<Network>().unwrap();
+        assert!(net.contains(ip("192.0.2.200")));
+        assert!(!net.contains(ip("192.0.2.100")));
+        assert!(net.contains(ip("::ffff:192.0.2.200")));
+        let net = "2001:db8::/32".parse::<Network>().unwrap();
+        assert!(net.contains(ip("2001:db8:1::53")));
+        assert!(!net.contains(ip("2001:db9::53")));
+        assert!(!net.contains(ip("192.0.2.1")));
+        assert!("0.0.0.0/0".parse::<Network>().unwrap().contains(ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let acl = Acl {
+            allow: vec!["10.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()],
+            deny: vec!["10.1.0.0/16".parse().unwrap()],
+        };
+        assert!(acl.permits(ip("10.2.3.4")));
+        assert!(acl.permits(ip("::1")));
+        assert!(!acl.permits(ip("10.1.3.4")));
+        assert!(!acl.permits(ip("192.0.2.1")));
+        assert!(Acl::default().permits(ip("192.0.2.1")));
+    }
+}
@@ -1,7 +1,12 @@
 
 
-use bytes::{BufMut, BytesMut};
-use crate::message::{Class, Labels, Ty};
+use std::fmt::{Display, Formatter};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use anyhow::{anyhow, bail};
+use bytes::{BufMut, Bytes, BytesMut};
+use crate::encoding::{base32hex, base64, from_hex, hex, timestamp};
+use crate::error::{read_u16, read_u32, read_u8, ParseError};
+use crate::message::{Class, Compression, Labels, Ty};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Answer {
@@ -14,9 +19,52 @@ pub struct Answer {
 }
 
 #[non_exhaustive]
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Data {
     A(u32),
+    AAAA(u128),
+    NS(Labels),
+    CNAME(Labels),
+    PTR(Labels),
+    MX { preference: u16, exchange: Labels },
+    TXT(Vec<Bytes>),
+    SOA {
+        mname: Labels,
+        rname: Labels,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    SRV { priority: u16, weight: u16, port: u16, target: Labels },
+    CAA { flags: u8, tag: Bytes, value: Bytes },
+    DNSKEY { flags: u16, protocol: u8, algorithm: u8, public_key: Bytes },
+    DS { key_tag: u16, algorithm: u8, digest_type: u8, digest: Bytes },
+    RRSIG {
+        type_covered: Ty,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: Labels,
+        signature: Bytes,
+    },
+    NSEC { next: Labels, types: Vec<Ty> },
+    NSEC3 {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Bytes,
+        next_hashed: Bytes,
+        types: Vec<Ty>,
+    },
+    NSEC3PARAM { hash_algorithm: u8, flags: u8, iterations: u16, salt: Bytes },
+    /// RDATA of a type we have no model for, kept verbatim (RFC 3597).
+    Opaque(Bytes),
 }
 
 impl Default for Answer {
@@ -40,10 +88,33 @@ impl Answer {
     }
 
 
+    /// Builds a record whose type follows from the kind of `r_data`.
+    #[allow(dead_code)]
+    pub fn with_data<A: AsRef<str>>(name: A, class: Class, ttl: u32, r_data: Data) -> Self {
+        let ty = r_data.ty().expect("opaque rdata carries no type, use Answer::opaque");
+        Self::with_type(name, ty, class, ttl, r_data)
+    }
+
+    #[allow(dead_code)]
+    pub fn opaque<A: AsRef<str>>(name: A, ty: Ty, class: Class, ttl: u32, r_data: Bytes) -> Self {
+        Self::with_type(name, ty, class, ttl, Data::Opaque(r_data))
+    }
+
+    pub fn with_type<A: AsRef<str>>(name: A, ty: Ty, class: Class, ttl: u32, r_data: Data) -> Self {
+        Self {
+            name: Labels::from_domain(name.as_ref()),
+            ty,
+            class,
+            ttl,
+            rd_length: r_data.len(),
+            r_data,
+        }
+    }
+
     pub fn new<A: AsRef<str>>(name: A, ty: u16, class: u16, ttl: u32, data: u32) -> Self {
         let name = Labels::from_domain(name.as_ref());
-        let ty = Ty::try_from(ty).unwrap();
-        let class = Class::try_from(class).unwrap();
+        let ty = Ty::from(ty);
+        let class = Class::from(class);
         let r_data = match class {
             Class::IN => Data::A(data),
             _ => unimplemented!()
@@ -58,121 +129,664 @@ impl Answer {
             r_data,
         }
     }
+    #[allow(dead_code)]
     pub fn serialize(self) -> BytesMut {
-        let mut bytes = self.name.into_bytes_mut();
-        bytes.put_u8(0);
-        bytes.put_u16(self.ty as u16);
-        bytes.put_u16(self.class as u16);
-        bytes.put_u32(self.ttl);
-        bytes.put_u16(self.rd_length);
-        bytes.extend(self.r_data.as_bytes());
+        let mut bytes = BytesMut::new();
+        self.write(&mut bytes, &mut Compression::disabled());
         bytes
     }
 
-    pub fn deserialize(bytes: &[u8], start: usize) -> (Self, usize) {
-        // let mut buf = bytes.into_iter().copied();
-        // let labels = buf
-        //     .by_ref()
-        //     .take_while(|&c| {
-        //         c != b'\0' })
-        //     .collect::<BytesMut>();
-        // let name = Labels::from_bytes(&labels);
-        // let t = [buf.next().unwrap(), buf.next().unwrap()];
-        // let ty = u16::from_be_bytes(t);
-        // let ty = Ty::try_from(ty).unwrap();
-        // let c = [buf.next().unwrap(), buf.next().unwrap()];
-        // let class = u16::from_be_bytes(c);
-        // let class = Class::try_from(class).unwrap();
-        // let tl:[u8; 4] = buf.by_ref().take(4).collect::<Vec<_>>().try_into().unwrap();
-        // let ttl = u32::from_be_bytes(tl);
-        // let len = [buf.next().unwrap(), buf.next().unwrap()];
-        // let rd_length = u16::from_be_bytes(len);
-        // let data : [u8; 4] = buf.by_ref().take(rd_length as usize).collect::<Vec<_>>().try_into().unwrap();
-        // let r_data = Data::A(u32::from_be_bytes(data));
-        // let l = labels.len() + 1 + 2 + 2 + 4 + 2 + rd_length as usize; // 15
-        let is_compressed = (bytes[start] & 0b1100_0000) == 0b1100_0000;
-        let (labels, end) = if is_compressed {
-            let offset = u16::from_be_bytes([bytes[start], bytes[start + 1]]);
-            let offset = offset ^ 0b1100_0000_0000_0000u16;
-            let l = bytes[offset as usize..]
-                .iter()
-                .copied()
-                .take_while(|&c| {
-                    c != b'\0'
-                })
-                .collect::<BytesMut>();
-            let e = start + 1;
-            (l, e)
-        } else {
-            let l =
-                bytes[start..]
-                    .iter()
-                    .copied()
-                    .take_while(|&c| {
-                        c != b'\0'
-                    })
-                    .collect::<BytesMut>();
-            let e = start + l.len();
-            (l, e)
-        };
-        let name = Labels::from_bytes(&labels);
-        let ty = u16::from_be_bytes([bytes[end + 1], bytes[end + 2]]);
-        let ty = Ty::try_from(ty).unwrap();
-        let class = u16::from_be_bytes([bytes[end + 3], bytes[end + 4]]);
-        let class = Class::try_from(class).unwrap();
-        let ttl = u32::from_be_bytes([
-            bytes[end + 5],
-            bytes[end + 6],
-            bytes[end + 7],
-            bytes[end + 8],
-        ]);
-        let rd_length = u16::from_be_bytes([bytes[end + 9], bytes[end + 10]]);
-        let r_data = Data::A(u32::from_be_bytes([
-            bytes[end + 11],
-            bytes[end + 12],
-            bytes[end + 13],
-            bytes[end + 14],
-        ]));
-        let l = end + 15;
-
-        (Self {
+    pub fn write(&self, buf: &mut BytesMut, table: &mut Compression) {
+        self.name.write(buf, table);
+        buf.put_u16(self.ty.into());
+        buf.put_u16(self.class.into());
+        buf.put_u32(self.ttl);
+        let len_at = buf.len();
+        buf.put_u16(0);
+        self.r_data.write(buf, table);
+        let rd_length = (buf.len() - len_at - 2) as u16;
+        buf[len_at..len_at + 2].copy_from_slice(&rd_length.to_be_bytes());
+    }
+
+    pub fn deserialize(bytes: &[u8], start: usize) -> Result<(Self, usize), ParseError> {
+        // compressed owner names may point at a suffix that is itself
+        // compressed, so they have to go through the full label parser
+        let (name, end) = Labels::parse(bytes, start)?;
+        let ty = Ty::from(read_u16(bytes, end)?);
+        let class = Class::from(read_u16(bytes, end + 2)?);
+        let ttl = read_u32(bytes, end + 4)?;
+        let rd_length = read_u16(bytes, end + 8)?;
+        let r_data = Data::deserialize(&ty, bytes, end + 10, rd_length)?;
+        let l = end + 10 + rd_length as usize;
+
+        Ok((Self {
             name,
             ty,
             class,
             ttl,
             rd_length,
             r_data,
-        }, l)
+        }, l))
     }
     #[allow(dead_code)]
     pub fn domain(&self) -> String {
         self.name.to_string()
     }
+
+    /// Owner name (lowercased), type and class: records sharing it form an RRset.
+    pub fn rrset_key(&self) -> (BytesMut, Ty, Class) {
+        (self.name.to_lowercase().to_bytes(), self.ty, self.class)
+    }
+
+    #[allow(dead_code)]
+    pub fn data(&self) -> &Data {
+        &self.r_data
+    }
+
+    pub fn ty(&self) -> Ty {
+        self.ty
+    }
+
+    pub fn class(&self) -> Class {
+        self.class
+    }
+
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = ttl
+    }
+
+    /// Moves the record to another owner, as when expanding a wildcard.
+    pub fn set_name(&mut self, name: Labels) {
+        self.name = name
+    }
 }
 
 impl Data {
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> u16 {
-        match *self {
-            Data::A(_) => 4
-        }
+        self.as_bytes().len() as u16
     }
+
+    /// Record type implied by the variant, `None` for opaque data.
+    pub fn ty(&self) -> Option<Ty> {
+        let ty = match self {
+            Data::A(_) => Ty::A,
+            Data::AAAA(_) => Ty::AAAA,
+            Data::NS(_) => Ty::NS,
+            Data::CNAME(_) => Ty::CNAME,
+            Data::PTR(_) => Ty::PTR,
+            Data::MX { .. } => Ty::MX,
+            Data::TXT(_) => Ty::TXT,
+            Data::SOA { .. } => Ty::SOA,
+            Data::SRV { .. } => Ty::SRV,
+            Data::CAA { .. } => Ty::CAA,
+            Data::DNSKEY { .. } => Ty::DNSKEY,
+            Data::DS { .. } => Ty::DS,
+            Data::RRSIG { .. } => Ty::RRSIG,
+            Data::NSEC { .. } => Ty::NSEC,
+            Data::NSEC3 { .. } => Ty::NSEC3,
+            Data::NSEC3PARAM { .. } => Ty::NSEC3PARAM,
+            Data::Opaque(_) => return None,
+        };
+        Some(ty)
+    }
+
     pub fn as_bytes(&self) -> BytesMut {
+        let mut buf = BytesMut::new();
+        self.write(&mut buf, &mut Compression::disabled());
+        buf
+    }
+
+    /// Names in the RFC 1035 types may be compressed; SRV and newer types
+    /// must be written in full (RFC 2782, RFC 3597 section 4).
+    pub fn write(&self, buf: &mut BytesMut, table: &mut Compression) {
+        match self {
+            Data::A(a) => buf.put_u32(*a),
+            Data::AAAA(a) => buf.put_u128(*a),
+            Data::NS(name) | Data::CNAME(name) | Data::PTR(name) => name.write(buf, table),
+            Data::MX { preference, exchange } => {
+                buf.put_u16(*preference);
+                exchange.write(buf, table);
+            }
+            Data::TXT(strings) => {
+                for s in strings {
+                    buf.put_u8(s.len() as u8);
+                    buf.extend_from_slice(s);
+                }
+            }
+            Data::SOA { mname, rname, serial, refresh, retry, expire, minimum } => {
+                mname.write(buf, table);
+                rname.write(buf, table);
+                for v in [serial, refresh, retry, expire, minimum] {
+                    buf.put_u32(*v);
+                }
+            }
+            Data::SRV { priority, weight, port, target } => {
+                buf.put_u16(*priority);
+                buf.put_u16(*weight);
+                buf.put_u16(*port);
+                buf.extend(target.to_bytes());
+            }
+            Data::CAA { flags, tag, value } => {
+                buf.put_u8(*flags);
+                buf.put_u8(tag.len() as u8);
+                buf.extend_from_slice(tag);
+                buf.extend_from_slice(value);
+            }
+            Data::DNSKEY { flags, protocol, algorithm, public_key } => {
+                buf.put_u16(*flags);
+                buf.put_u8(*protocol);
+                buf.put_u8(*algorithm);
+                buf.extend_from_slice(public_key);
+            }
+            Data::DS { key_tag, algorithm, digest_type, digest } => {
+                buf.put_u16(*key_tag);
+                buf.put_u8(*algorithm);
+                buf.put_u8(*digest_type);
+                buf.extend_from_slice(digest);
+            }
+            Data::RRSIG { signature, .. } => {
+                buf.extend(self.rrsig_header());
+                buf.extend_from_slice(signature);
+            }
+            Data::NSEC { next, types } => {
+                buf.extend(next.to_bytes());
+                write_type_bitmap(buf, types);
+            }
+            Data::NSEC3 { hash_algorithm, flags, iterations, salt, next_hashed, types } => {
+                buf.put_u8(*hash_algorithm);
+                buf.put_u8(*flags);
+                buf.put_u16(*iterations);
+                buf.put_u8(salt.len() as u8);
+                buf.extend_from_slice(salt);
+                buf.put_u8(next_hashed.len() as u8);
+                buf.extend_from_slice(next_hashed);
+                write_type_bitmap(buf, types);
+            }
+            Data::NSEC3PARAM { hash_algorithm, flags, iterations, salt } => {
+                buf.put_u8(*hash_algorithm);
+                buf.put_u8(*flags);
+                buf.put_u16(*iterations);
+                buf.put_u8(salt.len() as u8);
+                buf.extend_from_slice(salt);
+            }
+            Data::Opaque(bytes) => buf.extend_from_slice(bytes),
+        }
+    }
+
+    /// The RRSIG RDATA up to and including the signer's name: the part that
+    /// is itself signed (RFC 4034 section 3.1.8.1). Empty for other types.
+    pub fn rrsig_header(&self) -> BytesMut {
+        let mut buf = BytesMut::new();
+        if let Data::RRSIG { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, .. } = self {
+            buf.put_u16((*type_covered).into());
+            buf.put_u8(*algorithm);
+            buf.put_u8(*labels);
+            buf.put_u32(*original_ttl);
+            buf.put_u32(*expiration);
+            buf.put_u32(*inception);
+            buf.put_u16(*key_tag);
+            buf.extend(signer.to_bytes());
+        }
+        buf
+    }
+
+    /// Decodes `rd_length` bytes of RDATA starting at `start`. Names inside
+    /// the RDATA may be compressed, so `bytes` has to be the whole message.
+    pub fn deserialize(ty: &Ty, bytes: &[u8], start: usize, rd_length: u16) -> Result<Self, ParseError> {
+        let end = start + rd_length as usize;
+        if bytes.len() < end {
+            return Err(ParseError::Truncated { offset: bytes.len() })
+        }
+        let bad = || ParseError::BadRdata { ty: (*ty).into(), offset: start };
+        let rdata = &bytes[..end];
+        let (data, consumed) = match ty {
+            Ty::A => (Data::A(read_u32(rdata, start)?), start + 4),
+            Ty::AAAA => {
+                let b = rdata.get(start..start + 16).ok_or_else(bad)?;
+                (Data::AAAA(u128::from_be_bytes(b.try_into().unwrap())), start + 16)
+            }
+            Ty::NS | Ty::CNAME | Ty::PTR => {
+                let (name, e) = Labels::parse(rdata, start)?;
+                let data = match ty {
+                    Ty::NS => Data::NS(name),
+                    Ty::CNAME => Data::CNAME(name),
+                    _ => Data::PTR(name),
+                };
+                (data, e)
+            }
+            Ty::MX => {
+                let preference = read_u16(rdata, start)?;
+                let (exchange, e) = Labels::parse(rdata, start + 2)?;
+                (Data::MX { preference, exchange }, e)
+            }
+            Ty::TXT => {
+                let mut strings = vec![];
+                let mut offset = start;
+                while offset < end {
+                    let len = read_u8(rdata, offset)? as usize;
+                    let s = rdata.get(offset + 1..offset + 1 + len).ok_or_else(bad)?;
+                    strings.push(Bytes::copy_from_slice(s));
+                    offset += 1 + len;
+                }
+                (Data::TXT(strings), offset)
+            }
+            Ty::SOA => {
+                let (mname, e) = Labels::parse(rdata, start)?;
+                let (rname, e) = Labels::parse(rdata, e)?;
+                let data = Data::SOA {
+                    mname,
+                    rname,
+                    serial: read_u32(rdata, e)?,
+                    refresh: read_u32(rdata, e + 4)?,
+                    retry: read_u32(rdata, e + 8)?,
+                    expire: read_u32(rdata, e + 12)?,
+                    minimum: read_u32(rdata, e + 16)?,
+                };
+                (data, e + 20)
+            }
+            Ty::SRV => {
+                let priority = read_u16(rdata, start)?;
+                let weight = read_u16(rdata, start + 2)?;
+                let port = read_u16(rdata, start + 4)?;
+                let (target, e) = Labels::parse(rdata, start + 6)?;
+                (Data::SRV { priority, weight, port, target }, e)
+            }
+            Ty::CAA => {
+                let flags = read_u8(rdata, start)?;
+                let tag_len = read_u8(rdata, start + 1)? as usize;
+                let tag = rdata.get(start + 2..start + 2 + tag_len).ok_or_else(bad)?;
+                let value = &rdata[start + 2 + tag_len..];
+                let data = Data::CAA {
+                    flags,
+                    tag: Bytes::copy_from_slice(tag),
+                    value: Bytes::copy_from_slice(value),
+                };
+                (data, end)
+            }
+            Ty::DNSKEY => {
+                let data = Data::DNSKEY {
+                    flags: read_u16(rdata, start)?,
+                    protocol: read_u8(rdata, start + 2)?,
+                    algorithm: read_u8(rdata, start + 3)?,
+                    public_key: Bytes::copy_from_slice(&rdata[start + 4..]),
+                };
+                (data, end)
+            }
+            Ty::DS => {
+                let data = Data::DS {
+                    key_tag: read_u16(rdata, start)?,
+                    algorithm: read_u8(rdata, start + 2)?,
+                    digest_type: read_u8(rdata, start + 3)?,
+                    digest: Bytes::copy_from_slice(&rdata[start + 4..]),
+                };
+                (data, end)
+            }
+            Ty::RRSIG => {
+                let (signer, e) = Labels::parse(rdata, start + 18)?;
+                let data = Data::RRSIG {
+                    type_covered: Ty::from(read_u16(rdata, start)?),
+                    algorithm: read_u8(rdata, start + 2)?,
+                    labels: read_u8(rdata, start + 3)?,
+                    original_ttl: read_u32(rdata, start + 4)?,
+                    expiration: read_u32(rdata, start + 8)?,
+                    inception: read_u32(rdata, start + 12)?,
+                    key_tag: read_u16(rdata, start + 16)?,
+                    signer,
+                    signature: Bytes::copy_from_slice(rdata.get(e..).ok_or_else(bad)?),
+                };
+                (data, end)
+            }
+            Ty::NSEC => {
+                let (next, e) = Labels::parse(rdata, start)?;
+                let types = read_type_bitmap(rdata.get(e..).ok_or_else(bad)?).ok_or_else(bad)?;
+                (Data::NSEC { next, types }, end)
+            }
+            Ty::NSEC3 => {
+                let salt_len = read_u8(rdata, start + 4)? as usize;
+                let salt = rdata.get(start + 5..start + 5 + salt_len).ok_or_else(bad)?;
+                let hash_at = start + 5 + salt_len;
+                let hash_len = read_u8(rdata, hash_at)? as usize;
+                let next_hashed = rdata.get(hash_at + 1..hash_at + 1 + hash_len).ok_or_else(bad)?;
+                let types = read_type_bitmap(&rdata[hash_at + 1 + hash_len..]).ok_or_else(bad)?;
+                let data = Data::NSEC3 {
+                    hash_algorithm: read_u8(rdata, start)?,
+                    flags: read_u8(rdata, start + 1)?,
+                    iterations: read_u16(rdata, start + 2)?,
+                    salt: Bytes::copy_from_slice(salt),
+                    next_hashed: Bytes::copy_from_slice(next_hashed),
+                    types,
+                };
+                (data, end)
+            }
+            Ty::NSEC3PARAM => {
+                let salt_len = read_u8(rdata, start + 4)? as usize;
+                let data = Data::NSEC3PARAM {
+                    hash_algorithm: read_u8(rdata, start)?,
+                    flags: read_u8(rdata, start + 1)?,
+                    iterations: read_u16(rdata, start + 2)?,
+                    salt: Bytes::copy_from_slice(rdata.get(start + 5..start + 5 + salt_len).ok_or_else(bad)?),
+                };
+                (data, start + 5 + salt_len)
+            }
+            _ => (Data::Opaque(Bytes::copy_from_slice(&rdata[start..])), end),
+        };
+        if consumed != end {
+            return Err(bad())
+        }
+        Ok(data)
+    }
+}
+
+impl Data {
+    /// Parses the RFC 3597 generic form `\# <length> <hex>...`, which is valid
+    /// for any type. Known types are decoded into their typed variant.
+    #[allow(dead_code)]
+    pub fn from_generic(ty: &Ty, text: &str) -> anyhow::Result<Self> {
+        let mut tokens = text.split_whitespace();
+        if tokens.next() != Some("\\#") {
+            bail!("generic rdata must start with \\#")
+        }
+        let len: usize = tokens.next().ok_or_else(|| anyhow!("missing rdata length"))?.parse()?;
+        let bytes = from_hex(&tokens.collect::<String>())?;
+        if bytes.len() != len {
+            bail!("rdata length is {} but {} octets were given", len, bytes.len())
+        }
+        Ok(Data::deserialize(ty, &bytes, 0, len as u16)?)
+    }
+}
+
+/// Type bitmap windows of RFC 4034 section 4.1.2: for each block of 256
+/// types in use, its number, the bitmap length and the bitmap itself.
+fn write_type_bitmap(buf: &mut BytesMut, types: &[Ty]) {
+    let mut types = types.iter().map(|&t| u16::from(t)).collect::<Vec<_>>();
+    types.sort_unstable();
+    types.dedup();
+    for window in types.chunk_by(|a, b| a >> 8 == b >> 8) {
+        let mut bitmap = [0u8; 32];
+        for &t in window {
+            bitmap[(t & 0xff) as usize / 8] |= 0x80 >> (t % 8);
+        }
+        let len = bitmap.iter().rposition(|&b| b != 0).unwrap_or(0) + 1;
+        buf.put_u8((window[0] >> 8) as u8);
+        buf.put_u8(len as u8);
+        buf.extend_from_slice(&bitmap[..len]);
+    }
+}
+
+fn read_type_bitmap(mut bytes: &[u8]) -> Option<Vec<Ty>> {
+    let mut types = vec![];
+    let mut last_window = None;
+    while !bytes.is_empty() {
+        let (window, len) = (*bytes.first()?, *bytes.get(1)? as usize);
+        if len == 0 || len > 32 || last_window >= Some(window) {
+            return None
+        }
+        let bitmap = bytes.get(2..2 + len)?;
+        for (i, &b) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if b & 0x80 >> bit != 0 {
+                    types.push(Ty::from((window as u16) << 8 | (i * 8 + bit) as u16));
+                }
+            }
+        }
+        last_window = Some(window);
+        bytes = &bytes[2 + len..];
+    }
+    Some(types)
+}
+
+fn fqdn(name: &Labels) -> String {
+    format!("{}.", name)
+}
+
+/// NSEC3 salts in hex, with `-` for none.
+fn salt_text(salt: &[u8]) -> String {
+    match salt.is_empty() {
+        true => "-".to_string(),
+        false => hex(salt),
+    }
+}
+
+fn write_types(f: &mut Formatter<'_>, types: &[Ty]) -> std::fmt::Result {
+    types.iter().try_for_each(|t| write!(f, " {}", t))
+}
+
+fn write_character_string(f: &mut Formatter<'_>, s: &[u8]) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for &c in s {
+        match c {
+            b'"' | b'\\' => write!(f, "\\{}", c as char)?,
+            0x20..=0x7e => write!(f, "{}", c as char)?,
+            _ => write!(f, "\\{:03}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// One master-file line: owner, TTL, class, type and RDATA.
+impl Display for Answer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {} {} {}", fqdn(&self.name), self.ttl, self.class, self.ty, self.r_data)
+    }
+}
+
+/// Presentation format, as used in zone files and by `dig`.
+impl Display for Data {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Data::A(a) => { BytesMut::from(&a.to_be_bytes()[..]) }
+            Data::A(a) => write!(f, "{}", Ipv4Addr::from(*a)),
+            Data::AAAA(a) => write!(f, "{}", Ipv6Addr::from(*a)),
+            Data::NS(name) | Data::CNAME(name) | Data::PTR(name) => write!(f, "{}", fqdn(name)),
+            Data::MX { preference, exchange } => write!(f, "{} {}", preference, fqdn(exchange)),
+            Data::TXT(strings) => {
+                for (i, s) in strings.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write_character_string(f, s)?;
+                }
+                Ok(())
+            }
+            Data::SOA { mname, rname, serial, refresh, retry, expire, minimum } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                fqdn(mname), fqdn(rname), serial, refresh, retry, expire, minimum
+            ),
+            Data::SRV { priority, weight, port, target } => {
+                write!(f, "{} {} {} {}", priority, weight, port, fqdn(target))
+            }
+            Data::CAA { flags, tag, value } => {
+                write!(f, "{} {} ", flags, String::from_utf8_lossy(tag))?;
+                write_character_string(f, value)
+            }
+            Data::DNSKEY { flags, protocol, algorithm, public_key } => {
+                write!(f, "{} {} {} {}", flags, protocol, algorithm, base64(public_key))
+            }
+            Data::DS { key_tag, algorithm, digest_type, digest } => {
+                write!(f, "{} {} {} {}", key_tag, algorithm, digest_type, hex(digest))
+            }
+            Data::RRSIG { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature } => write!(
+                f,
+                "{} {} {} {} {} {} {} {} {}",
+                type_covered, algorithm, labels, original_ttl, timestamp(*expiration), timestamp(*inception),
+                key_tag, fqdn(signer), base64(signature)
+            ),
+            Data::NSEC { next, types } => {
+                write!(f, "{}", fqdn(next))?;
+                write_types(f, types)
+            }
+            Data::NSEC3 { hash_algorithm, flags, iterations, salt, next_hashed, types } => {
+                write!(f, "{} {} {} {} {}", hash_algorithm, flags, iterations, salt_text(salt), base32hex(next_hashed))?;
+                write_types(f, types)
+            }
+            Data::NSEC3PARAM { hash_algorithm, flags, iterations, salt } => {
+                write!(f, "{} {} {} {}", hash_algorithm, flags, iterations, salt_text(salt))
+            }
+            Data::Opaque(bytes) => {
+                write!(f, "\\# {}", bytes.len())?;
+                if !bytes.is_empty() {
+                    write!(f, " ")?;
+                }
+                bytes.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::answer::Answer;
+    use bytes::Bytes;
+    use crate::answer::{Answer, Data};
+    use crate::error::ParseError;
+    use crate::message::{Class, Labels, Ty};
 
     #[test]
     pub fn serialize_de() {
         let ans = Answer::from_domain_name("hello.world.io");
         let ser = ans.clone().serialize();
-        let (de, _) = Answer::deserialize(&ser, 0);
+        let (de, end) = Answer::deserialize(&ser, 0).unwrap();
         assert_eq!(ans, de);
+        assert_eq!(end, ser.len());
+    }
+
+    fn round_trip(data: Data) -> Answer {
+        let ans = Answer::with_data("example.com", Class::IN, 300, data);
+        let ser = ans.clone().serialize();
+        let (de, end) = Answer::deserialize(&ser, 0).unwrap();
+        assert_eq!(end, ser.len());
+        assert_eq!(ans, de);
+        de
+    }
+
+    #[test]
+    fn typed_rdata_round_trip() {
+        round_trip(Data::AAAA(0x2001_0db8_0000_0000_0000_0000_0000_0001));
+        round_trip(Data::NS(Labels::from_domain("ns1.example.com")));
+        round_trip(Data::CNAME(Labels::from_domain("www.example.com")));
+        round_trip(Data::PTR(Labels::from_domain("host.example.com")));
+        round_trip(Data::MX { preference: 10, exchange: Labels::from_domain("mail.example.com") });
+        round_trip(Data::TXT(vec![Bytes::from_static(b"v=spf1 -all"), Bytes::new()]));
+        round_trip(Data::SOA {
+            mname: Labels::from_domain("ns1.example.com"),
+            rname: Labels::from_domain("hostmaster.example.com"),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        });
+        round_trip(Data::SRV { priority: 0, weight: 5, port: 5060, target: Labels::from_domain("sip.example.com") });
+        round_trip(Data::CAA { flags: 0, tag: Bytes::from_static(b"issue"), value: Bytes::from_static(b"letsencrypt.org") });
+        round_trip(Data::DNSKEY { flags: 257, protocol: 3, algorithm: 15, public_key: Bytes::from_static(&[7; 32]) });
+        round_trip(Data::DS { key_tag: 2371, algorithm: 13, digest_type: 2, digest: Bytes::from_static(&[9; 32]) });
+        round_trip(Data::RRSIG {
+            type_covered: Ty::A,
+            algorithm: 13,
+            labels: 2,
+            original_ttl: 300,
+            expiration: 1_900_000_000,
+            inception: 1_700_000_000,
+            key_tag: 2371,
+            signer: Labels::from_domain("example.com"),
+            signature: Bytes::from_static(&[1; 64]),
+        });
+        round_trip(Data::NSEC3 {
+            hash_algorithm: 1,
+            flags: 1,
+            iterations: 0,
+            salt: Bytes::new(),
+            next_hashed: Bytes::from_static(&[3; 20]),
+            types: vec![],
+        });
+        round_trip(Data::NSEC3PARAM { hash_algorithm: 1, flags: 0, iterations: 0, salt: Bytes::from_static(b"\xab") });
+    }
+
+    #[test]
+    fn nsec_type_bitmap() {
+        // the example of RFC 4034 section 4.3
+        let nsec = Data::NSEC {
+            next: Labels::from_domain("host.example.com"),
+            types: vec![Ty::A, Ty::MX, Ty::RRSIG, Ty::NSEC, Ty::Unknown(1234)],
+        };
+        let mut expected = b"\x04host\x07example\x03com\x00\x00\x06\x40\x01\x00\x00\x00\x03\x04\x1b".to_vec();
+        expected.extend_from_slice(&[0; 26]);
+        expected.push(0x20);
+        assert_eq!(&nsec.as_bytes()[..], &expected[..]);
+        assert_eq!(round_trip(nsec.clone()).data(), &nsec);
+        assert_eq!(nsec.to_string(), "host.example.com. A MX RRSIG NSEC TYPE1234");
+        // the root as next name, then window 1 before window 0
+        let bad = b"\x00\x01\x01\x40\x00\x01\x40";
+        assert!(Data::deserialize(&Ty::NSEC, bad, 0, bad.len() as u16).is_err());
+    }
+
+    #[test]
+    fn compressed_name_in_rdata() {
+        // owner "a.io" at offset 0, MX exchange "mx" + pointer back to "io"
+        let val = b"\x01a\x02io\0\0\x0f\0\x01\0\0\0\x3c\0\x07\0\x0a\x02mx\xc0\x02";
+        let (de, end) = Answer::deserialize(val, 0).unwrap();
+        assert_eq!(end, val.len());
+        assert_eq!(de.data().to_string(), "10 mx.io.");
+    }
+
+    #[test]
+    fn rrset_key_compares_wire_names() {
+        // one label "www.example" against the two labels "www" and "example"
+        let val = b"\x0bwww.example\0\0\x01\0\x01\0\0\0\x3c\0\x04\x7f\0\0\x01";
+        let (dotted, _) = Answer::deserialize(val, 0).unwrap();
+        let two_labels = Answer::new("WWW.example", 1, 1, 60, 0x7f000001);
+        assert_ne!(dotted.rrset_key(), two_labels.rrset_key());
+        assert_eq!(two_labels.rrset_key(), Answer::new("www.example", 1, 1, 300, 1).rrset_key());
     }
-}
 
+    #[test]
+    fn rd_length_mismatch_is_an_error() {
+        let val = b"\x01a\x02io\0\0\x01\0\x01\0\0\0\x3c\0\x05\x01\x02\x03\x04\x05";
+        assert_eq!(Answer::deserialize(val, 0), Err(ParseError::BadRdata { ty: 1, offset: 16 }));
+    }
+
+    #[test]
+    fn presentation_format() {
+        let txt = Data::TXT(vec![Bytes::from_static(b"say \"hi\"\x01")]);
+        assert_eq!(txt.to_string(), r#""say \"hi\"\001""#);
+        assert_eq!(Data::AAAA(1).to_string(), "::1");
+        let ds = Data::DS { key_tag: 60485, algorithm: 5, digest_type: 1, digest: Bytes::from_static(b"\x2b\xb1") };
+        assert_eq!(ds.to_string(), "60485 5 1 2BB1");
+        let nsec3 = Data::NSEC3 {
+            hash_algorithm: 1,
+            flags: 0,
+            iterations: 12,
+            salt: Bytes::from_static(b"\xaa\xbb\xcc\xdd"),
+            next_hashed: Bytes::from_static(b"foobar"),
+            types: vec![Ty::A, Ty::RRSIG],
+        };
+        assert_eq!(nsec3.to_string(), "1 0 12 AABBCCDD CPNMUOJ1E8 A RRSIG");
+        let ns = Answer::with_data("Example.com", Class::IN, 3600, Data::NS(Labels::from_domain("ns1.example.com")));
+        assert_eq!(ns.to_string(), "Example.com. 3600 IN NS ns1.example.com.");
+    }
+
+    #[test]
+    fn unknown_type_is_opaque() {
+        let ans = Answer::opaque("example.com", Ty::Unknown(65), Class::IN, 60, Bytes::from_static(b"\x00\x01\x00\x00"));
+        let ser = ans.clone().serialize();
+        let (de, _) = Answer::deserialize(&ser, 0).unwrap();
+        assert_eq!(de, ans);
+        assert_eq!(de.data().to_string(), r"\# 4 00010000");
+        assert_eq!(Data::Opaque(Bytes::new()).to_string(), r"\# 0");
+    }
+
+    #[test]
+    fn generic_rdata_form() {
+        assert_eq!(Data::from_generic(&Ty::A, r"\# 4 0A000001").unwrap(), Data::A(0x0a000001));
+        assert_eq!(
+            Data::from_generic(&Ty::Unknown(65), r"\# 3 abcd ef").unwrap(),
+            Data::Opaque(Bytes::from_static(b"\xab\xcd\xef"))
+        );
+        assert!(Data::from_generic(&Ty::A, r"\# 5 0A000001").is_err());
+        assert!(Data::from_generic(&Ty::A, "10.0.0.1").is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! A `dig`-like client for poking at DNS servers, ours included:
+//!
+//! ```text
+//! dnsq @127.0.0.1 -p 2053 example.com MX +dnssec +tcp
+//! ```
+use std::env;
+use std::fmt::Write as _;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::process;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail};
+use clap::Parser;
+use dns_starter_rust::edns::Edns;
+use dns_starter_rust::message::{Class, Labels, Message, MessageBuilder, Ty};
+use dns_starter_rust::question::Question;
+use dns_starter_rust::upstream::{query_tcp, query_udp};
+
+#[derive(Debug, Parser)]
+#[command(name = "dnsq", about = "Sends a DNS query and prints the response the way dig does")]
+struct Args {
+    /// Port the server listens on
+    #[arg(short = 'p', long, default_value_t = 53)]
+    port: u16,
+    /// Seconds to wait for a response
+    #[arg(short = 't', long, default_value_t = 5)]
+    timeout: u64,
+    /// `@server` (default 127.0.0.1), the name (default the root), its type
+    /// (default A) and class (default IN), and any of +[no]recurse,
+    /// +[no]dnssec, +[no]tcp, +bufsize=N and +[no]hex
+    query: Vec<String>,
+}
+
+/// What to ask and how, from the dig-style words of the command line.
+#[derive(Debug, PartialEq)]
+struct Query {
+    server: IpAddr,
+    name: Labels,
+    ty: Ty,
+    class: Class,
+    recurse: bool,
+    dnssec: bool,
+    tcp: bool,
+    bufsize: u16,
+    hex: bool,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Self {
+            server: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            name: Labels::default(),
+            ty: Ty::A,
+            class: Class::IN,
+            recurse: true,
+            dnssec: false,
+            tcp: false,
+            bufsize: Edns::PAYLOAD_SIZE,
+            hex: false,
+        }
+    }
+}
+
+impl Query {
+    /// Words that aren't a server or an option are the name, its type and
+    /// its class, in any order. As with dig, a word that reads as a type
+    /// (or class) is taken for one until a type (or class) has been given,
+    /// so `MX example.com` asks for the MX records of example.com.
+    fn parse(words: &[String]) -> anyhow::Result<Self> {
+        let mut query = Self::default();
+        let (mut named, mut typed, mut classed) = (false, false, false);
+        for word in words {
+            if let Some(server) = word.strip_prefix('@') {
+                query.server = server.parse().map_err(|_| anyhow!("bad server address {:?}", server))?;
+            } else if let Some(option) = word.strip_prefix('+') {
+                query.set(option)?;
+            } else if let Some(ty) = word.parse::<Ty>().ok().filter(|_| !typed) {
+                query.ty = ty;
+                typed = true;
+            } else if let Some(class) = word.parse::<Class>().ok().filter(|_| !classed) {
+                query.class = class;
+                classed = true;
+            } else if !named {
+                query.name = Labels::parse_domain(word)?;
+                named = true;
+            } else {
+                bail!("unexpected argument {:?}, expected a record type or class", word)
+            }
+        }
+        Ok(query)
+    }
+
+    fn set(&mut self, option: &str) -> anyhow::Result<()> {
+        if let Some(size) = option.strip_prefix("bufsize=") {
+            self.bufsize = size.parse().map_err(|_| anyhow!("bad buffer size {:?}", size))?;
+            return Ok(())
+        }
+        let (name, on) = option.strip_prefix("no").map_or((option, true), |name| (name, false));
+        match name {
+            "recurse" => self.recurse = on,
+            "dnssec" => self.dnssec = on,
+            "tcp" => self.tcp = on,
+            "hex" => self.hex = on,
+            _ => bail!("unknown option +{}", option),
+        }
+        Ok(())
+    }
+
+    fn message(&self) -> Message {
+        let mut edns = Edns { payload_size: self.bufsize, ..Default::default() };
+        edns.set_dnssec_ok(self.dnssec);
+        MessageBuilder::new()
+            .set_id(rand::random())
+            .set_qr(false)
+            .set_rd(self.recurse)
+            .add_question(Question::new(&self.name.to_bytes(), self.ty.into(), self.class.into()))
+            .set_edns(edns)
+            .finish()
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let query = Query::parse(&args.query).unwrap_or_else(|e| {
+        eprintln!("dnsq: {:#}", e);
+        process::exit(1)
+    });
+    let server = SocketAddr::new(query.server, args.port);
+    let timeout = Duration::from_secs(args.timeout);
+    let request = query.message();
+    let sent = request.clone().serialize().to_vec();
+    println!("; <<>> dnsq <<>> {}", env::args().skip(1).collect::<Vec<_>>().join(" "));
+    if query.hex {
+        print!(";; Sent {} bytes:\n{}", sent.len(), hex_dump(&sent));
+    }
+
+    let start = Instant::now();
+    let mut tcp = query.tcp;
+    let mut exchanged = exchange(&request, server, tcp, timeout);
+    if !tcp && exchanged.as_ref().is_ok_and(|(response, _)| response.tc()) {
+        println!(";; Truncated, retrying in TCP mode.");
+        tcp = true;
+        exchanged = exchange(&request, server, tcp, timeout);
+    }
+    let (response, received) = exchanged.unwrap_or_else(|e| {
+        eprintln!(";; communications error to {}: {:#}", server, e);
+        process::exit(9)
+    });
+    let elapsed = start.elapsed();
+
+    if query.hex {
+        print!(";; Received {} bytes:\n{}", received.len(), hex_dump(&received));
+    }
+    print!("{}", render(&response));
+    println!(";; Query time: {} msec", elapsed.as_millis());
+    println!(";; SERVER: {}#{}({}) ({})", server.ip(), server.port(), server.ip(), if tcp { "TCP" } else { "UDP" });
+    println!(";; MSG SIZE  rcvd: {}", received.len());
+}
+
+/// Sends `query` and waits for its response, returning it along with its
+/// wire format.
+fn exchange(query: &Message, server: SocketAddr, tcp: bool, timeout: Duration) -> anyhow::Result<(Message, Vec<u8>)> {
+    match tcp {
+        true => query_tcp(query, server, timeout),
+        false => query_udp(query, server, timeout),
+    }
+}
+
+/// The header, OPT pseudo-section and record sections as `dig` prints them.
+fn render(response: &Message) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, ";; ->>HEADER<<- opcode: {}, status: {}, id: {}", response.opcode(), response.rcode(), response.id());
+    let flags = [
+        ("qr", response.qr()),
+        ("aa", response.aa()),
+        ("tc", response.tc()),
+        ("rd", response.rd()),
+        ("ra", response.ra()),
+        ("ad", response.ad()),
+        ("cd", response.cd()),
+    ];
+    let flags = flags.iter().filter(|(_, set)| *set).map(|(flag, _)| *flag).collect::<Vec<_>>().join(" ");
+    let _ = writeln!(
+        out,
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        flags,
+        response.questions().len(),
+        response.answers().len(),
+        response.authorities().len(),
+        response.additionals().len() + response.edns().is_some() as usize,
+    );
+    if let Some(edns) = response.edns() {
+        let flags = if edns.dnssec_ok() { " do" } else { "" };
+        let _ = writeln!(out, "\n;; OPT PSEUDOSECTION:\n; EDNS: version: {}, flags:{}; udp: {}", edns.version, flags, edns.payload_size);
+    }
+    let _ = writeln!(out, "\n;; QUESTION SECTION:");
+    for question in response.questions() {
+        let _ = writeln!(out, ";{}.\t\t{}\t{}", question.domain(), question.class(), question.ty());
+    }
+    let sections = [("ANSWER", response.answers()), ("AUTHORITY", response.authorities()), ("ADDITIONAL", response.additionals())];
+    for (name, records) in sections.into_iter().filter(|(_, records)| !records.is_empty()) {
+        let _ = writeln!(out, "\n;; {} SECTION:", name);
+        for record in records {
+            let _ = writeln!(out, "{}", record);
+        }
+    }
+    out.push('\n');
+    out
+}
+
+/// 16 bytes a line, with the offset in front and printable ASCII behind.
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex = line.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+        let text = line.iter().map(|&b| if b.is_ascii_graphic() { b as char } else { '.' }).collect::<String>();
+        let _ = writeln!(out, "{:04x}  {:<47}  {}", i * 16, hex, text);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr, TcpListener, UdpSocket};
+    use std::thread;
+    use std::time::Duration;
+    use dns_starter_rust::answer::Answer;
+    use dns_starter_rust::header::Rcode;
+    use dns_starter_rust::message::{Class, Message, MessageBuilder, Ty};
+    use dns_starter_rust::server::{read_framed, write_framed};
+    use crate::{exchange, hex_dump, render, Query};
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parses_dig_style_arguments() {
+        let query = Query::parse(&words("@::1 example.com mx ch +norecurse +dnssec +tcp +bufsize=4096 +hex")).unwrap();
+        assert_eq!(query.server, "::1".parse::<IpAddr>().unwrap());
+        assert_eq!((query.name.to_string(), query.ty, query.class), ("example.com".to_string(), Ty::MX, Class::CH));
+        assert!(!query.recurse && query.dnssec && query.tcp && query.hex);
+        assert_eq!(query.bufsize, 4096);
+        assert_eq!(Query::parse(&[]).unwrap(), Query::default());
+        assert_eq!(Query::parse(&words("+frobnicate")).unwrap_err().to_string(), "unknown option +frobnicate");
+        assert!(Query::parse(&words("example.com A bogus")).is_err());
+        assert!(Query::parse(&words("@localhost")).is_err());
+        let typed_first = Query::parse(&words("MX ch example.com")).unwrap();
+        assert_eq!((typed_first.name.to_string(), typed_first.ty, typed_first.class), ("example.com".to_string(), Ty::MX, Class::CH));
+        // once there is a type, the next one is the name
+        let named_a = Query::parse(&words("A a")).unwrap();
+        assert_eq!((named_a.name.to_string(), named_a.ty), ("a".to_string(), Ty::A));
+        let long = format!("{}.example", "x".repeat(256));
+        assert!(Query::parse(&[long]).unwrap_err().to_string().starts_with("bad label"));
+    }
+
+    #[test]
+    fn queries_carry_the_options() {
+        let message = Query::parse(&words("example.com AAAA +norecurse +dnssec +bufsize=800")).unwrap().message();
+        assert!(!message.qr() && !message.rd());
+        let question = &message.questions()[0];
+        assert_eq!((question.domain(), question.ty()), ("example.com".to_string(), Ty::AAAA));
+        let edns = message.edns().unwrap();
+        assert!(edns.dnssec_ok());
+        assert_eq!(edns.payload_size, 800);
+    }
+
+    #[test]
+    fn renders_like_dig() {
+        let query = Query::parse(&words("example.com")).unwrap().message();
+        let response = MessageBuilder::response_to(&query)
+            .set_id(7)
+            .set_ra(true)
+            .set_rcode(Rcode::NXDomain)
+            .add_answer(Answer::new("example.com", 1, 1, 60, 0xc0000201))
+            .finish();
+        let text = render(&response);
+        assert!(text.starts_with(";; ->>HEADER<<- opcode: QUERY, status: NXDOMAIN, id: 7\n"), "{}", text);
+        assert!(text.contains(";; flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 0\n"), "{}", text);
+        assert!(text.contains("\n;; QUESTION SECTION:\n;example.com.\t\tIN\tA\n"), "{}", text);
+        assert!(text.contains("\n;; ANSWER SECTION:\nexample.com. 60 IN A 192.0.2.1\n"), "{}", text);
+        assert!(!text.contains("AUTHORITY SECTION"));
+        let dump = hex_dump(b"\x00\x07abcdefghijklmnopq");
+        let lines = dump.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "0000  00 07 61 62 63 64 65 66 67 68 69 6a 6b 6c 6d 6e  ..abcdefghijklmn");
+        assert_eq!(lines[1], format!("0010  6f 70 71{}opq", " ".repeat(41)));
+    }
+
+    #[test]
+    fn exchanges_over_udp_and_tcp() {
+        let query = Query::parse(&words("example.com")).unwrap().message();
+        let answer = MessageBuilder::response_to(&query)
+            .add_answer(Answer::new("example.com", 1, 1, 60, 0xc0000201))
+            .finish()
+            .serialize();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tcp = TcpListener::bind(udp.local_addr().unwrap()).unwrap();
+        let server: SocketAddr = udp.local_addr().unwrap();
+        let reply = answer.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (_, client) = udp.recv_from(&mut buf).unwrap();
+            // a stray datagram with another ID first
+            udp.send_to(&[0xff; 12], client).unwrap();
+            udp.send_to(&reply, client).unwrap();
+        });
+        thread::spawn(move || {
+            let (mut stream, _) = tcp.accept().unwrap();
+            read_framed(&mut stream).unwrap();
+            write_framed(&mut stream, &answer).unwrap();
+        });
+        for tcp in [false, true] {
+            let (response, bytes) = exchange(&query, server, tcp, Duration::from_secs(2)).unwrap();
+            assert_eq!(response.answers().len(), 1);
+            assert_eq!(Message::deserialize(&bytes).unwrap(), response);
+        }
+    }
+}
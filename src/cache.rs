@@ -0,0 +1,302 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+use bytes::BytesMut;
+use crate::answer::{Answer, Data};
+use crate::header::Rcode;
+use crate::message::{Class, Message, MessageBuilder, Ty};
+
+/// Longest we keep any answer, whatever TTL upstream gave it.
+const MAX_TTL: u32 = 24 * 60 * 60;
+/// Cap on negative answers, the upper end of RFC 2308's recommendation.
+const MAX_NEGATIVE_TTL: u32 = 3 * 60 * 60;
+
+/// Cache of upstream responses for single-question queries.
+///
+/// Entries are evicted least recently used first once either `max_entries`
+/// or `max_bytes` (the wire size of the cached records) would be exceeded.
+pub struct Cache {
+    entries: HashMap<Key, Entry>,
+    /// Entries by the tick they were last used at, oldest first.
+    recency: BTreeMap<u64, Key>,
+    tick: u64,
+    bytes: usize,
+    max_entries: usize,
+    max_bytes: usize,
+}
+
+/// A name-wide NXDOMAIN is stored with no type, so it answers every type.
+/// The name is the lowercased wire form, which unlike the presentation form
+/// can't mistake a dot inside a label for a label boundary.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+struct Key {
+    name: BytesMut,
+    ty: Option<Ty>,
+    class: Class,
+}
+
+struct Entry {
+    rcode: Rcode,
+    /// Whether the answer was validated, so hits can carry AD again.
+    authentic: bool,
+    answers: Vec<Answer>,
+    authorities: Vec<Answer>,
+    additionals: Vec<Answer>,
+    stored: Instant,
+    ttl: u32,
+    size: usize,
+    used: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new(10_000, 16 * 1024 * 1024)
+    }
+}
+
+impl Cache {
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+            max_entries,
+            max_bytes,
+        }
+    }
+
+    pub fn get(&mut self, query: &Message) -> Option<Message> {
+        self.get_at(query, Instant::now())
+    }
+
+    pub fn insert(&mut self, response: &Message) {
+        self.insert_at(response, Instant::now())
+    }
+
+    /// Builds a response to `query` from the cache, with every TTL reduced
+    /// by the time the entry has spent here.
+    fn get_at(&mut self, query: &Message, now: Instant) -> Option<Message> {
+        let key = Key::of(query)?;
+        let key = [Key { ty: None, ..key.clone() }, key]
+            .into_iter()
+            .find(|k| self.entries.contains_key(k))?;
+        let entry = &self.entries[&key];
+        let age = now.saturating_duration_since(entry.stored).as_secs();
+        if age >= entry.ttl as u64 {
+            self.remove(&key);
+            return None
+        }
+        let age = age as u32;
+        let response = MessageBuilder::response_to(query)
+            .set_ra(true)
+            .set_ad(entry.authentic)
+            .set_rcode(entry.rcode)
+            .add_answers(aged(&entry.answers, age))
+            .add_authorities(aged(&entry.authorities, age))
+            .add_additionals(aged(&entry.additionals, age))
+            .finish();
+        self.touch(&key);
+        Some(response)
+    }
+
+    /// Caches `response` if it is a cacheable answer to a single question:
+    /// a positive answer for its lowest TTL, or an NXDOMAIN/NODATA answer
+    /// with an SOA for the SOA's negative TTL (RFC 2308 section 5).
+    fn insert_at(&mut self, response: &Message, now: Instant) {
+        if self.max_entries == 0 || response.tc() {
+            return
+        }
+        let Some(mut key) = Key::of(response) else {
+            return
+        };
+        let rcode = response.rcode();
+        let records = || response.answers.iter().chain(response.authorities.iter()).chain(response.additionals.iter());
+        let ttl = match rcode {
+            Rcode::NoError if response.answers.iter().any(|a| Some(a.ty()) == key.ty || a.ty() == Ty::CNAME) => {
+                records().map(Answer::ttl).min().unwrap_or(0).min(MAX_TTL)
+            }
+            Rcode::NoError | Rcode::NXDomain => {
+                let Some(ttl) = response.authorities.iter().find_map(negative_ttl) else {
+                    return
+                };
+                if rcode == Rcode::NXDomain && response.answers.is_empty() {
+                    key.ty = None;
+                }
+                ttl.min(MAX_NEGATIVE_TTL)
+            }
+            _ => return,
+        };
+        if ttl == 0 {
+            return
+        }
+        let size = key.name.len() + records().map(|r| r.clone().serialize().len()).sum::<usize>();
+        if size > self.max_bytes {
+            return
+        }
+        self.remove(&key);
+        while self.entries.len() >= self.max_entries || self.bytes + size > self.max_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break
+            };
+            self.remove(&oldest);
+        }
+        self.tick += 1;
+        self.bytes += size;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, Entry {
+            rcode,
+            authentic: response.ad(),
+            answers: response.answers.to_vec(),
+            authorities: response.authorities.to_vec(),
+            additionals: response.additionals.to_vec(),
+            stored: now,
+            ttl,
+            size,
+            used: self.tick,
+        });
+    }
+
+    fn touch(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recency.remove(&entry.used);
+            self.tick += 1;
+            entry.used = self.tick;
+            self.recency.insert(self.tick, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used);
+            self.bytes -= entry.size;
+        }
+    }
+}
+
+impl Key {
+    fn of(message: &Message) -> Option<Self> {
+        let [question] = &message.questions[..] else {
+            return None
+        };
+        Some(Self {
+            name: question.labels().to_lowercase().to_bytes(),
+            ty: Some(question.ty()),
+            class: question.class(),
+        })
+    }
+}
+
+fn aged(records: &[Answer], age: u32) -> Vec<Answer> {
+    records
+        .iter()
+        .cloned()
+        .map(|mut r| {
+            r.set_ttl(r.ttl().saturating_sub(age));
+            r
+        })
+        .collect()
+}
+
+/// The lesser of an SOA record's own TTL and its MINIMUM field.
+fn negative_ttl(record: &Answer) -> Option<u32> {
+    match record.data() {
+        Data::SOA { minimum, .. } => Some(record.ttl().min(*minimum)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::answer::{Answer, Data};
+    use crate::cache::Cache;
+    use crate::header::Rcode;
+    use crate::message::{Class, Labels, Message, MessageBuilder};
+    use crate::question::Question;
+
+    fn query(name: &str) -> Message {
+        MessageBuilder::new().set_id(7).add_question(Question::from_domain_name(name)).finish()
+    }
+
+    fn answer(name: &str, ttl: u32) -> Message {
+        MessageBuilder::response_to(&query(name))
+            .add_answer(Answer::with_data(name, Class::IN, ttl, Data::A(0x7f000001)))
+            .finish()
+    }
+
+    fn soa(ttl: u32, minimum: u32) -> Answer {
+        let name = Labels::from_domain("example");
+        let data = Data::SOA { mname: name.clone(), rname: name, serial: 1, refresh: 2, retry: 3, expire: 4, minimum };
+        Answer::with_data("example", Class::IN, ttl, data)
+    }
+
+    #[test]
+    fn ttls_count_down_and_expire() {
+        let mut cache = Cache::default();
+        let start = Instant::now();
+        cache.insert_at(&answer("a.example", 60), start);
+
+        let q = MessageBuilder::new().set_id(99).add_question(Question::from_domain_name("A.EXAMPLE")).finish();
+        let hit = cache.get_at(&q, start + Duration::from_secs(45)).unwrap();
+        assert_eq!(hit.id(), 99);
+        assert_eq!(hit.answers[0].ttl(), 15);
+        assert!(cache.get_at(&q, start + Duration::from_secs(60)).is_none());
+        assert!(cache.entries.is_empty() && cache.bytes == 0);
+    }
+
+    #[test]
+    fn negative_answers_use_the_soa_minimum() {
+        let mut cache = Cache::default();
+        let start = Instant::now();
+        let nxdomain = MessageBuilder::response_to(&query("gone.example"))
+            .set_rcode(Rcode::NXDomain)
+            .add_authority(soa(3600, 300))
+            .finish();
+        cache.insert_at(&nxdomain, start);
+        // NXDOMAIN covers every type at the name
+        let mut aaaa = query("gone.example");
+        aaaa.questions[0] = Question::new(&Labels::from_domain("gone.example").to_bytes(), 28, 1);
+        let hit = cache.get_at(&aaaa, start + Duration::from_secs(100)).unwrap();
+        assert_eq!(hit.rcode(), Rcode::NXDomain);
+        assert_eq!(hit.authorities[0].ttl(), 3500);
+        assert!(cache.get_at(&aaaa, start + Duration::from_secs(300)).is_none());
+
+        // NODATA without an SOA can't be cached
+        cache.insert_at(&MessageBuilder::response_to(&query("empty.example")).finish(), start);
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn names_are_compared_label_by_label() {
+        let mut cache = Cache::default();
+        let now = Instant::now();
+        let mut dotted = query("www.example");
+        dotted.questions[0] = Question::new(b"\x0bwww.example\0", 1, 1);
+        let nxdomain = MessageBuilder::response_to(&dotted)
+            .set_rcode(Rcode::NXDomain)
+            .add_authority(soa(3600, 300))
+            .finish();
+        cache.insert_at(&nxdomain, now);
+        assert!(cache.get_at(&dotted, now).is_some());
+        assert!(cache.get_at(&query("www.example"), now).is_none());
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let mut cache = Cache::new(2, usize::MAX);
+        let now = Instant::now();
+        cache.insert_at(&answer("a.example", 60), now);
+        cache.insert_at(&answer("b.example", 60), now);
+        assert!(cache.get_at(&query("a.example"), now).is_some());
+        cache.insert_at(&answer("c.example", 60), now);
+        assert!(cache.get_at(&query("b.example"), now).is_none());
+        assert!(cache.get_at(&query("a.example"), now).is_some());
+
+        let size = cache.bytes / 2;
+        let mut cache = Cache::new(100, size * 2);
+        cache.insert_at(&answer("a.example", 60), now);
+        cache.insert_at(&answer("b.example", 60), now);
+        cache.insert_at(&answer("c.example", 60), now);
+        assert_eq!((cache.entries.len(), cache.bytes), (2, size * 2));
+    }
+}
@@ -1,8 +1,100 @@
-use std::net::SocketAddrV4;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use clap::Parser;
+use crate::config::ListenerConfig;
+use crate::log::Level;
+use crate::server::PartialFailure;
+use crate::zone::ZoneSpec;
 
+/// Every flag overrides the corresponding key of the config file; see
+/// `config` for the defaults.
 #[derive(Debug, Parser)]
 pub struct Args {
-    #[arg(short = 'r', long)]
-    pub resolver: Option<SocketAddrV4>
-}
\ No newline at end of file
+    /// TOML file to read settings from; re-read on SIGHUP
+    #[arg(short = 'c', long)]
+    pub config: Option<PathBuf>,
+    /// Address to answer queries on over UDP and TCP, as `[mode=]address`
+    /// with a mode of authoritative, forward or recursive; repeat or
+    /// comma-separate for more [default: 127.0.0.1:2053]
+    #[arg(short = 'l', long, value_delimiter = ',')]
+    pub listen: Vec<ListenerConfig>,
+    /// Upstream resolver to forward to, such as 192.0.2.53:53 or
+    /// [2001:db8::53]:53; repeat or comma-separate for failover, in order of
+    /// preference
+    #[arg(short = 'r', long, value_delimiter = ',')]
+    pub resolver: Vec<SocketAddr>,
+    /// Resolve recursively from the root servers on listeners without a
+    /// mode, instead of forwarding
+    #[arg(long)]
+    pub recursive: bool,
+    /// Root hints file in master-file format, instead of the built-in roots
+    #[arg(long)]
+    pub root_hints: Option<PathBuf>,
+    /// Port authoritative servers are queried on when recursing [default: 53]
+    #[arg(long)]
+    pub nameserver_port: Option<u16>,
+    /// Milliseconds to wait for an upstream before trying the next one
+    /// [default: 2000]
+    #[arg(long)]
+    pub upstream_timeout: Option<u64>,
+    /// Upstream attempts per query, across all resolvers [default: 3]
+    #[arg(long)]
+    pub upstream_attempts: Option<usize>,
+    /// Consecutive failures after which a resolver is considered dead
+    /// [default: 3]
+    #[arg(long)]
+    pub upstream_max_failures: Option<u32>,
+    /// Seconds between probes of a dead resolver [default: 30]
+    #[arg(long)]
+    pub upstream_probe_interval: Option<u64>,
+    /// Milliseconds all the questions of a query get to resolve, in
+    /// parallel [default: 5000]
+    #[arg(long)]
+    pub upstream_deadline: Option<u64>,
+    /// What to answer when only some questions of a query resolve: servfail
+    /// or partial [default: servfail]
+    #[arg(long)]
+    pub partial_failure: Option<PartialFailure>,
+    /// Seconds a TCP connection may stay idle before it is closed
+    /// [default: 10]
+    #[arg(long)]
+    pub tcp_idle_timeout: Option<u64>,
+    /// UDP queries answered concurrently [default: 32]
+    #[arg(long)]
+    pub workers: Option<usize>,
+    /// UDP queries waiting for a worker before we stop reading the socket
+    /// [default: 256]
+    #[arg(long)]
+    pub queue_size: Option<usize>,
+    /// TCP connections served concurrently [default: 64]
+    #[arg(long)]
+    pub max_tcp_connections: Option<usize>,
+    /// Zone file to serve authoritatively, as `[origin=]path`; repeatable
+    #[arg(short = 'z', long = "zone")]
+    pub zones: Vec<ZoneSpec>,
+    /// DNSSEC key to sign the zone it belongs to with, as the base name of
+    /// the `.key` and `.private` files dnssec-keygen writes; repeatable
+    #[arg(long = "key")]
+    pub keys: Vec<PathBuf>,
+    /// Days our signatures stay valid; zones are re-signed when a quarter
+    /// of that is left [default: 14]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub signature_validity: Option<u32>,
+    /// Validate forwarded and recursive answers with DNSSEC
+    #[arg(long)]
+    pub validate: bool,
+    /// DS or DNSKEY records to trust, in master-file format, instead of the
+    /// built-in root key
+    #[arg(long)]
+    pub trust_anchor: Option<PathBuf>,
+    /// Most forwarded answers kept in the cache; 0 disables caching
+    /// [default: 10000]
+    #[arg(long)]
+    pub cache_entries: Option<usize>,
+    /// Most bytes of records kept in the cache [default: 16777216]
+    #[arg(long)]
+    pub cache_bytes: Option<usize>,
+    /// One of error, warn, info or debug [default: info]
+    #[arg(long)]
+    pub log_level: Option<Level>,
+}
@@ -0,0 +1,668 @@
+//! The configuration file, in TOML. Every key is optional:
+//!
+//! ```toml
+//! [[listener]]
+//! address = "127.0.0.1:2053"
+//! protocols = ["udp", "tcp"]
+//! mode = "forward"            # authoritative, forward or recursive
+//!
+//! [[listener]]
+//! address = "[::1]:53"
+//!
+//! [server]
+//! workers = 32                # UDP queries answered concurrently
+//! queue_size = 256
+//! max_tcp_connections = 64
+//! tcp_idle_timeout = 10       # seconds
+//!
+//! [upstream]
+//! servers = ["1.1.1.1:53", "8.8.8.8:53"]
+//! timeout = 2000              # milliseconds
+//! attempts = 3
+//! max_failures = 3
+//! probe_interval = 30         # seconds
+//! deadline = 5000             # milliseconds for all questions of a query
+//! partial_failure = "servfail"  # or "partial", to answer what we can
+//!
+//! [recursion]
+//! enabled = false
+//! root_hints = "named.root"
+//! nameserver_port = 53
+//!
+//! [[zone]]
+//! file = "example.zone"
+//! origin = "example."         # defaults to the zone's $ORIGIN
+//!
+//! [signing]
+//! keys = ["Kexample.+013+32741"]
+//! validity = 14               # days
+//!
+//! [validation]
+//! enabled = false
+//! trust_anchor = "root.ds"
+//!
+//! [cache]
+//! entries = 10000
+//! bytes = 16777216
+//!
+//! [acl]
+//! allow = ["127.0.0.0/8", "::1"]
+//! deny = []
+//!
+//! [log]
+//! level = "info"              # error, warn, info or debug
+//! ```
+//!
+//! Without any listener we listen on 127.0.0.1:2053. A listener without a
+//! mode recurses if recursion is enabled, forwards if there are upstream
+//! servers and only serves our zones otherwise.
+//!
+//! Relative paths are relative to the directory of the file. Command line
+//! flags override the file.
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context};
+use crate::acl::{Acl, Network};
+use crate::cli::Args;
+use crate::log::Level;
+use crate::server::{Mode, PartialFailure};
+use crate::toml::{self, Table, Value};
+use crate::zone::ZoneSpec;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub upstream: UpstreamConfig,
+    pub recursion: RecursionConfig,
+    pub zones: Vec<ZoneSpec>,
+    pub signing: SigningConfig,
+    pub validation: ValidationConfig,
+    pub cache: CacheConfig,
+    pub acl: Acl,
+    pub log_level: Level,
+}
+
+/// The sockets and the threads serving them, which only change on restart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    pub listeners: Vec<ListenerConfig>,
+    pub workers: usize,
+    pub queue_size: usize,
+    pub max_tcp_connections: usize,
+    pub tcp_idle_timeout: Duration,
+}
+
+/// An address we answer on, given on the command line as
+/// `[mode=]address`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenerConfig {
+    pub address: SocketAddr,
+    pub udp: bool,
+    pub tcp: bool,
+    /// Unless set, see `Config::mode`.
+    pub mode: Option<Mode>,
+}
+
+impl ListenerConfig {
+    pub fn new(address: SocketAddr) -> Self {
+        Self { address, udp: true, tcp: true, mode: None }
+    }
+}
+
+impl FromStr for ListenerConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode, address) = match s.split_once('=') {
+            Some((mode, address)) => (Some(mode.parse()?), address),
+            None => (None, s),
+        };
+        let address = parse(address)?;
+        Ok(Self { mode, ..Self::new(address) })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamConfig {
+    pub servers: Vec<SocketAddr>,
+    pub timeout: Duration,
+    pub attempts: usize,
+    pub max_failures: u32,
+    pub probe_interval: Duration,
+    /// Also applies to recursion.
+    pub deadline: Duration,
+    pub partial_failure: PartialFailure,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecursionConfig {
+    pub enabled: bool,
+    pub root_hints: Option<PathBuf>,
+    pub nameserver_port: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigningConfig {
+    pub keys: Vec<PathBuf>,
+    pub validity: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationConfig {
+    pub enabled: bool,
+    pub trust_anchor: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheConfig {
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+const DAY: u64 = 24 * 60 * 60;
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server: ServerConfig {
+                listeners: vec![ListenerConfig::new("127.0.0.1:2053".parse().unwrap())],
+                workers: 32,
+                queue_size: 256,
+                max_tcp_connections: 64,
+                tcp_idle_timeout: Duration::from_secs(10),
+            },
+            upstream: UpstreamConfig {
+                servers: vec![],
+                timeout: Duration::from_millis(2000),
+                attempts: 3,
+                max_failures: 3,
+                probe_interval: Duration::from_secs(30),
+                deadline: Duration::from_millis(5000),
+                partial_failure: PartialFailure::ServFail,
+            },
+            recursion: RecursionConfig { enabled: false, root_hints: None, nameserver_port: 53 },
+            zones: vec![],
+            signing: SigningConfig { keys: vec![], validity: Duration::from_secs(14 * DAY) },
+            validation: ValidationConfig { enabled: false, trust_anchor: None },
+            cache: CacheConfig { entries: 10_000, bytes: 16 * 1024 * 1024 },
+            acl: Acl::default(),
+            log_level: Level::Info,
+        }
+    }
+}
+
+impl Config {
+    /// The file named by `--config`, if any, with the other flags applied on
+    /// top. Called again on every reload.
+    pub fn from_args(args: &Args) -> anyhow::Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+        config.apply(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        Self::parse(&text, dir).with_context(|| format!("{}", path.display()))
+    }
+
+    /// Parses a file's text; relative paths in it are taken to be relative
+    /// to `dir`.
+    pub fn parse(text: &str, dir: &Path) -> anyhow::Result<Self> {
+        let mut root = toml::parse(text)?;
+        let mut config = Self::default();
+
+        let listeners = Section::tables(&mut root, "listener")?;
+        if !listeners.is_empty() {
+            config.server.listeners.clear();
+        }
+        for mut listener in listeners {
+            let address = listener.require("address")?;
+            let mut parsed = ListenerConfig::new(address);
+            if let Some(protocols) = listener.get::<Vec<String>>("protocols")? {
+                let path = listener.key("protocols");
+                if let Some(unknown) = protocols.iter().find(|p| !matches!(p.as_str(), "udp" | "tcp")) {
+                    bail!("{}: unknown protocol {:?}, expected udp or tcp", path, unknown)
+                }
+                parsed.udp = protocols.iter().any(|p| p == "udp");
+                parsed.tcp = protocols.iter().any(|p| p == "tcp");
+                if !(parsed.udp || parsed.tcp) {
+                    bail!("{}: expected udp, tcp or both", path)
+                }
+            }
+            parsed.mode = listener.get("mode")?;
+            listener.finish()?;
+            config.server.listeners.push(parsed);
+        }
+
+        let mut server = Section::take(&mut root, "server")?;
+        let defaults = &mut config.server;
+        server.set(&mut defaults.workers, "workers")?;
+        server.set(&mut defaults.queue_size, "queue_size")?;
+        server.set(&mut defaults.max_tcp_connections, "max_tcp_connections")?;
+        if let Some(seconds) = server.get("tcp_idle_timeout")? {
+            defaults.tcp_idle_timeout = Duration::from_secs(seconds);
+        }
+        server.finish()?;
+
+        let mut upstream = Section::take(&mut root, "upstream")?;
+        let defaults = &mut config.upstream;
+        upstream.set(&mut defaults.servers, "servers")?;
+        if let Some(millis) = upstream.get("timeout")? {
+            defaults.timeout = Duration::from_millis(millis);
+        }
+        upstream.set(&mut defaults.attempts, "attempts")?;
+        upstream.set(&mut defaults.max_failures, "max_failures")?;
+        if let Some(seconds) = upstream.get("probe_interval")? {
+            defaults.probe_interval = Duration::from_secs(seconds);
+        }
+        if let Some(millis) = upstream.get("deadline")? {
+            defaults.deadline = Duration::from_millis(millis);
+        }
+        upstream.set(&mut defaults.partial_failure, "partial_failure")?;
+        upstream.finish()?;
+
+        let mut recursion = Section::take(&mut root, "recursion")?;
+        recursion.set(&mut config.recursion.enabled, "enabled")?;
+        config.recursion.root_hints = recursion.get::<PathBuf>("root_hints")?.map(|p| dir.join(p));
+        recursion.set(&mut config.recursion.nameserver_port, "nameserver_port")?;
+        recursion.finish()?;
+
+        for mut zone in Section::tables(&mut root, "zone")? {
+            let path = zone.require::<PathBuf>("file")?;
+            let origin = zone.get("origin")?;
+            zone.finish()?;
+            config.zones.push(ZoneSpec { origin, path: dir.join(path) });
+        }
+
+        let mut signing = Section::take(&mut root, "signing")?;
+        if let Some(keys) = signing.get::<Vec<PathBuf>>("keys")? {
+            config.signing.keys = keys.into_iter().map(|p| dir.join(p)).collect();
+        }
+        if let Some(days) = signing.get::<u64>("validity")? {
+            if days == 0 {
+                bail!("{}: must be at least 1 day", signing.key("validity"))
+            }
+            config.signing.validity = Duration::from_secs(days * DAY);
+        }
+        signing.finish()?;
+
+        let mut validation = Section::take(&mut root, "validation")?;
+        validation.set(&mut config.validation.enabled, "enabled")?;
+        config.validation.trust_anchor = validation.get::<PathBuf>("trust_anchor")?.map(|p| dir.join(p));
+        validation.finish()?;
+
+        let mut cache = Section::take(&mut root, "cache")?;
+        cache.set(&mut config.cache.entries, "entries")?;
+        cache.set(&mut config.cache.bytes, "bytes")?;
+        cache.finish()?;
+
+        let mut acl = Section::take(&mut root, "acl")?;
+        acl.set(&mut config.acl.allow, "allow")?;
+        acl.set(&mut config.acl.deny, "deny")?;
+        acl.finish()?;
+
+        let mut log = Section::take(&mut root, "log")?;
+        log.set(&mut config.log_level, "level")?;
+        log.finish()?;
+
+        Section::new(String::new(), Value::Table(root))?.finish()?;
+        Ok(config)
+    }
+
+    /// Overrides whatever the command line sets.
+    fn apply(&mut self, args: &Args) {
+        fn set<T: Clone>(value: &mut T, flag: &Option<T>) {
+            if let Some(flag) = flag {
+                *value = flag.clone();
+            }
+        }
+        if !args.listen.is_empty() {
+            self.server.listeners = args.listen.clone();
+        }
+        set(&mut self.server.workers, &args.workers);
+        set(&mut self.server.queue_size, &args.queue_size);
+        set(&mut self.server.max_tcp_connections, &args.max_tcp_connections);
+        if let Some(seconds) = args.tcp_idle_timeout {
+            self.server.tcp_idle_timeout = Duration::from_secs(seconds);
+        }
+        if !args.resolver.is_empty() {
+            self.upstream.servers = args.resolver.clone();
+        }
+        if let Some(millis) = args.upstream_timeout {
+            self.upstream.timeout = Duration::from_millis(millis);
+        }
+        set(&mut self.upstream.attempts, &args.upstream_attempts);
+        set(&mut self.upstream.max_failures, &args.upstream_max_failures);
+        if let Some(seconds) = args.upstream_probe_interval {
+            self.upstream.probe_interval = Duration::from_secs(seconds);
+        }
+        if let Some(millis) = args.upstream_deadline {
+            self.upstream.deadline = Duration::from_millis(millis);
+        }
+        set(&mut self.upstream.partial_failure, &args.partial_failure);
+        self.recursion.enabled |= args.recursive;
+        if args.root_hints.is_some() {
+            self.recursion.root_hints = args.root_hints.clone();
+        }
+        set(&mut self.recursion.nameserver_port, &args.nameserver_port);
+        if !args.zones.is_empty() {
+            self.zones = args.zones.clone();
+        }
+        if !args.keys.is_empty() {
+            self.signing.keys = args.keys.clone();
+        }
+        if let Some(days) = args.signature_validity {
+            self.signing.validity = Duration::from_secs(days as u64 * DAY);
+        }
+        self.validation.enabled |= args.validate;
+        if args.trust_anchor.is_some() {
+            self.validation.trust_anchor = args.trust_anchor.clone();
+        }
+        set(&mut self.cache.entries, &args.cache_entries);
+        set(&mut self.cache.bytes, &args.cache_bytes);
+        set(&mut self.log_level, &args.log_level);
+    }
+
+    /// How `listener` answers names outside our zones.
+    pub fn mode(&self, listener: &ListenerConfig) -> Mode {
+        match listener.mode {
+            Some(mode) => mode,
+            None if self.recursion.enabled => Mode::Recursive,
+            None if !self.upstream.servers.is_empty() => Mode::Forward,
+            None => Mode::Authoritative,
+        }
+    }
+
+    /// Whether any listener recurses.
+    pub fn recurses(&self) -> bool {
+        self.server.listeners.iter().any(|l| self.mode(l) == Mode::Recursive)
+    }
+
+    /// Checks that upstreams are configured if any of `modes` forwards.
+    /// Listeners keep the mode they started in, so a reload has to be
+    /// checked against those rather than the modes the new file implies.
+    pub fn supports(&self, modes: &[Mode]) -> anyhow::Result<()> {
+        if modes.contains(&Mode::Forward) && self.upstream.servers.is_empty() {
+            bail!("upstream.servers: a listener forwards, but there are no servers to forward to")
+        }
+        Ok(())
+    }
+
+    /// Checks the settings that only make sense together.
+    fn validate(&self) -> anyhow::Result<()> {
+        for (i, listener) in self.server.listeners.iter().enumerate() {
+            if self.mode(listener) == Mode::Forward && self.upstream.servers.is_empty() {
+                bail!("listener[{}].mode: there are no upstream.servers to forward to", i)
+            }
+        }
+        if self.recursion.root_hints.is_some() && !self.recurses() {
+            bail!("recursion.root_hints: no listener recurses")
+        }
+        if self.validation.trust_anchor.is_some() && !self.validation.enabled {
+            bail!("validation.trust_anchor: validation is not enabled")
+        }
+        if !self.signing.keys.is_empty() && self.zones.is_empty() {
+            bail!("signing.keys: there are no zones to sign")
+        }
+        Ok(())
+    }
+}
+
+/// One table of the file. Keys are taken out as they are read, so that
+/// whatever is left over can be reported as unknown.
+struct Section {
+    path: String,
+    table: Table,
+}
+
+impl Section {
+    fn new(path: String, value: Value) -> anyhow::Result<Self> {
+        match value {
+            Value::Table(table) => Ok(Self { path, table }),
+            value => bail!("{}: expected a table, found {}", path, value.kind()),
+        }
+    }
+
+    /// The table `name` of `root`, empty if there is none.
+    fn take(root: &mut Table, name: &str) -> anyhow::Result<Self> {
+        Self::new(name.to_string(), root.remove(name).unwrap_or(Value::Table(Table::new())))
+    }
+
+    /// The array of tables `name` of `root`, which may be absent.
+    fn tables(root: &mut Table, name: &str) -> anyhow::Result<Vec<Self>> {
+        match root.remove(name) {
+            Some(Value::Array(tables)) => tables
+                .into_iter()
+                .enumerate()
+                .map(|(i, table)| Self::new(format!("{}[{}]", name, i), table))
+                .collect(),
+            Some(value) => bail!("{}: expected an array of tables, found {}", name, value.kind()),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// The full name of `key`, as errors show it.
+    fn key(&self, key: &str) -> String {
+        match self.path.is_empty() {
+            true => key.to_string(),
+            false => format!("{}.{}", self.path, key),
+        }
+    }
+
+    fn get<T: FromValue>(&mut self, key: &str) -> anyhow::Result<Option<T>> {
+        let path = self.key(key);
+        self.table.remove(key).map(|value| T::from_value(value, &path)).transpose()
+    }
+
+    fn require<T: FromValue>(&mut self, key: &str) -> anyhow::Result<T> {
+        self.get(key)?.ok_or_else(|| anyhow!("{}: missing key", self.key(key)))
+    }
+
+    /// Replaces `value` if the key is present.
+    fn set<T: FromValue>(&mut self, value: &mut T, key: &str) -> anyhow::Result<()> {
+        if let Some(found) = self.get(key)? {
+            *value = found;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self.table.keys().next() {
+            Some(key) => bail!("{}: unknown key", self.key(key)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Conversion from a TOML value found at `path`, which errors name.
+trait FromValue: Sized {
+    fn from_value(value: Value, path: &str) -> anyhow::Result<Self>;
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value, path: &str) -> anyhow::Result<Self> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            value => bail!("{}: expected a boolean, found {}", path, value.kind()),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value, path: &str) -> anyhow::Result<Self> {
+        match value {
+            Value::String(s) => Ok(s),
+            value => bail!("{}: expected a string, found {}", path, value.kind()),
+        }
+    }
+}
+
+impl FromValue for PathBuf {
+    fn from_value(value: Value, path: &str) -> anyhow::Result<Self> {
+        String::from_value(value, path).map(PathBuf::from)
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value, path: &str) -> anyhow::Result<Self> {
+        match value {
+            Value::Array(values) => values
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| T::from_value(value, &format!("{}[{}]", path, i)))
+                .collect(),
+            value => bail!("{}: expected an array, found {}", path, value.kind()),
+        }
+    }
+}
+
+macro_rules! integer_value {
+    ($($ty:ty),*) => {$(
+        impl FromValue for $ty {
+            fn from_value(value: Value, path: &str) -> anyhow::Result<Self> {
+                match value {
+                    Value::Integer(n) => <$ty>::try_from(n)
+                        .map_err(|_| anyhow!("{}: {} is out of range", path, n)),
+                    value => bail!("{}: expected an integer, found {}", path, value.kind()),
+                }
+            }
+        }
+    )*};
+}
+
+integer_value!(u16, u32, u64, usize);
+
+/// Values written as strings in their usual notation.
+macro_rules! string_value {
+    ($($ty:ty),*) => {$(
+        impl FromValue for $ty {
+            fn from_value(value: Value, path: &str) -> anyhow::Result<Self> {
+                let text = String::from_value(value, path)?;
+                parse(&text).with_context(|| path.to_string())
+            }
+        }
+    )*};
+}
+
+string_value!(SocketAddr, Network, Level, Mode, PartialFailure);
+
+fn parse<T: FromStr>(text: &str) -> anyhow::Result<T> where T::Err: std::fmt::Display {
+    text.parse().map_err(|e| anyhow!("{:?}: {}", text, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+    use clap::Parser;
+    use crate::cli::Args;
+    use crate::config::Config;
+    use crate::log::Level;
+    use crate::server::{Mode, PartialFailure};
+    use crate::signer::SigningKey;
+    use crate::zone::Catalog;
+
+    fn testdata() -> &'static Path {
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src/testdata"))
+    }
+
+    #[test]
+    fn loads_every_section() {
+        let config = Config::load(&testdata().join("dns.toml")).unwrap();
+        let listeners = &config.server.listeners;
+        assert_eq!(listeners.len(), 2);
+        assert_eq!(listeners[0].address.to_string(), "127.0.0.1:5353");
+        assert_eq!(config.mode(&listeners[0]), Mode::Forward);
+        assert_eq!((listeners[1].address.to_string(), listeners[1].udp, listeners[1].tcp), ("[::1]:5353".into(), true, false));
+        assert_eq!(config.mode(&listeners[1]), Mode::Authoritative);
+        assert_eq!(config.server.tcp_idle_timeout, Duration::from_secs(5));
+        assert_eq!(config.server.workers, 32);
+        assert_eq!(config.upstream.servers.iter().map(|s| s.to_string()).collect::<Vec<_>>(), ["1.1.1.1:53", "8.8.8.8:53"]);
+        assert_eq!(config.upstream.timeout, Duration::from_millis(500));
+        assert_eq!(config.upstream.partial_failure, PartialFailure::Partial);
+        assert_eq!(config.zones.len(), 2);
+        assert_eq!(config.zones[0].path, testdata().join("zones/example.zone"));
+        assert_eq!(config.zones[0].origin.as_deref(), None);
+        assert_eq!(config.zones[1].origin.as_deref(), Some("example.org."));
+        let keys = config.signing.keys.iter().map(|path| SigningKey::load(path).unwrap()).collect();
+        let catalog = Catalog::load(&config.zones).unwrap().sign(keys, config.signing.validity).unwrap();
+        assert_eq!(catalog.find("www.example").unwrap().apex(), "example.");
+        assert_eq!(catalog.find("www.example.org").unwrap().apex(), "example.org.");
+        assert_eq!(catalog.ds_records().len(), 1);
+        assert_eq!(config.signing.validity, Duration::from_secs(7 * 24 * 60 * 60));
+        assert_eq!(config.cache.entries, 500);
+        assert!(config.acl.permits("10.1.2.3".parse().unwrap()));
+        assert!(!config.acl.permits("10.66.0.1".parse().unwrap()));
+        assert_eq!(config.log_level, Level::Debug);
+    }
+
+    #[test]
+    fn errors_name_the_key() {
+        let error = |text: &str| {
+            let config = Config::parse(text, Path::new(".")).and_then(|c| c.validate());
+            format!("{:#}", config.unwrap_err())
+        };
+        assert_eq!(error("[cache]\nentries = \"many\""), "cache.entries: expected an integer, found a string");
+        assert_eq!(error("[cache]\nentires = 10"), "cache.entires: unknown key");
+        assert_eq!(error("[caches]\nentries = 10"), "caches: unknown key");
+        assert_eq!(error("[server]\nworkers = -1"), "server.workers: -1 is out of range");
+        assert!(error("[upstream]\nservers = [\"1.1.1.1:53\", \"dns.google\"]").starts_with("upstream.servers[1]: \"dns.google\": "));
+        assert_eq!(error("[[zone]]\norigin = \"example.\""), "zone[0].file: missing key");
+        assert_eq!(error("[acl]\ndeny = [\"10.0.0.1/8\"]"), "acl.deny[0]: \"10.0.0.1/8\": 10.0.0.1/8 has host bits set");
+        assert_eq!(error("[signing]\nvalidity = 0"), "signing.validity: must be at least 1 day");
+        assert_eq!(error("[[listener]]\nport = 53"), "listener[0].address: missing key");
+        assert_eq!(
+            error("[[listener]]\naddress = \"[::1]:53\"\nprotocols = [\"udp\", \"quic\"]"),
+            "listener[0].protocols: unknown protocol \"quic\", expected udp or tcp"
+        );
+        assert_eq!(
+            error("[[listener]]\naddress = \"[::1]:53\"\nmode = \"stub\""),
+            "listener[0].mode: \"stub\": unknown mode \"stub\", expected authoritative, forward or recursive"
+        );
+        assert_eq!(
+            error("[[listener]]\naddress = \"[::1]:53\"\nmode = \"forward\""),
+            "listener[0].mode: there are no upstream.servers to forward to"
+        );
+        assert_eq!(error("[recursion]\nroot_hints = \"named.root\""), "recursion.root_hints: no listener recurses");
+        assert_eq!(error("[cache]\nentries = 10\nentries = 20"), "line 3: entries is defined twice");
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let path = testdata().join("dns.toml");
+        let args = Args::parse_from([
+            "server",
+            "--config", path.to_str().unwrap(),
+            "--cache-entries", "7",
+            "--resolver", "[2620:fe::fe]:53",
+            "--listen", "recursive=[::1]:53,127.0.0.1:53",
+            "--log-level", "warn",
+        ]);
+        let config = Config::from_args(&args).unwrap();
+        assert_eq!(config.cache.entries, 7);
+        assert_eq!(config.cache.bytes, 1 << 20);
+        assert_eq!(config.upstream.servers.iter().map(|s| s.to_string()).collect::<Vec<_>>(), ["[2620:fe::fe]:53"]);
+        let modes = config.server.listeners.iter().map(|l| config.mode(l)).collect::<Vec<_>>();
+        assert_eq!(modes, [Mode::Recursive, Mode::Forward]);
+        assert!(config.recurses());
+        assert_eq!(config.log_level, Level::Warn);
+        assert_eq!(Config::from_args(&Args::parse_from(["server"])).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn running_forwarders_need_upstreams() {
+        let config = Config::default();
+        assert!(config.supports(&[Mode::Authoritative, Mode::Recursive]).is_ok());
+        let error = config.supports(&[Mode::Authoritative, Mode::Forward]).unwrap_err();
+        assert_eq!(error.to_string(), "upstream.servers: a listener forwards, but there are no servers to forward to");
+    }
+}
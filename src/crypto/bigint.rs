@@ -0,0 +1,347 @@
+use std::cmp::Ordering;
+
+/// An unsigned integer of any size, as little-endian 32-bit limbs.
+///
+/// Nothing here tries to run in constant time: fine for verifying, but it
+/// means the time signing takes depends on the private key.
+#[derive(Debug, Clone)]
+pub struct Uint(Vec<u32>);
+
+impl Uint {
+    pub fn from_u64(value: u64) -> Self {
+        Self(vec![value as u32, (value >> 32) as u32])
+    }
+
+    pub fn from_be_bytes(bytes: &[u8]) -> Self {
+        let limbs = bytes
+            .rchunks(4)
+            .map(|chunk| chunk.iter().fold(0u32, |limb, &b| limb << 8 | b as u32))
+            .collect();
+        Self(limbs)
+    }
+
+    pub fn from_le_bytes(bytes: &[u8]) -> Self {
+        let reversed = bytes.iter().rev().copied().collect::<Vec<_>>();
+        Self::from_be_bytes(&reversed)
+    }
+
+    /// Parses a big-endian hex constant; panics on bad input.
+    pub fn from_hex(hex: &str) -> Self {
+        let hex = if hex.len() % 2 == 1 { format!("0{}", hex) } else { hex.to_string() };
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("bad hex constant"))
+            .collect::<Vec<_>>();
+        Self::from_be_bytes(&bytes)
+    }
+
+    /// Big-endian bytes, left-padded to `len`; higher bytes are dropped.
+    pub fn to_be_bytes(&self, len: usize) -> Vec<u8> {
+        let mut bytes = self.0.iter().flat_map(|limb| limb.to_le_bytes()).collect::<Vec<_>>();
+        bytes.resize(len, 0);
+        bytes.reverse();
+        bytes
+    }
+
+    pub fn to_le_bytes(&self, len: usize) -> Vec<u8> {
+        let mut bytes = self.to_be_bytes(len);
+        bytes.reverse();
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|&limb| limb == 0)
+    }
+
+    pub fn bits(&self) -> usize {
+        match self.0.iter().rposition(|&limb| limb != 0) {
+            Some(i) => i * 32 + 32 - self.0[i].leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    pub fn bit(&self, i: usize) -> bool {
+        self.0.get(i / 32).is_some_and(|limb| limb >> (i % 32) & 1 == 1)
+    }
+
+    pub fn sub_u32(&self, value: u32) -> Self {
+        let mut limbs = self.0.clone();
+        let mut borrow = value;
+        for limb in limbs.iter_mut() {
+            let (result, overflow) = limb.overflowing_sub(borrow);
+            *limb = result;
+            borrow = overflow as u32;
+        }
+        assert_eq!(borrow, 0, "Uint underflow");
+        Self(limbs)
+    }
+
+    #[allow(dead_code)]
+    pub fn add_u32(&self, value: u32) -> Self {
+        let mut limbs = self.0.clone();
+        limbs.push(0);
+        let mut carry = value;
+        for limb in limbs.iter_mut() {
+            let (result, overflow) = limb.overflowing_add(carry);
+            *limb = result;
+            carry = overflow as u32;
+        }
+        Self(limbs)
+    }
+
+    /// `self >> n`.
+    pub fn shr(&self, n: usize) -> Self {
+        let (limbs, bits) = (n / 32, n % 32);
+        let high = |i: usize| self.0.get(i).copied().unwrap_or(0);
+        let shifted = (limbs..self.0.len())
+            .map(|i| match bits {
+                0 => high(i),
+                _ => high(i) >> bits | high(i + 1) << (32 - bits),
+            })
+            .collect();
+        Self(shifted)
+    }
+
+    fn limb(&self, i: usize) -> u32 {
+        self.0.get(i).copied().unwrap_or(0)
+    }
+}
+
+impl PartialEq for Uint {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Uint {}
+
+impl PartialOrd for Uint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compares values, whatever the number of limbs.
+impl Ord for Uint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        (0..len).rev().map(|i| self.limb(i).cmp(&other.limb(i))).find(|o| o.is_ne()).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Arithmetic modulo an odd number, with products in Montgomery form.
+///
+/// Values passed to and returned by `mul`, `pow` and `inv` are Montgomery
+/// residues (x·R mod m); `enter` and `leave` convert. `add`, `sub` and
+/// equality work the same on either form.
+#[derive(Debug, Clone)]
+pub struct Modulus {
+    m: Uint,
+    /// -m⁻¹ mod 2³².
+    m_inv: u32,
+    /// R² mod m, with R = 2^(32·limbs).
+    r2: Uint,
+}
+
+impl Modulus {
+    pub fn new(m: Uint) -> Self {
+        assert!(m.bit(0) && m.bits() > 1, "Montgomery modulus must be odd and greater than 1");
+        let limbs = m.bits().div_ceil(32);
+        let m = Uint(m.0[..limbs].to_vec());
+        // Newton's iteration doubles the correct low bits each round
+        let mut inv = 1u32;
+        for _ in 0..5 {
+            inv = inv.wrapping_mul(2u32.wrapping_sub(m.0[0].wrapping_mul(inv)));
+        }
+        let mut modulus = Self { m_inv: inv.wrapping_neg(), r2: Uint(vec![0; limbs]), m };
+        let mut r2 = modulus.reduce(&Uint::from_u64(1));
+        for _ in 0..64 * limbs {
+            r2 = modulus.add(&r2, &r2);
+        }
+        modulus.r2 = r2;
+        modulus
+    }
+
+    pub fn value(&self) -> &Uint {
+        &self.m
+    }
+
+    fn limbs(&self) -> usize {
+        self.m.0.len()
+    }
+
+    /// `x mod m` for any `x`, one bit at a time.
+    pub fn reduce(&self, x: &Uint) -> Uint {
+        let mut r = Uint(vec![0; self.limbs()]);
+        for i in (0..x.bits()).rev() {
+            r = self.add(&r, &r);
+            if x.bit(i) {
+                r = self.add(&r, &Uint::from_u64(1));
+            }
+        }
+        r
+    }
+
+    /// Brings `x` into Montgomery form, x·R mod m.
+    pub fn enter(&self, x: &Uint) -> Uint {
+        self.mul(&self.reduce(x), &self.r2)
+    }
+
+    /// Back out of Montgomery form.
+    pub fn leave(&self, x: &Uint) -> Uint {
+        self.mul(x, &Uint::from_u64(1))
+    }
+
+    pub fn zero(&self) -> Uint {
+        Uint(vec![0; self.limbs()])
+    }
+
+    /// 1 in Montgomery form.
+    pub fn one(&self) -> Uint {
+        self.enter(&Uint::from_u64(1))
+    }
+
+    /// `a + b mod m` for reduced `a` and `b`.
+    pub fn add(&self, a: &Uint, b: &Uint) -> Uint {
+        let k = self.limbs();
+        let mut sum = Vec::with_capacity(k + 1);
+        let mut carry = 0u64;
+        for i in 0..k {
+            let s = a.limb(i) as u64 + b.limb(i) as u64 + carry;
+            sum.push(s as u32);
+            carry = s >> 32;
+        }
+        sum.push(carry as u32);
+        self.subtract_if_not_below(sum)
+    }
+
+    /// `a - b mod m` for reduced `a` and `b`.
+    pub fn sub(&self, a: &Uint, b: &Uint) -> Uint {
+        let k = self.limbs();
+        let mut diff = Vec::with_capacity(k);
+        let mut borrow = 0i64;
+        for i in 0..k {
+            let d = a.limb(i) as i64 - b.limb(i) as i64 - borrow;
+            diff.push(d as u32);
+            borrow = (d < 0) as i64;
+        }
+        if borrow == 1 {
+            let mut carry = 0u64;
+            for (i, limb) in diff.iter_mut().enumerate() {
+                let s = *limb as u64 + self.m.0[i] as u64 + carry;
+                *limb = s as u32;
+                carry = s >> 32;
+            }
+        }
+        Uint(diff)
+    }
+
+    pub fn neg(&self, a: &Uint) -> Uint {
+        self.sub(&self.zero(), a)
+    }
+
+    /// Montgomery product a·b·R⁻¹ mod m (CIOS).
+    pub fn mul(&self, a: &Uint, b: &Uint) -> Uint {
+        let k = self.limbs();
+        let m = &self.m.0;
+        let mut t = vec![0u32; k + 2];
+        for i in 0..k {
+            let bi = b.limb(i) as u64;
+            let mut carry = 0u64;
+            for (j, tj) in t.iter_mut().enumerate().take(k) {
+                let s = *tj as u64 + a.limb(j) as u64 * bi + carry;
+                *tj = s as u32;
+                carry = s >> 32;
+            }
+            let s = t[k] as u64 + carry;
+            t[k] = s as u32;
+            t[k + 1] = (s >> 32) as u32;
+
+            let q = t[0].wrapping_mul(self.m_inv) as u64;
+            let mut carry = (t[0] as u64 + q * m[0] as u64) >> 32;
+            for j in 1..k {
+                let s = t[j] as u64 + q * m[j] as u64 + carry;
+                t[j - 1] = s as u32;
+                carry = s >> 32;
+            }
+            let s = t[k] as u64 + carry;
+            t[k - 1] = s as u32;
+            t[k] = t[k + 1] + (s >> 32) as u32;
+            t[k + 1] = 0;
+        }
+        t.truncate(k + 1);
+        self.subtract_if_not_below(t)
+    }
+
+    pub fn square(&self, a: &Uint) -> Uint {
+        self.mul(a, a)
+    }
+
+    /// `base^exp` for a Montgomery `base` and a plain exponent.
+    pub fn pow(&self, base: &Uint, exp: &Uint) -> Uint {
+        let mut result = self.one();
+        for i in (0..exp.bits()).rev() {
+            result = self.square(&result);
+            if exp.bit(i) {
+                result = self.mul(&result, base);
+            }
+        }
+        result
+    }
+
+    /// Inverse by Fermat's little theorem, so `m` has to be prime.
+    pub fn inv(&self, a: &Uint) -> Uint {
+        self.pow(a, &self.m.sub_u32(2))
+    }
+
+    /// Brings a value below 2m back under m, dropping it to `limbs` limbs.
+    fn subtract_if_not_below(&self, mut value: Vec<u32>) -> Uint {
+        let k = self.limbs();
+        if Uint(value.clone()) >= self.m {
+            let mut borrow = 0i64;
+            for (i, limb) in value.iter_mut().enumerate() {
+                let d = *limb as i64 - self.m.limb(i) as i64 - borrow;
+                *limb = d as u32;
+                borrow = (d < 0) as i64;
+            }
+        }
+        value.truncate(k);
+        Uint(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::bigint::{Modulus, Uint};
+
+    #[test]
+    fn montgomery_arithmetic() {
+        // the P-256 field prime, and a small one spanning a single limb
+        let p = Uint::from_hex("ffffffff00000001000000000000000000000000ffffffffffffffffffffffff");
+        let field = Modulus::new(p.clone());
+        let a = Uint::from_hex("123456789abcdef0fedcba9876543210");
+        let a_mont = field.enter(&a);
+        assert_eq!(field.leave(&a_mont), a);
+        // a · a⁻¹ = 1 and a^(p-1) = 1
+        assert_eq!(field.leave(&field.mul(&a_mont, &field.inv(&a_mont))), Uint::from_u64(1));
+        assert_eq!(field.pow(&a_mont, &p.sub_u32(1)), field.one());
+        assert_eq!(field.sub(&field.zero(), &Uint::from_u64(1)), p.sub_u32(1));
+        assert_eq!(field.reduce(&p.add_u32(5)), Uint::from_u64(5));
+
+        let small = Modulus::new(Uint::from_u64(1_000_003));
+        let x = small.enter(&Uint::from_u64(123_456));
+        let y = small.enter(&Uint::from_u64(654_321));
+        assert_eq!(small.leave(&small.mul(&x, &y)), Uint::from_u64(123_456 * 654_321 % 1_000_003));
+    }
+
+    #[test]
+    fn byte_conversions() {
+        let n = Uint::from_be_bytes(&[1, 2, 3, 4, 5]);
+        assert_eq!(n, Uint::from_u64(0x0102030405));
+        assert_eq!(n.to_be_bytes(6), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(Uint::from_le_bytes(&[5, 4, 3, 2, 1]), n);
+        assert_eq!(n.bits(), 33);
+        assert_eq!(n.shr(8), Uint::from_u64(0x01020304));
+        assert_eq!(n.shr(36), Uint::from_u64(0));
+    }
+}
@@ -0,0 +1,300 @@
+use crate::crypto::bigint::{Modulus, Uint};
+
+/// A short Weierstrass curve y² = x³ - 3x + b over a prime field, which
+/// covers both NIST curves DNSSEC uses (RFC 6605).
+pub struct Curve {
+    field: Modulus,
+    order: Modulus,
+    /// b, in Montgomery form.
+    b: Uint,
+    /// The generator, in Montgomery form.
+    g: (Uint, Uint),
+    /// Size of a coordinate, and of r and s, in bytes.
+    size: usize,
+}
+
+/// Jacobian coordinates (X/Z², Y/Z³); Z = 0 is the point at infinity.
+#[derive(Clone)]
+struct Point {
+    x: Uint,
+    y: Uint,
+    z: Uint,
+}
+
+impl Curve {
+    pub fn p256() -> Self {
+        Self::new(
+            "ffffffff00000001000000000000000000000000ffffffffffffffffffffffff",
+            "ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551",
+            "5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b",
+            "6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296",
+            "4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5",
+        )
+    }
+
+    pub fn p384() -> Self {
+        Self::new(
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffeffffffff0000000000000000ffffffff",
+            "ffffffffffffffffffffffffffffffffffffffffffffffffc7634d81f4372ddf581a0db248b0a77aecec196accc52973",
+            "b3312fa7e23ee7e4988e056be3f82d19181d9c6efe8141120314088f5013875ac656398d8a2ed19d2a85c8edd3ec2aef",
+            "aa87ca22be8b05378eb1c71ef320ad746e1d3b628ba79b9859f741e082542a385502f25dbf55296c3a545e3872760ab7",
+            "3617de4a96262c6f5d9e98bf9292dc29f8f41dbd289a147ce9da3113b5f0b8c00a60b1ce1d7e819d7a431d7c90ea0e5f",
+        )
+    }
+
+    fn new(p: &str, n: &str, b: &str, gx: &str, gy: &str) -> Self {
+        let field = Modulus::new(Uint::from_hex(p));
+        let order = Modulus::new(Uint::from_hex(n));
+        let b = field.enter(&Uint::from_hex(b));
+        let g = (field.enter(&Uint::from_hex(gx)), field.enter(&Uint::from_hex(gy)));
+        Self { size: p.len() / 2, field, order, b, g }
+    }
+
+    /// Checks an ECDSA signature `r || s` over `digest` by the public key
+    /// `x || y`, both in the fixed-size form RFC 6605 uses.
+    pub fn verify(&self, public_key: &[u8], digest: &[u8], signature: &[u8]) -> bool {
+        if public_key.len() != 2 * self.size || signature.len() != 2 * self.size {
+            return false
+        }
+        let Some(q) = self.decode_point(public_key) else {
+            return false
+        };
+        let n = self.order.value();
+        let r = Uint::from_be_bytes(&signature[..self.size]);
+        let s = Uint::from_be_bytes(&signature[self.size..]);
+        if r.is_zero() || s.is_zero() || r >= *n || s >= *n {
+            return false
+        }
+        let order = &self.order;
+        // the digest is as long as the order here, so no truncation is needed
+        let e = order.enter(&Uint::from_be_bytes(digest));
+        let w = order.inv(&order.enter(&s));
+        let u1 = order.leave(&order.mul(&e, &w));
+        let u2 = order.leave(&order.mul(&order.enter(&r), &w));
+        let sum = self.add(&self.multiply(&self.generator(), &u1), &self.multiply(&q, &u2));
+        let Some((x, _)) = self.to_affine(&sum) else {
+            return false
+        };
+        order.reduce(&self.field.leave(&x)) == r
+    }
+
+    /// Signs `digest` with the private scalar `d` as `r || s`, or fails for
+    /// a scalar out of range. The nonce comes from the thread RNG.
+    pub fn sign(&self, private_key: &[u8], digest: &[u8]) -> Option<Vec<u8>> {
+        let order = &self.order;
+        let d = Uint::from_be_bytes(private_key);
+        if private_key.len() != self.size || d.is_zero() || d >= *order.value() {
+            return None
+        }
+        let (e, d) = (order.enter(&Uint::from_be_bytes(digest)), order.enter(&d));
+        loop {
+            // twice the bytes the order needs, so the reduction has no bias
+            // worth speaking of
+            let random = (0..2 * self.size).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+            let k = order.reduce(&Uint::from_be_bytes(&random));
+            let Some((x, _)) = self.to_affine(&self.multiply(&self.generator(), &k)) else {
+                continue
+            };
+            let r = order.reduce(&self.field.leave(&x));
+            let s = order.mul(&order.inv(&order.enter(&k)), &order.add(&e, &order.mul(&order.enter(&r), &d)));
+            let s = order.leave(&s);
+            if r.is_zero() || s.is_zero() {
+                continue
+            }
+            let mut signature = r.to_be_bytes(self.size);
+            signature.extend(s.to_be_bytes(self.size));
+            return Some(signature)
+        }
+    }
+
+    fn generator(&self) -> Point {
+        Point { x: self.g.0.clone(), y: self.g.1.clone(), z: self.field.one() }
+    }
+
+    fn infinity(&self) -> Point {
+        Point { x: self.field.one(), y: self.field.one(), z: self.field.zero() }
+    }
+
+    fn decode_point(&self, bytes: &[u8]) -> Option<Point> {
+        let (x, y) = (Uint::from_be_bytes(&bytes[..self.size]), Uint::from_be_bytes(&bytes[self.size..]));
+        if x >= *self.field.value() || y >= *self.field.value() {
+            return None
+        }
+        let point = (self.field.enter(&x), self.field.enter(&y));
+        self.is_on_curve(&point).then(|| Point { x: point.0, y: point.1, z: self.field.one() })
+    }
+
+    fn is_on_curve(&self, (x, y): &(Uint, Uint)) -> bool {
+        let f = &self.field;
+        let x3 = f.mul(&f.square(x), x);
+        let three_x = f.add(&f.add(x, x), x);
+        f.square(y) == f.add(&f.sub(&x3, &three_x), &self.b)
+    }
+
+    fn to_affine(&self, p: &Point) -> Option<(Uint, Uint)> {
+        if p.z.is_zero() {
+            return None
+        }
+        let f = &self.field;
+        let z_inv = f.inv(&p.z);
+        let z_inv2 = f.square(&z_inv);
+        Some((f.mul(&p.x, &z_inv2), f.mul(&p.y, &f.mul(&z_inv2, &z_inv))))
+    }
+
+    /// Left-to-right double-and-add.
+    fn multiply(&self, p: &Point, k: &Uint) -> Point {
+        let mut result = self.infinity();
+        for i in (0..k.bits()).rev() {
+            result = self.double(&result);
+            if k.bit(i) {
+                result = self.add(&result, p);
+            }
+        }
+        result
+    }
+
+    /// dbl-2001-b, which relies on a = -3.
+    fn double(&self, p: &Point) -> Point {
+        let f = &self.field;
+        if p.z.is_zero() || p.y.is_zero() {
+            return self.infinity()
+        }
+        let delta = f.square(&p.z);
+        let gamma = f.square(&p.y);
+        let beta = f.mul(&p.x, &gamma);
+        let t = f.mul(&f.sub(&p.x, &delta), &f.add(&p.x, &delta));
+        let alpha = f.add(&f.add(&t, &t), &t);
+        let beta4 = f.add(&f.add(&beta, &beta), &f.add(&beta, &beta));
+        let beta8 = f.add(&beta4, &beta4);
+        let x = f.sub(&f.square(&alpha), &beta8);
+        let z = f.sub(&f.sub(&f.square(&f.add(&p.y, &p.z)), &gamma), &delta);
+        let gamma2 = f.square(&gamma);
+        let gamma2_8 = {
+            let g2 = f.add(&gamma2, &gamma2);
+            let g4 = f.add(&g2, &g2);
+            f.add(&g4, &g4)
+        };
+        let y = f.sub(&f.mul(&alpha, &f.sub(&beta4, &x)), &gamma2_8);
+        Point { x, y, z }
+    }
+
+    /// add-2007-bl, falling back to doubling when both points are equal.
+    fn add(&self, p: &Point, q: &Point) -> Point {
+        let f = &self.field;
+        if p.z.is_zero() {
+            return q.clone()
+        }
+        if q.z.is_zero() {
+            return p.clone()
+        }
+        let z1z1 = f.square(&p.z);
+        let z2z2 = f.square(&q.z);
+        let u1 = f.mul(&p.x, &z2z2);
+        let u2 = f.mul(&q.x, &z1z1);
+        let s1 = f.mul(&f.mul(&p.y, &q.z), &z2z2);
+        let s2 = f.mul(&f.mul(&q.y, &p.z), &z1z1);
+        let h = f.sub(&u2, &u1);
+        let r = f.add(&f.sub(&s2, &s1), &f.sub(&s2, &s1));
+        if h.is_zero() {
+            return match r.is_zero() {
+                true => self.double(p),
+                false => self.infinity(),
+            }
+        }
+        let i = f.square(&f.add(&h, &h));
+        let j = f.mul(&h, &i);
+        let v = f.mul(&u1, &i);
+        let x = f.sub(&f.sub(&f.square(&r), &j), &f.add(&v, &v));
+        let s1j = f.mul(&s1, &j);
+        let y = f.sub(&f.mul(&r, &f.sub(&v, &x)), &f.add(&s1j, &s1j));
+        let z = f.mul(&f.sub(&f.sub(&f.square(&f.add(&p.z, &q.z)), &z1z1), &z2z2), &h);
+        Point { x, y, z }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::ecdsa::Curve;
+    use crate::crypto::sha::{sha256, sha384};
+    use crate::encoding::from_hex;
+
+    #[test]
+    fn curve_parameters() {
+        for curve in [Curve::p256(), Curve::p384()] {
+            let g = curve.generator();
+            assert!(curve.is_on_curve(&(g.x.clone(), g.y.clone())));
+            // n·G is the point at infinity, (n-1)·G is -G
+            let n = curve.order.value().clone();
+            assert!(curve.multiply(&g, &n).z.is_zero());
+            let minus_g = curve.to_affine(&curve.multiply(&g, &n.sub_u32(1))).unwrap();
+            assert_eq!(minus_g.0, g.x);
+            assert_eq!(minus_g.1, curve.field.neg(&g.y));
+        }
+    }
+
+    #[test]
+    fn verifies_known_signatures() {
+        // generated with the pyca/cryptography package
+        let message = b"DNSSEC known answer test";
+        let p256_key = from_hex(
+            "e6cfea25fe29819b95c75c9586d808d391c65eba40d29f50606eb04ad877b416\
+            e71a7350a3409d94585d0509b13503089aeede22efac47f20a48c98a17b0be06",
+        ).unwrap();
+        let p256_sig = from_hex(
+            "bc171cb27681c5485eabc972f610cf007f55f3accc1dd6b8074bcf9f42caf709\
+            16570232b2ba13fd193c3ba88ded78a5ef3ad7cf463132887e80021f74b5f0a2",
+        ).unwrap();
+        let curve = Curve::p256();
+        assert!(curve.verify(&p256_key, &sha256(message), &p256_sig));
+        assert!(!curve.verify(&p256_key, &sha256(b"something else"), &p256_sig));
+
+        let p384_key = from_hex(
+            "0b5a0d40cb95b09bb04208f7785b1819492e5893d59ee9887b058c3c62bc86bd\
+            0f9fc8983ccd1335eaa8ad8998e20a9d4b0d9424defa7944bdd137d2892b2fe1\
+            b02b983db9c651d9739774f05cc7575ffa91298d85e3c4de3717653e47583a16",
+        ).unwrap();
+        let p384_sig = from_hex(
+            "a49d5d78c6a3a873fffa328ab6b2c8b1212930b20b6f69588736d2b5f18cb97f\
+            b0a17ba61336e379f65630ed97b15880b3d1b818f94a5115eda1c90b220272e1\
+            b53a5be55737e82ba3ff1e2c2a8b53400e9e22d41b842a6395da0362940ac311",
+        ).unwrap();
+        let curve = Curve::p384();
+        assert!(curve.verify(&p384_key, &sha384(message), &p384_sig));
+        let mut broken = p384_sig.clone();
+        broken[10] ^= 1;
+        assert!(!curve.verify(&p384_key, &sha384(message), &broken));
+    }
+
+    #[test]
+    fn signs_for_its_own_verification() {
+        // key pairs generated with pyca/cryptography
+        let keys = [
+            (
+                Curve::p256(),
+                "b69cd2169bbe4595be6018ab6718d497a9647f9b129936ed2093d590508101a9",
+                "38c47476b572fabcd7c2b50053ac9f8c0f694441c68abbcbc8ff566f881ca5b3\
+                bb2ae80e4348770b9287ffe901ccb084612e334b912972e0d7798478ac2ee35c",
+            ),
+            (
+                Curve::p384(),
+                "db6a51917b61e9abe9d478196222c3ee94f311679153e001cbde91f71ce7e59a\
+                504ecec7f585ec4d190693c73f6cca36",
+                "c396e0ad045403840f77b066958f41950c039ef297e2f6f4d55a8232388f655f\
+                549f591c4b10282e1e8aa2577e2f98f0bd4e4d22baed4b025e5b338e762650f0\
+                59048bd657c452cc72885182740cea71e9638aa9d1ca254d109bf69444829c4b",
+            ),
+        ];
+        for (curve, private_key, public_key) in keys {
+            let (private_key, public_key) = (from_hex(private_key).unwrap(), from_hex(public_key).unwrap());
+            let digest = match curve.size {
+                32 => sha256(b"DNSSEC known answer test").to_vec(),
+                _ => sha384(b"DNSSEC known answer test").to_vec(),
+            };
+            let signature = curve.sign(&private_key, &digest).unwrap();
+            assert!(curve.verify(&public_key, &digest, &signature));
+            // a fresh nonce every time
+            assert_ne!(curve.sign(&private_key, &digest).unwrap(), signature);
+            assert!(curve.sign(&vec![0; curve.size], &digest).is_none());
+        }
+    }
+}
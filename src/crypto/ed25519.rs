@@ -0,0 +1,197 @@
+use crate::crypto::bigint::{Modulus, Uint};
+use crate::crypto::sha::sha512;
+
+/// Ed25519 signatures (RFC 8032 section 5.1).
+pub struct Ed25519 {
+    field: Modulus,
+    /// The group order L.
+    order: Modulus,
+    d: Uint,
+    /// 2·d, which the addition formula uses.
+    d2: Uint,
+    /// A square root of -1, for point decompression.
+    sqrt_m1: Uint,
+    base: Point,
+}
+
+/// Extended coordinates (X:Y:Z:T) with x = X/Z, y = Y/Z and x·y = T/Z, all
+/// in Montgomery form.
+#[derive(Clone)]
+struct Point {
+    x: Uint,
+    y: Uint,
+    z: Uint,
+    t: Uint,
+}
+
+impl Default for Ed25519 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ed25519 {
+    pub fn new() -> Self {
+        let field = Modulus::new(Uint::from_hex("7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffed"));
+        let order = Modulus::new(Uint::from_hex("1000000000000000000000000000000014def9dea2f79cd65812631a5cf5d3ed"));
+        let constant = |n: u64| field.enter(&Uint::from_u64(n));
+        // d = -121665/121666 and √-1 = 2^((p-1)/4)
+        let d = field.neg(&field.mul(&constant(121665), &field.inv(&constant(121666))));
+        let sqrt_m1 = field.pow(&constant(2), &field.value().sub_u32(1).shr(2));
+        // the base point is the one with y = 4/5 and an even x
+        let base_y = field.leave(&field.mul(&constant(4), &field.inv(&constant(5)))).to_le_bytes(32);
+        let identity = Point { x: field.zero(), y: field.one(), z: field.one(), t: field.zero() };
+        let mut curve = Self { d2: field.add(&d, &d), d, sqrt_m1, base: identity, field, order };
+        curve.base = curve.decode(&base_y).expect("base point");
+        curve
+    }
+
+    /// Checks `signature` (R || S) over `message` by the 32-byte key.
+    pub fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        if public_key.len() != 32 || signature.len() != 64 {
+            return false
+        }
+        let Some(a) = self.decode(public_key) else {
+            return false
+        };
+        let s = Uint::from_le_bytes(&signature[32..]);
+        if s >= *self.order.value() {
+            return false
+        }
+        let mut hashed = signature[..32].to_vec();
+        hashed.extend_from_slice(public_key);
+        hashed.extend_from_slice(message);
+        let k = self.order.reduce(&Uint::from_le_bytes(&sha512(&hashed)));
+        // [S]B - [k]A has to come out as R
+        let minus_a = Point { x: self.field.neg(&a.x), t: self.field.neg(&a.t), ..a };
+        let r = self.add(&self.multiply(&self.base, &s), &self.multiply(&minus_a, &k));
+        self.encode(&r) == signature[..32]
+    }
+
+    /// Signs `message` with a 32-byte private key (RFC 8032 section 5.1.6),
+    /// returning R || S.
+    pub fn sign(&self, private_key: &[u8], message: &[u8]) -> Option<Vec<u8>> {
+        if private_key.len() != 32 {
+            return None
+        }
+        let hash = sha512(private_key);
+        let mut scalar = hash[..32].to_vec();
+        scalar[0] &= 0xf8;
+        scalar[31] = scalar[31] & 0x7f | 0x40;
+        let a = Uint::from_le_bytes(&scalar);
+        let public_key = self.encode(&self.multiply(&self.base, &a));
+        let digest = |parts: &[&[u8]]| self.order.reduce(&Uint::from_le_bytes(&sha512(&parts.concat())));
+        let r = digest(&[&hash[32..], message]);
+        let mut signature = self.encode(&self.multiply(&self.base, &r));
+        let k = digest(&[&signature, &public_key, message]);
+        let order = &self.order;
+        let s = order.add(&r, &order.leave(&order.mul(&order.enter(&k), &order.enter(&a))));
+        signature.extend(s.to_le_bytes(32));
+        Some(signature)
+    }
+
+    fn identity(&self) -> Point {
+        Point { x: self.field.zero(), y: self.field.one(), z: self.field.one(), t: self.field.zero() }
+    }
+
+    /// Decompresses a point: y in little-endian with the sign of x on top.
+    fn decode(&self, bytes: &[u8]) -> Option<Point> {
+        let f = &self.field;
+        let mut y = bytes.to_vec();
+        let sign = y[31] >> 7 == 1;
+        y[31] &= 0x7f;
+        let y = Uint::from_le_bytes(&y);
+        if y >= *f.value() {
+            return None
+        }
+        let y = f.enter(&y);
+        // x² = (y² - 1) / (d·y² + 1) = u / v
+        let y2 = f.square(&y);
+        let u = f.sub(&y2, &f.one());
+        let v = f.add(&f.mul(&self.d, &y2), &f.one());
+        let v3 = f.mul(&f.square(&v), &v);
+        let uv7 = f.mul(&u, &f.mul(&f.square(&v3), &v));
+        let mut x = f.mul(&f.mul(&u, &v3), &f.pow(&uv7, &f.value().sub_u32(5).shr(3)));
+        let vx2 = f.mul(&v, &f.square(&x));
+        if vx2 == f.neg(&u) {
+            x = f.mul(&x, &self.sqrt_m1);
+        } else if vx2 != u {
+            return None
+        }
+        let plain = f.leave(&x);
+        if plain.is_zero() && sign {
+            return None
+        }
+        if plain.bit(0) != sign {
+            x = f.neg(&x);
+        }
+        Some(Point { t: f.mul(&x, &y), x, y, z: f.one() })
+    }
+
+    fn encode(&self, p: &Point) -> Vec<u8> {
+        let f = &self.field;
+        let z_inv = f.inv(&p.z);
+        let x = f.leave(&f.mul(&p.x, &z_inv));
+        let y = f.leave(&f.mul(&p.y, &z_inv));
+        let mut bytes = y.to_le_bytes(32);
+        bytes[31] |= (x.bit(0) as u8) << 7;
+        bytes
+    }
+
+    fn multiply(&self, p: &Point, k: &Uint) -> Point {
+        let mut result = self.identity();
+        for i in (0..k.bits()).rev() {
+            result = self.add(&result, &result);
+            if k.bit(i) {
+                result = self.add(&result, p);
+            }
+        }
+        result
+    }
+
+    /// add-2008-hwcd-3 for a = -1, which is complete: it also doubles.
+    fn add(&self, p: &Point, q: &Point) -> Point {
+        let f = &self.field;
+        let a = f.mul(&f.sub(&p.y, &p.x), &f.sub(&q.y, &q.x));
+        let b = f.mul(&f.add(&p.y, &p.x), &f.add(&q.y, &q.x));
+        let c = f.mul(&f.mul(&p.t, &self.d2), &q.t);
+        let zz = f.mul(&p.z, &q.z);
+        let d = f.add(&zz, &zz);
+        let (e, ff, g, h) = (f.sub(&b, &a), f.sub(&d, &c), f.add(&d, &c), f.add(&b, &a));
+        Point { x: f.mul(&e, &ff), y: f.mul(&g, &h), t: f.mul(&e, &h), z: f.mul(&ff, &g) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::ed25519::Ed25519;
+    use crate::encoding::from_hex;
+
+    #[test]
+    fn rfc8032_test_vectors() {
+        let curve = Ed25519::new();
+        let vectors = [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+                5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "72",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+                085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+        ];
+        for (private_key, key, message, signature) in vectors {
+            let private_key = from_hex(private_key).unwrap();
+            let (key, message, signature) = (from_hex(key).unwrap(), from_hex(message).unwrap(), from_hex(signature).unwrap());
+            assert!(curve.verify(&key, &message, &signature));
+            assert_eq!(curve.sign(&private_key, &message).unwrap(), signature);
+            assert!(!curve.verify(&key, b"tampered", &signature));
+        }
+    }
+}
@@ -0,0 +1,10 @@
+//! The cryptography DNSSEC validation and zone signing need, implemented
+//! here because the crate has no crypto dependency. None of it runs in
+//! constant time, so signing keys are only safe on hosts where nobody else
+//! can time them.
+
+pub mod bigint;
+pub mod ecdsa;
+pub mod ed25519;
+pub mod rsa;
+pub mod sha;
@@ -0,0 +1,140 @@
+use crate::crypto::bigint::{Modulus, Uint};
+
+/// ASN.1 DigestInfo header for SHA-256 (RFC 8017 section 9.2, note 1).
+const SHA256_DIGEST_INFO: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20,
+];
+/// RFC 3110 allows 512 to 4096 bit keys; anything bigger is just expensive.
+const MAX_MODULUS_BITS: usize = 4096;
+
+pub struct PublicKey {
+    modulus: Modulus,
+    exponent: Uint,
+    /// Modulus length in bytes, which is also the signature length.
+    size: usize,
+}
+
+impl PublicKey {
+    /// Reads the DNSKEY form of RFC 3110 section 2: the exponent length in
+    /// one octet (or zero and then two), the exponent, then the modulus.
+    pub fn from_dnskey(key: &[u8]) -> Option<Self> {
+        let (len, rest) = match key.split_first()? {
+            (0, rest) => (u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize, rest.get(2..)?),
+            (&len, rest) => (len as usize, rest),
+        };
+        if rest.len() <= len {
+            return None
+        }
+        let (exponent, modulus) = rest.split_at(len);
+        Self::new(modulus, exponent)
+    }
+
+    pub fn new(modulus: &[u8], exponent: &[u8]) -> Option<Self> {
+        let n = Uint::from_be_bytes(modulus);
+        if !n.bit(0) || n.bits() < 512 || n.bits() > MAX_MODULUS_BITS {
+            return None
+        }
+        let size = n.bits().div_ceil(8);
+        Some(Self { modulus: Modulus::new(n), exponent: Uint::from_be_bytes(exponent), size })
+    }
+
+    /// RSASSA-PKCS1-v1_5 verification of a SHA-256 `digest`.
+    pub fn verify_sha256(&self, digest: &[u8], signature: &[u8]) -> bool {
+        let s = Uint::from_be_bytes(signature);
+        if signature.len() != self.size || s >= *self.modulus.value() {
+            return false
+        }
+        let m = &self.modulus;
+        let encoded = m.leave(&m.pow(&m.enter(&s), &self.exponent)).to_be_bytes(self.size);
+        encoded == encode_sha256(digest, self.size)
+    }
+}
+
+pub struct PrivateKey {
+    public: PublicKey,
+    exponent: Uint,
+}
+
+impl PrivateKey {
+    pub fn new(modulus: &[u8], public_exponent: &[u8], private_exponent: &[u8]) -> Option<Self> {
+        let public = PublicKey::new(modulus, public_exponent)?;
+        Some(Self { public, exponent: Uint::from_be_bytes(private_exponent) })
+    }
+
+    /// RSASSA-PKCS1-v1_5 signature over a SHA-256 `digest`, without the
+    /// CRT speedup since we only sign when a zone is (re)signed.
+    pub fn sign_sha256(&self, digest: &[u8]) -> Vec<u8> {
+        let m = &self.public.modulus;
+        let encoded = Uint::from_be_bytes(&encode_sha256(digest, self.public.size));
+        m.leave(&m.pow(&m.enter(&encoded), &self.exponent)).to_be_bytes(self.public.size)
+    }
+}
+
+/// EMSA-PKCS1-v1_5 encoding of a SHA-256 digest (RFC 8017 section 9.2).
+fn encode_sha256(digest: &[u8], size: usize) -> Vec<u8> {
+    let mut encoded = vec![0x00, 0x01];
+    encoded.resize(size - SHA256_DIGEST_INFO.len() - digest.len() - 1, 0xff);
+    encoded.push(0x00);
+    encoded.extend_from_slice(&SHA256_DIGEST_INFO);
+    encoded.extend_from_slice(digest);
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::rsa::{PrivateKey, PublicKey};
+    use crate::crypto::sha::sha256;
+    use crate::encoding::from_hex;
+
+    #[test]
+    fn verifies_known_signature() {
+        // a 1024 bit key and signature generated with pyca/cryptography
+        let modulus = from_hex(
+            "bb8f06a287f42cb9da17ca25675b96efa6814f512750467e142eac02c3b9ac31\
+            989faf8fe5b2c3349c48436f84c54f093491e2872212ab7c22d06378e03b90a9\
+            68428a47e41bb7fc14381e74a97e9c35d1947ad8c40d0ac12a81a03dc978e534\
+            c113cbb7f00aafd99aabfcb700f8505d481b32c08394ca0098cb719fc91d7d3b",
+        ).unwrap();
+        let signature = from_hex(
+            "7919a6cafa869302aac58d46184ecc9791015ea8715aaba3a252bd79661f0d66\
+            83f5d812d220a728cf3a3a283ccc3b302818644a9bb217a55c8c37c1350431fd\
+            baab77e97586dc934a500e7f84b5caf2932ce9fbe02286d7904dbda6bdcdf290\
+            6f2e2a6cf0f11265353d9eadf56e061566245334235d4f26d6a8d687f584c182",
+        ).unwrap();
+        let mut dnskey = vec![3, 1, 0, 1];
+        dnskey.extend_from_slice(&modulus);
+        let key = PublicKey::from_dnskey(&dnskey).unwrap();
+        assert!(key.verify_sha256(&sha256(b"DNSSEC known answer test"), &signature));
+        assert!(!key.verify_sha256(&sha256(b"DNSSEC known answer test!"), &signature));
+        assert!(!key.verify_sha256(&sha256(b"DNSSEC known answer test"), &signature[1..]));
+        assert!(PublicKey::from_dnskey(&[3, 1, 0, 1]).is_none());
+    }
+
+    #[test]
+    fn signs_known_answer() {
+        // PKCS #1 v1.5 is deterministic, so this is pyca/cryptography's
+        // signature with a key it generated
+        let modulus = from_hex(
+            "c34e21d02cefaa01450fcff168566922e620e54c2b64a27068027a60286082b0\
+            17578edc6cfb0fac279138cd66d72054c255922f608637978b7a722a3899968b\
+            6a21db2c778e48bf3ff600ea1cc33970f06d52e7d83cedee15e6abc35a15c2eb\
+            77c2842929b165ee0db54d8001bf0ee27c4653d06fc5470b1976dcbb449eff23",
+        ).unwrap();
+        let private_exponent = from_hex(
+            "9236e4499ddaaf43d4184a6d48d7a84e604b305f1dfd713f061f14cef86b886b\
+            9c8e087e2d78a7fb12843e2a485bb5f0beaca18ca68ed4ffb7842c043feb7c30\
+            048a8b711980308180e3fdb5278767ee71b32d6b2619a7c4a522f21aa6fa6926\
+            6bb7512d17cec91db8a76837fd8d5f184216c4c02f315640c65e35a933f01141",
+        ).unwrap();
+        let signature = from_hex(
+            "8a65a9fdf72dd8f88d9887abf4722f16943ab729feb3fb425a967f18fdf57cbd\
+            0dedfeae12b2895594fd3fe04f480429ae582afbe586b3338b7324fe327f4f25\
+            5d929a6025d91d16079a6600c01e681081fafcfbc5c1c8672e0c5b507ef17385\
+            19d59b75ab603e10d31662e73a9a971a8905fc5abf98dc7c6837c17b45aba482",
+        ).unwrap();
+        let key = PrivateKey::new(&modulus, &[1, 0, 1], &private_exponent).unwrap();
+        let digest = sha256(b"DNSSEC known answer test");
+        assert_eq!(key.sign_sha256(&digest), signature);
+        assert!(PublicKey::new(&modulus, &[1, 0, 1]).unwrap().verify_sha256(&digest, &signature));
+    }
+}
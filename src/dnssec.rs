@@ -0,0 +1,634 @@
+use std::cmp::Ordering;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bytes::{BufMut, BytesMut};
+use crate::answer::{Answer, Data};
+use crate::crypto::ecdsa::Curve;
+use crate::crypto::ed25519::Ed25519;
+use crate::crypto::rsa::PublicKey;
+use crate::crypto::sha::{sha1, sha256, sha384};
+use crate::encoding::from_base32hex;
+use crate::message::{Labels, Ty};
+use crate::zone::{is_subdomain, key, parent};
+
+/// DNSSEC algorithm numbers we can verify (RFC 5702, RFC 6605, RFC 8080).
+pub const RSASHA256: u8 = 8;
+pub const ECDSAP256SHA256: u8 = 13;
+pub const ECDSAP384SHA384: u8 = 14;
+pub const ED25519: u8 = 15;
+
+/// DNSKEY flags (RFC 4034 section 2.1.1, RFC 5011 section 3).
+pub const ZONE_KEY: u16 = 0x0100;
+pub const REVOKED: u16 = 0x0080;
+/// Marks a key-signing key, the one a DS in the parent points at.
+pub const SECURE_ENTRY_POINT: u16 = 0x0001;
+/// DNSKEY protocol, which must always be 3.
+pub const PROTOCOL: u8 = 3;
+
+/// DS digest types (RFC 4034, RFC 4509, RFC 6605).
+pub const SHA1: u8 = 1;
+pub const SHA256: u8 = 2;
+pub const SHA384: u8 = 4;
+
+/// The only NSEC3 hash algorithm, SHA-1 (RFC 5155 section 11).
+pub const NSEC3_SHA1: u8 = 1;
+pub const OPT_OUT: u8 = 1;
+/// NSEC3 chains with more iterations than this are treated as insecure
+/// rather than hashed (RFC 9276 section 3.2).
+pub const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+pub fn is_supported(algorithm: u8) -> bool {
+    matches!(algorithm, RSASHA256 | ECDSAP256SHA256 | ECDSAP384SHA384 | ED25519)
+}
+
+/// Seconds since the epoch, in the 32-bit serial form RRSIGs use.
+pub fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as u32)
+}
+
+/// Key tag of a DNSKEY RDATA (RFC 4034 appendix B).
+pub fn key_tag(dnskey: &Data) -> u16 {
+    let rdata = dnskey.as_bytes();
+    let sum = rdata
+        .chunks(2)
+        .map(|pair| (pair[0] as u32) << 8 | pair.get(1).copied().unwrap_or(0) as u32)
+        .sum::<u32>();
+    (sum + (sum >> 16 & 0xffff)) as u16
+}
+
+/// The digest a DS record for the DNSKEY at `owner` would carry, or `None`
+/// for digest types we don't know.
+pub fn ds_digest(owner: &str, dnskey: &Data, digest_type: u8) -> Option<Vec<u8>> {
+    let mut data = canonical_name(owner).to_vec();
+    data.extend_from_slice(&dnskey.as_bytes());
+    match digest_type {
+        SHA1 => Some(sha1(&data).to_vec()),
+        SHA256 => Some(sha256(&data).to_vec()),
+        SHA384 => Some(sha384(&data).to_vec()),
+        _ => None,
+    }
+}
+
+/// Whether `ds` refers to the DNSKEY record `dnskey`.
+pub fn ds_matches(ds: &Data, dnskey: &Answer) -> bool {
+    let (Data::DS { key_tag: tag, algorithm, digest_type, digest }, Data::DNSKEY { algorithm: key_algorithm, .. }) =
+        (ds, dnskey.data())
+    else {
+        return false
+    };
+    *tag == key_tag(dnskey.data())
+        && algorithm == key_algorithm
+        && ds_digest(&dnskey.domain(), dnskey.data(), *digest_type).is_some_and(|d| d[..] == digest[..])
+}
+
+/// A DNSKEY that may sign zone data: the zone key flag set, not revoked.
+pub fn is_zone_key(dnskey: &Answer) -> bool {
+    matches!(dnskey.data(), Data::DNSKEY { flags, protocol: PROTOCOL, .. } if flags & ZONE_KEY != 0 && flags & REVOKED == 0)
+}
+
+/// Number of labels in a name, not counting a leading wildcard label, as
+/// the RRSIG labels field has it.
+pub fn label_count(name: &str) -> usize {
+    let name = key(name);
+    match name.as_str() {
+        "" => 0,
+        name => name.split('.').filter(|&label| label != "*").count(),
+    }
+}
+
+/// Orders names the way NSEC chains do (RFC 4034 section 6.1): label by
+/// label from the root, each label compared as lowercased octets.
+pub fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let labels = |name: &str| match key(name) {
+        name if name.is_empty() => vec![],
+        name => name.rsplit('.').map(|label| label.as_bytes().to_vec()).collect::<Vec<_>>(),
+    };
+    labels(a).cmp(&labels(b))
+}
+
+/// RFC 1982 serial number comparison, so validity periods keep working
+/// when the 32-bit timestamps wrap.
+pub fn in_validity_period(rrsig: &Data, now: u32) -> bool {
+    match rrsig {
+        Data::RRSIG { inception, expiration, .. } => {
+            now.wrapping_sub(*inception) as i32 >= 0 && expiration.wrapping_sub(now) as i32 >= 0
+        }
+        _ => false,
+    }
+}
+
+/// Checks `rrsig` over `rrset` against `keys`, which must be the signer's
+/// zone keys. The caller makes sure the signer is the right zone.
+pub fn verify_rrset(rrset: &[Answer], rrsig: &Answer, keys: &[Answer], now: u32) -> bool {
+    let Data::RRSIG { type_covered, algorithm, labels, key_tag: tag, signature, .. } = rrsig.data() else {
+        return false
+    };
+    let Some(first) = rrset.first() else {
+        return false
+    };
+    if *type_covered != first.ty() || *labels as usize > label_count(&first.domain()) || !in_validity_period(rrsig.data(), now) {
+        return false
+    }
+    let signed = signed_data(rrsig.data(), rrset);
+    keys.iter().any(|dnskey| match dnskey.data() {
+        Data::DNSKEY { algorithm: key_algorithm, public_key, .. } => {
+            key_algorithm == algorithm
+                && key_tag(dnskey.data()) == *tag
+                && verify_signature(*algorithm, public_key, &signed, signature)
+        }
+        _ => false,
+    })
+}
+
+/// The octets an RRSIG signs (RFC 4034 section 3.1.8.1): its own RDATA
+/// without the signature, then the RRset in canonical form and order.
+pub fn signed_data(rrsig: &Data, rrset: &[Answer]) -> Vec<u8> {
+    let Data::RRSIG { labels, original_ttl, signer, .. } = rrsig else {
+        return vec![]
+    };
+    let mut header = rrsig.clone();
+    if let Data::RRSIG { signer: lowercase, .. } = &mut header {
+        *lowercase = signer.to_lowercase();
+    }
+    let mut data = header.rrsig_header().to_vec();
+    let mut records = rrset
+        .iter()
+        .map(|rr| {
+            let owner = key(&rr.domain());
+            // a wildcard expansion is signed as the wildcard itself
+            let owner = match label_count(&owner) > *labels as usize {
+                true => {
+                    let kept = owner.split('.').rev().take(*labels as usize).collect::<Vec<_>>();
+                    kept.into_iter().rev().fold("*".to_string(), |name, label| format!("{}.{}", name, label))
+                }
+                false => owner,
+            };
+            let rdata = canonical_rdata(rr.data());
+            let mut buf = canonical_name(&owner);
+            buf.put_u16(rr.ty().into());
+            buf.put_u16(rr.class().into());
+            buf.put_u32(*original_ttl);
+            buf.put_u16(rdata.len() as u16);
+            (rdata, buf)
+        })
+        .collect::<Vec<_>>();
+    records.sort_by(|a, b| a.0.cmp(&b.0));
+    records.dedup_by(|a, b| a.0 == b.0);
+    for (rdata, head) in records {
+        data.extend_from_slice(&head);
+        data.extend_from_slice(&rdata);
+    }
+    data
+}
+
+/// Uncompressed, lowercased wire form of a name.
+pub fn canonical_name(name: &str) -> BytesMut {
+    Labels::from_domain(&key(name)).to_bytes()
+}
+
+/// RDATA with the embedded names of the RFC 4034 section 6.2 types
+/// lowercased (of those, the ones we model).
+pub fn canonical_rdata(data: &Data) -> BytesMut {
+    let lowercased = match data {
+        Data::NS(name) => Data::NS(name.to_lowercase()),
+        Data::CNAME(name) => Data::CNAME(name.to_lowercase()),
+        Data::PTR(name) => Data::PTR(name.to_lowercase()),
+        Data::MX { preference, exchange } => Data::MX { preference: *preference, exchange: exchange.to_lowercase() },
+        Data::SOA { mname, rname, serial, refresh, retry, expire, minimum } => Data::SOA {
+            mname: mname.to_lowercase(),
+            rname: rname.to_lowercase(),
+            serial: *serial,
+            refresh: *refresh,
+            retry: *retry,
+            expire: *expire,
+            minimum: *minimum,
+        },
+        Data::SRV { priority, weight, port, target } => Data::SRV {
+            priority: *priority,
+            weight: *weight,
+            port: *port,
+            target: target.to_lowercase(),
+        },
+        Data::RRSIG { .. } => {
+            let mut rrsig = data.clone();
+            if let Data::RRSIG { signer, .. } = &mut rrsig {
+                *signer = signer.to_lowercase();
+            }
+            rrsig
+        }
+        other => other.clone(),
+    };
+    lowercased.as_bytes()
+}
+
+pub fn verify_signature(algorithm: u8, public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    match algorithm {
+        RSASHA256 => PublicKey::from_dnskey(public_key).is_some_and(|key| key.verify_sha256(&sha256(data), signature)),
+        ECDSAP256SHA256 => p256().verify(public_key, &sha256(data), signature),
+        ECDSAP384SHA384 => p384().verify(public_key, &sha384(data), signature),
+        ED25519 => curve25519().verify(public_key, data, signature),
+        _ => false,
+    }
+}
+
+/// The curves, whose constants take a moment to set up, built once.
+pub fn p256() -> &'static Curve {
+    static P256: OnceLock<Curve> = OnceLock::new();
+    P256.get_or_init(Curve::p256)
+}
+
+pub fn p384() -> &'static Curve {
+    static P384: OnceLock<Curve> = OnceLock::new();
+    P384.get_or_init(Curve::p384)
+}
+
+pub fn curve25519() -> &'static Ed25519 {
+    static CURVE25519: OnceLock<Ed25519> = OnceLock::new();
+    CURVE25519.get_or_init(Ed25519::new)
+}
+
+/// The NSEC3 hash of a name (RFC 5155 section 5).
+pub fn nsec3_hash(name: &str, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut data = canonical_name(name).to_vec();
+    data.extend_from_slice(salt);
+    let mut hash = sha1(&data);
+    for _ in 0..iterations {
+        let mut data = hash.to_vec();
+        data.extend_from_slice(salt);
+        hash = sha1(&data);
+    }
+    hash.to_vec()
+}
+
+/// What authenticated NSEC or NSEC3 records say about a name.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Denial {
+    Proven,
+    /// Opt-out, or an NSEC3 chain too costly to check: nothing is proven
+    /// either way, so the answer can only be insecure.
+    Insecure,
+    Unproven,
+}
+
+/// Proves that `name` does not exist and that no wildcard could have
+/// produced it, from the validated NSEC or NSEC3 records of its zone.
+pub fn prove_nxdomain(name: &str, records: &[Answer]) -> Denial {
+    let name = key(name);
+    match Chain::of(records) {
+        Chain::Nsec(nsecs) => {
+            let Some(cover) = nsecs.iter().find(|n| n.covers(&name)) else {
+                return Denial::Unproven
+            };
+            let encloser = [&cover.owner, &cover.next]
+                .into_iter()
+                .map(|other| common_ancestor(&name, other))
+                .max_by_key(|a| label_count(a))
+                .unwrap_or_default();
+            proven(nsecs.iter().any(|n| n.covers(&wildcard(&encloser))))
+        }
+        Chain::Nsec3(nsec3s) => {
+            let Some((encloser, _)) = closest_encloser(&name, &nsec3s) else {
+                return Denial::Unproven
+            };
+            proven(nsec3s.iter().any(|n| n.covers(&wildcard(&encloser))))
+        }
+        Chain::Unusable => Denial::Insecure,
+    }
+}
+
+/// Proves that `name` has no records of type `ty` (nor a CNAME), either
+/// directly, as an empty non-terminal, or through a matching wildcard.
+pub fn prove_nodata(name: &str, ty: Ty, records: &[Answer]) -> Denial {
+    let name = key(name);
+    let lacks = |types: &[Ty]| {
+        let delegation = types.contains(&Ty::NS) && !types.contains(&Ty::SOA);
+        !types.contains(&ty)
+            && !types.contains(&Ty::CNAME)
+            // an NSEC from the parent side of a cut only speaks for DS, and
+            // one from the child apex never does
+            && match ty {
+                Ty::DS => !types.contains(&Ty::SOA) || name.is_empty(),
+                _ => !delegation,
+            }
+    };
+    match Chain::of(records) {
+        Chain::Nsec(nsecs) => {
+            if let Some(nsec) = nsecs.iter().find(|n| n.owner == name) {
+                return proven(lacks(&nsec.types))
+            }
+            let Some(cover) = nsecs.iter().find(|n| n.covers(&name)) else {
+                return Denial::Unproven
+            };
+            if is_subdomain(&cover.next, &name) {
+                // an empty non-terminal
+                return Denial::Proven
+            }
+            let encloser = [&cover.owner, &cover.next]
+                .into_iter()
+                .map(|other| common_ancestor(&name, other))
+                .max_by_key(|a| label_count(a))
+                .unwrap_or_default();
+            proven(nsecs.iter().any(|n| n.owner == wildcard(&encloser) && lacks(&n.types)))
+        }
+        Chain::Nsec3(nsec3s) => {
+            if let Some(nsec3) = nsec3s.iter().find(|n| n.matches(&name)) {
+                return proven(lacks(&nsec3.types))
+            }
+            let Some((encloser, cover)) = closest_encloser(&name, &nsec3s) else {
+                return Denial::Unproven
+            };
+            // an unsigned delegation may hide behind an opt-out span
+            if ty == Ty::DS && cover.flags & OPT_OUT != 0 {
+                return Denial::Insecure
+            }
+            proven(nsec3s.iter().any(|n| n.matches(&wildcard(&encloser)) && lacks(&n.types)))
+        }
+        Chain::Unusable => Denial::Insecure,
+    }
+}
+
+/// Proves that a wildcard was rightly expanded to `name`: the RRSIG says
+/// the source was `*.` plus the last `labels` labels of `name`, so the
+/// name one label below that must not exist.
+pub fn prove_wildcard(name: &str, labels: usize, records: &[Answer]) -> Denial {
+    let name = key(name);
+    let next_closer = name.split('.').rev().take(labels + 1).collect::<Vec<_>>();
+    let next_closer = next_closer.into_iter().rev().collect::<Vec<_>>().join(".");
+    match Chain::of(records) {
+        Chain::Nsec(nsecs) => proven(nsecs.iter().any(|n| n.covers(&name))),
+        Chain::Nsec3(nsec3s) => match nsec3s.iter().find(|n| n.covers(&next_closer)) {
+            Some(cover) if cover.flags & OPT_OUT != 0 => Denial::Insecure,
+            Some(_) => Denial::Proven,
+            None => Denial::Unproven,
+        },
+        Chain::Unusable => Denial::Insecure,
+    }
+}
+
+/// Whether `name` owns the NSEC record, or hashes to the NSEC3 record's
+/// owner.
+pub fn denial_matches(record: &Answer, name: &str) -> bool {
+    match Chain::of(std::slice::from_ref(record)) {
+        Chain::Nsec(nsecs) => nsecs.iter().any(|n| n.owner == key(name)),
+        Chain::Nsec3(nsec3s) => nsec3s.iter().any(|n| n.matches(&key(name))),
+        Chain::Unusable => false,
+    }
+}
+
+/// Whether `name` falls into the gap the NSEC or NSEC3 record spans.
+pub fn denial_covers(record: &Answer, name: &str) -> bool {
+    match Chain::of(std::slice::from_ref(record)) {
+        Chain::Nsec(nsecs) => nsecs.iter().any(|n| n.covers(&key(name))),
+        Chain::Nsec3(nsec3s) => nsec3s.iter().any(|n| n.covers(&key(name))),
+        Chain::Unusable => false,
+    }
+}
+
+/// Whether the NSEC or NSEC3 record for `name` among `records` shows a
+/// delegation: an NS record set without an SOA.
+pub fn is_delegation(name: &str, records: &[Answer]) -> bool {
+    records.iter().any(|r| {
+        let types = match r.data() {
+            Data::NSEC { types, .. } | Data::NSEC3 { types, .. } => types,
+            _ => return false,
+        };
+        denial_matches(r, name) && types.contains(&Ty::NS) && !types.contains(&Ty::SOA)
+    })
+}
+
+pub fn wildcard(encloser: &str) -> String {
+    match encloser {
+        "" => "*".to_string(),
+        encloser => format!("*.{}", encloser),
+    }
+}
+
+fn proven(proven: bool) -> Denial {
+    match proven {
+        true => Denial::Proven,
+        false => Denial::Unproven,
+    }
+}
+
+/// The longest name that `name` and `other` both are at or below.
+fn common_ancestor(name: &str, other: &str) -> String {
+    let mut ancestor = name;
+    while !is_subdomain(other, ancestor) {
+        ancestor = parent(ancestor);
+    }
+    ancestor.to_string()
+}
+
+/// The closest encloser proof of RFC 5155 section 7.2.1: the deepest
+/// existing ancestor of `name`, together with the NSEC3 covering the next
+/// closer name below it.
+fn closest_encloser<'a>(name: &str, nsec3s: &'a [Nsec3]) -> Option<(String, &'a Nsec3)> {
+    let mut next_closer = name;
+    while !next_closer.is_empty() {
+        let ancestor = parent(next_closer);
+        if nsec3s.iter().any(|n| n.matches(ancestor)) {
+            return nsec3s.iter().find(|n| n.covers(next_closer)).map(|cover| (ancestor.to_string(), cover))
+        }
+        next_closer = ancestor;
+    }
+    None
+}
+
+/// The denial records of one response, of whichever kind its zone uses.
+enum Chain {
+    Nsec(Vec<Nsec>),
+    Nsec3(Vec<Nsec3>),
+    /// NSEC3 records with no hash we can compute.
+    Unusable,
+}
+
+struct Nsec {
+    owner: String,
+    next: String,
+    types: Vec<Ty>,
+}
+
+struct Nsec3 {
+    /// The zone the hashed owner name is in.
+    zone: String,
+    hash: Vec<u8>,
+    next: Vec<u8>,
+    flags: u8,
+    salt: Vec<u8>,
+    iterations: u16,
+    types: Vec<Ty>,
+}
+
+impl Chain {
+    fn of(records: &[Answer]) -> Self {
+        let nsec3s = records
+            .iter()
+            .filter_map(|r| match r.data() {
+                Data::NSEC3 { hash_algorithm, flags, iterations, salt, next_hashed, types } => {
+                    let owner = key(&r.domain());
+                    let (hash, zone) = owner.split_once('.').unwrap_or((&owner, ""));
+                    Some((*hash_algorithm, Nsec3 {
+                        zone: zone.to_string(),
+                        hash: from_base32hex(hash).ok()?,
+                        next: next_hashed.to_vec(),
+                        flags: *flags,
+                        salt: salt.to_vec(),
+                        iterations: *iterations,
+                        types: types.clone(),
+                    }))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !nsec3s.is_empty() {
+            let usable = nsec3s
+                .into_iter()
+                .filter(|(algorithm, n)| *algorithm == NSEC3_SHA1 && n.iterations <= MAX_NSEC3_ITERATIONS)
+                .map(|(_, n)| n)
+                .collect::<Vec<_>>();
+            return match usable.is_empty() {
+                true => Chain::Unusable,
+                false => Chain::Nsec3(usable),
+            }
+        }
+        Chain::Nsec(records
+            .iter()
+            .filter_map(|r| match r.data() {
+                Data::NSEC { next, types } => Some(Nsec {
+                    owner: key(&r.domain()),
+                    next: key(&next.to_string()),
+                    types: types.clone(),
+                }),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+impl Nsec {
+    /// Whether `name` falls strictly between this record and the next; the
+    /// last NSEC of a zone wraps around to the apex.
+    fn covers(&self, name: &str) -> bool {
+        let after_owner = canonical_cmp(&self.owner, name) == Ordering::Less;
+        let before_next = canonical_cmp(name, &self.next) == Ordering::Less;
+        match canonical_cmp(&self.owner, &self.next) {
+            Ordering::Less => after_owner && before_next,
+            _ => after_owner || before_next,
+        }
+    }
+}
+
+impl Nsec3 {
+    fn hash_of(&self, name: &str) -> Option<Vec<u8>> {
+        is_subdomain(name, &self.zone).then(|| nsec3_hash(name, &self.salt, self.iterations))
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.hash_of(name).is_some_and(|hash| hash == self.hash)
+    }
+
+    fn covers(&self, name: &str) -> bool {
+        let Some(hash) = self.hash_of(name) else {
+            return false
+        };
+        match self.hash < self.next {
+            true => self.hash < hash && hash < self.next,
+            false => self.hash < hash || hash < self.next,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::answer::{Answer, Data};
+    use crate::dnssec::{canonical_cmp, ds_matches, key_tag, nsec3_hash, prove_nodata, prove_nxdomain, Denial};
+    use crate::encoding::{base32hex, from_base64, from_hex};
+    use crate::message::{Class, Labels, Ty};
+
+    #[test]
+    fn key_tags_and_hashes() {
+        // the 2017 root KSK, whose tag is famously 20326
+        let key = from_base64(
+            "AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN7SWXgnLh4+B5xQlNVz8Og8kv\
+            ArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+e\
+            oZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLYA4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwN\
+            R1AkUTV74bU=",
+        ).unwrap();
+        let dnskey = Data::DNSKEY { flags: 257, protocol: 3, algorithm: 8, public_key: key.into() };
+        assert_eq!(key_tag(&dnskey), 20326);
+        let ds = Data::DS {
+            key_tag: 20326,
+            algorithm: 8,
+            digest_type: 2,
+            digest: from_hex("E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D").unwrap().into(),
+        };
+        assert!(ds_matches(&ds, &Answer::with_data(".", Class::IN, 172800, dnskey)));
+
+        // RFC 5155 appendix A
+        let hash = nsec3_hash("example", &[0xaa, 0xbb, 0xcc, 0xdd], 12);
+        assert_eq!(base32hex(&hash).to_lowercase(), "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom");
+        let hash = nsec3_hash("a.example", &[0xaa, 0xbb, 0xcc, 0xdd], 12);
+        assert_eq!(base32hex(&hash).to_lowercase(), "35mthgpgcu1qg68fab165klnsnk3dpvl");
+    }
+
+    #[test]
+    fn canonical_order() {
+        // RFC 4034 section 6.1
+        let names = ["example", "a.example", "yljkjljk.a.example", "Z.a.example", "zABC.a.EXAMPLE", "*.z.example"];
+        for pair in names.windows(2) {
+            assert_eq!(canonical_cmp(pair[0], pair[1]), std::cmp::Ordering::Less, "{:?}", pair);
+        }
+        assert_eq!(canonical_cmp("A.example.", "a.example"), std::cmp::Ordering::Equal);
+    }
+
+    fn nsec(owner: &str, next: &str, types: &[Ty]) -> Answer {
+        Answer::with_data(owner, Class::IN, 300, Data::NSEC { next: Labels::from_domain(next), types: types.to_vec() })
+    }
+
+    fn nsec3(hashed: &str, next: &str, flags: u8, types: &[Ty]) -> Answer {
+        Answer::with_data(format!("{}.example", hashed), Class::IN, 300, Data::NSEC3 {
+            hash_algorithm: 1,
+            flags,
+            iterations: 12,
+            salt: Bytes::from_static(&[0xaa, 0xbb, 0xcc, 0xdd]),
+            next_hashed: crate::encoding::from_base32hex(next).unwrap().into(),
+            types: types.to_vec(),
+        })
+    }
+
+    #[test]
+    fn nsec_denials() {
+        let chain = [
+            nsec("example", "a.example", &[Ty::SOA, Ty::NS, Ty::NSEC]),
+            nsec("a.example", "b.c.example", &[Ty::A, Ty::NSEC]),
+            nsec("b.c.example", "example", &[Ty::A, Ty::NSEC]),
+        ];
+        assert_eq!(prove_nxdomain("aa.example", &chain), Denial::Proven);
+        // the wildcard *.example would sort right after the apex
+        assert_eq!(prove_nxdomain("aa.example", &chain[1..]), Denial::Unproven);
+        assert_eq!(prove_nodata("a.example", Ty::AAAA, &chain), Denial::Proven);
+        assert_eq!(prove_nodata("a.example", Ty::A, &chain), Denial::Unproven);
+        // c.example is an empty non-terminal
+        assert_eq!(prove_nodata("c.example", Ty::A, &chain), Denial::Proven);
+        assert_eq!(prove_nodata("example", Ty::DS, &chain), Denial::Unproven);
+    }
+
+    #[test]
+    fn nsec3_denials() {
+        // the relevant part of the RFC 5155 appendix A zone
+        let chain = [
+            nsec3("0p9mhaveqvm6t7vbl5lop2u3t2rp3tom", "2t7b4g4vsa5smi47k61mv5bv1a22bojr", 1, &[Ty::NS, Ty::SOA, Ty::MX, Ty::RRSIG, Ty::DNSKEY, Ty::NSEC3]),
+            nsec3("35mthgpgcu1qg68fab165klnsnk3dpvl", "b4um86eghhds6nea196smvmlo4ors995", 1, &[Ty::NS, Ty::DS, Ty::RRSIG]),
+            nsec3("b4um86eghhds6nea196smvmlo4ors995", "gjeqe526plbf1g8mklp59enfd789njgi", 1, &[Ty::MX, Ty::RRSIG]),
+        ];
+        // a.c.x.w.example: the closest encloser is x.w.example (b4um...),
+        // c.x.w.example (0va5...) and *.x.w.example (92pq...) are covered
+        assert_eq!(prove_nxdomain("a.c.x.w.example", &chain), Denial::Proven);
+        assert_eq!(prove_nodata("ns1.example", Ty::MX, &chain[1..]), Denial::Unproven);
+        assert_eq!(prove_nodata("example", Ty::A, &chain), Denial::Proven);
+        assert_eq!(prove_nodata("example", Ty::MX, &chain), Denial::Unproven);
+        // a.example is a signed delegation, so its DS can't be denied
+        assert_eq!(prove_nodata("a.example", Ty::DS, &chain), Denial::Unproven);
+    }
+}
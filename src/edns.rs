@@ -0,0 +1,116 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use crate::answer::{Answer, Data};
+use crate::error::{read_u16, ParseError};
+use crate::message::{Class, Ty};
+
+/// EDNS(0) parameters carried by the OPT pseudo-record (RFC 6891).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Edns {
+    pub payload_size: u16,
+    /// Upper 8 bits of the 12-bit RCODE; the lower 4 live in the header.
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub flags: u16,
+    pub options: Vec<EdnsOption>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Bytes,
+}
+
+impl Default for Edns {
+    fn default() -> Self {
+        Self {
+            payload_size: Self::PAYLOAD_SIZE,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: vec![],
+        }
+    }
+}
+
+impl Edns {
+    /// Payload size we advertise ourselves (the DNS flag day 2020 default).
+    pub const PAYLOAD_SIZE: u16 = 1232;
+    /// Receive buffer for UDP datagrams, large enough for any EDNS payload
+    /// we see from clients or upstreams.
+    pub const MAX_UDP_PAYLOAD: usize = 4096;
+    const DO: u16 = 0b1000_0000_0000_0000;
+
+    pub fn dnssec_ok(&self) -> bool {
+        self.flags & Self::DO != 0
+    }
+
+    pub fn set_dnssec_ok(&mut self, dnssec_ok: bool) {
+        if dnssec_ok {
+            self.flags |= Self::DO
+        } else {
+            self.flags &= !Self::DO
+        }
+    }
+
+    pub fn from_record(record: &Answer) -> Result<Self, ParseError> {
+        let ttl = record.ttl();
+        let rdata = match record.data() {
+            Data::Opaque(bytes) => bytes.clone(),
+            _ => Bytes::new(),
+        };
+        let mut options = vec![];
+        let mut offset = 0;
+        while offset < rdata.len() {
+            let code = read_u16(&rdata, offset)?;
+            let len = read_u16(&rdata, offset + 2)? as usize;
+            let data = rdata
+                .get(offset + 4..offset + 4 + len)
+                .ok_or(ParseError::BadRdata { ty: Ty::OPT.into(), offset })?;
+            options.push(EdnsOption { code, data: Bytes::copy_from_slice(data) });
+            offset += 4 + len;
+        }
+        Ok(Self {
+            payload_size: record.class().into(),
+            extended_rcode: (ttl >> 24) as u8,
+            version: (ttl >> 16) as u8,
+            flags: ttl as u16,
+            options,
+        })
+    }
+
+    pub fn to_record(&self) -> Answer {
+        let ttl = (self.extended_rcode as u32) << 24 | (self.version as u32) << 16 | self.flags as u32;
+        let mut rdata = BytesMut::new();
+        for option in &self.options {
+            rdata.put_u16(option.code);
+            rdata.put_u16(option.data.len() as u16);
+            rdata.extend_from_slice(&option.data);
+        }
+        Answer::opaque("", Ty::OPT, Class::from(self.payload_size), ttl, rdata.freeze())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::edns::{Edns, EdnsOption};
+
+    #[test]
+    fn opt_record_round_trip() {
+        let mut edns = Edns {
+            payload_size: 4096,
+            extended_rcode: 1,
+            options: vec![EdnsOption { code: 10, data: Bytes::from_static(b"cookie!!") }],
+            ..Default::default()
+        };
+        edns.set_dnssec_ok(true);
+        let record = edns.to_record();
+        assert_eq!(record.ttl(), 0x0100_8000);
+        let serialized = record.clone().serialize();
+        assert_eq!(&serialized[..3], b"\0\0\x29");
+        assert_eq!(Edns::from_record(&record), Ok(edns.clone()));
+        assert!(edns.dnssec_ok());
+        edns.set_dnssec_ok(false);
+        assert_eq!(edns.flags, 0);
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::{anyhow, bail};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+/// The "extended hex" alphabet of RFC 4648 section 7, which keeps sort order.
+const BASE32HEX: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+pub fn base64(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+/// Decodes base64, ignoring whitespace so that keys and signatures split
+/// over several master file tokens can be joined first.
+pub fn from_base64(text: &str) -> anyhow::Result<Vec<u8>> {
+    let text = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect::<Vec<_>>();
+    if text.len() % 4 != 0 {
+        bail!("base64 length {} is not a multiple of 4", text.len())
+    }
+    let padding = text.iter().rev().take_while(|&&b| b == b'=').count();
+    if padding > 2 {
+        bail!("too much base64 padding")
+    }
+    let mut out = vec![];
+    for chunk in text.chunks(4) {
+        let mut n = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let value = match c {
+                b'=' => 0,
+                c => BASE64.iter().position(|&b| b == c).ok_or_else(|| anyhow!("bad base64 character {:?}", c as char))?,
+            };
+            n |= (value as u32) << (18 - 6 * i);
+        }
+        out.extend_from_slice(&n.to_be_bytes()[1..]);
+    }
+    if text[..text.len() - padding].contains(&b'=') {
+        bail!("base64 padding in the middle of the data")
+    }
+    out.truncate(out.len() - padding);
+    Ok(out)
+}
+
+/// Base32hex without padding, as NSEC3 hashes are written.
+pub fn base32hex(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u16, 0);
+    for &b in bytes {
+        buffer = buffer << 8 | b as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32HEX[(buffer >> bits & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32HEX[(buffer << (5 - bits) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+pub fn from_base32hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    let mut out = vec![];
+    let (mut buffer, mut bits) = (0u16, 0);
+    for c in text.trim_end_matches('=').bytes() {
+        let value = BASE32HEX
+            .iter()
+            .position(|&b| b == c.to_ascii_uppercase())
+            .ok_or_else(|| anyhow!("bad base32hex character {:?}", c as char))?;
+        buffer = buffer << 5 | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    if buffer & ((1 << bits) - 1) != 0 {
+        bail!("base32hex has trailing bits set")
+    }
+    Ok(out)
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Decodes hex digits, ignoring whitespace between them.
+pub fn from_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    let digits = text.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    if digits.len() % 2 == 1 || !digits.is_ascii() {
+        bail!("not a whole number of hex octets")
+    }
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// RRSIG validity times in their `YYYYMMDDHHmmSS` presentation form (UTC).
+pub fn timestamp(secs: u32) -> String {
+    let days = (secs / 86400) as i64;
+    let rest = secs % 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}{:02}{:02}{:02}{:02}{:02}", year, month, day, rest / 3600, rest / 60 % 60, rest % 60)
+}
+
+/// Parses either form RFC 4034 section 3.2 allows: `YYYYMMDDHHmmSS`, or
+/// plain seconds since the epoch. Times past 2106 wrap, like on the wire.
+pub fn parse_timestamp(text: &str) -> anyhow::Result<u32> {
+    if !text.bytes().all(|b| b.is_ascii_digit()) {
+        bail!("bad timestamp {:?}", text)
+    }
+    if text.len() != 14 {
+        return Ok(text.parse()?)
+    }
+    let field = |range: std::ops::Range<usize>| text[range].parse::<i64>().unwrap();
+    let (year, month, day) = (field(0..4), field(4..6), field(6..8));
+    let (hour, minute, second) = (field(8..10), field(10..12), field(12..14));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        bail!("bad timestamp {:?}", text)
+    }
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Ok(secs as u32)
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    (year_of_era + era * 400 + (month <= 2) as i64, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::{base32hex, base64, from_base32hex, from_base64, from_hex, hex, parse_timestamp, timestamp};
+
+    #[test]
+    fn rfc4648_vectors() {
+        let cases = [
+            ("", "", ""),
+            ("f", "Zg==", "CO"),
+            ("fo", "Zm8=", "CPNG"),
+            ("foo", "Zm9v", "CPNMU"),
+            ("foob", "Zm9vYg==", "CPNMUOG"),
+            ("fooba", "Zm9vYmE=", "CPNMUOJ1"),
+            ("foobar", "Zm9vYmFy", "CPNMUOJ1E8"),
+        ];
+        for (plain, b64, b32) in cases {
+            assert_eq!(base64(plain.as_bytes()), b64);
+            assert_eq!(from_base64(b64).unwrap(), plain.as_bytes());
+            assert_eq!(base32hex(plain.as_bytes()), b32);
+            assert_eq!(from_base32hex(&b32.to_lowercase()).unwrap(), plain.as_bytes());
+        }
+        assert_eq!(from_base64("Zm9v YmFy").unwrap(), b"foobar");
+        assert!(from_base64("Zm=v").is_err());
+        assert!(from_base64("Zm9").is_err());
+        assert_eq!(hex(&from_hex("0aFf 10").unwrap()), "0AFF10");
+        assert!(from_hex("abc").is_err());
+    }
+
+    #[test]
+    fn rrsig_timestamps() {
+        assert_eq!(timestamp(0), "19700101000000");
+        assert_eq!(parse_timestamp("20240229123456").unwrap(), 1709210096);
+        assert_eq!(timestamp(1709210096), "20240229123456");
+        assert_eq!(parse_timestamp("1709210096").unwrap(), 1709210096);
+        assert_eq!(timestamp(u32::MAX), "21060207062815");
+        assert!(parse_timestamp("20241301000000").is_err());
+    }
+}
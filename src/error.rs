@@ -0,0 +1,54 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+pub enum ParseError {
+    #[error("message truncated at offset {offset}")]
+    Truncated { offset: usize },
+    #[error("bad label length {len:#04x} at offset {offset}")]
+    BadLabelLength { offset: usize, len: u8 },
+    #[error("compression pointer loop at offset {offset}")]
+    PointerLoop { offset: usize },
+    #[error("compression pointer at offset {offset} does not point backwards")]
+    ForwardPointer { offset: usize },
+    #[error("name starting at offset {offset} is longer than 255 octets")]
+    NameTooLong { offset: usize },
+    #[error("bad label {label:?} in {name:?}, labels are 1 to 63 octets")]
+    BadLabel { name: String, label: String },
+    #[error("name {name:?} is longer than 255 octets")]
+    DomainTooLong { name: String },
+    #[error("malformed rdata for type {ty} at offset {offset}")]
+    BadRdata { ty: u16, offset: usize },
+    #[error("second OPT record at offset {offset}")]
+    MultipleOpt { offset: usize },
+}
+
+pub(crate) fn read_u8(bytes: &[u8], offset: usize) -> Result<u8, ParseError> {
+    bytes.get(offset).copied().ok_or(ParseError::Truncated { offset })
+}
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, ParseError> {
+    match bytes.get(offset..offset + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+        None => Err(ParseError::Truncated { offset }),
+    }
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, ParseError> {
+    match bytes.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(ParseError::Truncated { offset }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{read_u16, read_u32, ParseError};
+
+    #[test]
+    fn read_past_end() {
+        let bytes = [0u8, 1, 2];
+        assert_eq!(read_u16(&bytes, 1), Ok(0x0102));
+        assert_eq!(read_u16(&bytes, 2), Err(ParseError::Truncated { offset: 2 }));
+        assert_eq!(read_u32(&bytes, 0), Err(ParseError::Truncated { offset: 0 }));
+    }
+}
@@ -1,21 +1,23 @@
 #![allow(dead_code, unused)]
 
 use std::fmt::Formatter;
-use anyhow::bail;
 use bytes::{BufMut, Bytes, BytesMut};
+use crate::error::ParseError;
 
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone)]
 pub struct Header {
     pub id: u16, // 16 bits
     pub qr: bool, // 1 bit
-    pub opcode: u8, // 4 bits
+    pub opcode: Opcode, // 4 bits
     pub aa: bool, // 1 bit
     pub tc: bool, // 1 bit
     pub rd: bool, // 1 bit
     pub ra: bool, // 1 bit
-    pub reserved: u8, // 3 bits
-    pub r_code: u8, // 4 bits
+    pub z: bool, // 1 bit, must be zero
+    pub ad: bool, // 1 bit, authenticated data (RFC 4035)
+    pub cd: bool, // 1 bit, checking disabled (RFC 4035)
+    pub r_code: Rcode, // 4 bits
     pub qd_count: u16, // 16 bits
     pub an_count: u16, // 16 bits
     pub ns_count: u16, // 16 bits
@@ -23,7 +25,7 @@ pub struct Header {
 }
 impl Default for Header {
     fn default() -> Self {
-        Self::new(1234,true, 0,false,false,false,false,0,0,0,0,0,0)
+        Self::new(1234,true, Opcode::Query,false,false,false,false,false,false,false,Rcode::NoError,0,0,0,0)
     }
 }
 
@@ -36,25 +38,56 @@ impl Header {
     pub fn an(&self) -> usize {
         self.an_count as usize
     }
+
+    pub fn ns(&self) -> usize {
+        self.ns_count as usize
+    }
+
+    pub fn ar(&self) -> usize {
+        self.ar_count as usize
+    }
     pub fn set_id(&mut self, id: u16) {
         self.id = id
     }
 
-    pub fn set_opcode(&mut self, opcode: u8) {
+    pub fn set_opcode(&mut self, opcode: Opcode) {
         self.opcode = opcode;
-        if opcode != 0 {
-            self.r_code = 4
-        };
+    }
+
+    pub fn set_aa(&mut self, aa: bool) {
+        self.aa = aa;
+    }
+
+    pub fn set_qr(&mut self, qr: bool) {
+        self.qr = qr;
     }
 
     pub fn set_rd(&mut self, rd: bool) {
         self.rd = rd;
     }
 
-    pub fn get_id_opcode_rd(&self) -> (u16, u8, bool) {
+    pub fn set_ra(&mut self, ra: bool) {
+        self.ra = ra;
+    }
+
+    pub fn set_ad(&mut self, ad: bool) {
+        self.ad = ad;
+    }
+
+    pub fn set_cd(&mut self, cd: bool) {
+        self.cd = cd;
+    }
+
+    /// Only the low 4 bits of `r_code` fit in the header; see `Message::set_rcode`.
+    pub fn set_r_code(&mut self, r_code: Rcode) {
+        self.r_code = r_code;
+    }
+
+    pub fn get_id_opcode_rd(&self) -> (u16, Opcode, bool) {
         (self.id, self.opcode, self.rd)
     }
-    pub fn new(id: u16, qr: bool, opcode: u8, aa: bool, tc: bool, rd: bool, ra: bool, reserved: u8, r_code: u8, qd_count: u16, an_count: u16, ns_count: u16, ar_count: u16) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(id: u16, qr: bool, opcode: Opcode, aa: bool, tc: bool, rd: bool, ra: bool, z: bool, ad: bool, cd: bool, r_code: Rcode, qd_count: u16, an_count: u16, ns_count: u16, ar_count: u16) -> Self {
         Self {
             id,
             qr,
@@ -63,7 +96,9 @@ impl Header {
             tc,
             rd,
             ra,
-            reserved,
+            z,
+            ad,
+            cd,
             r_code,
             qd_count,
             an_count,
@@ -79,19 +114,29 @@ impl Header {
     pub fn increment_an_count(&mut self) {
         self.an_count += 1;
     }
-    pub fn deserialize(v: &[u8]) -> anyhow::Result<Self> {
-        if v.len() != 12 {
-            bail!("headers len should be exactly 12 bytes long")
+
+    pub fn increment_ns_count(&mut self) {
+        self.ns_count += 1;
+    }
+
+    pub fn increment_ar_count(&mut self) {
+        self.ar_count += 1;
+    }
+    pub fn deserialize(v: &[u8]) -> Result<Self, ParseError> {
+        if v.len() < 12 {
+            return Err(ParseError::Truncated { offset: v.len() })
         };
         let id = u16::from_be_bytes([v[0], v[1]]);
         let qr = v[2] >> 7 == 1;
-        let opcode = v[2] >> 3 & 0b00001111;
+        let opcode = Opcode::from(v[2] >> 3 & 0b0000_1111);
         let aa = v[2] >> 2 & 1 == 1;
         let tc = v[2] >> 1 & 1 == 1;
         let rd = v[2] & 1 == 1;
         let ra = v[3] >> 7 == 1;
-        let reserved = v[3] >> 4 & 0b00000_111;
-        let r_code = v[3] & 0b0000_1111;
+        let z = v[3] >> 6 & 1 == 1;
+        let ad = v[3] >> 5 & 1 == 1;
+        let cd = v[3] >> 4 & 1 == 1;
+        let r_code = Rcode::from((v[3] & 0b0000_1111) as u16);
         let qd_count = u16::from_be_bytes([v[4], v[5]]);
         let an_count = u16::from_be_bytes([v[6], v[7]]);
         let ns_count = u16::from_be_bytes([v[8], v[9]]);
@@ -104,7 +149,9 @@ impl Header {
             tc,
             rd,
             ra,
-            reserved,
+            z,
+            ad,
+            cd,
             r_code,
             qd_count,
             an_count,
@@ -116,14 +163,17 @@ impl Header {
     pub fn serialize(self) -> anyhow::Result<BytesMut> {
         let mut buffer = BytesMut::with_capacity(12);
         buffer.put_u16(self.id);
-        let third_bite = 0u8
-            | ((self.qr as u8) << 7)
-            | (self.opcode << 3)
+        let third_bite = ((self.qr as u8) << 7)
+            | ((u8::from(self.opcode) & 0b0000_1111) << 3)
             | ((self.aa as u8) << 2)
             | ((self.tc as u8) << 1)
             | self.rd as u8;
         buffer.put_u8(third_bite);
-        let fourth_bite = 0u8 | ((self.ra as u8) << 7) | self.reserved << 4 | self.r_code;
+        let fourth_bite = ((self.ra as u8) << 7)
+            | ((self.z as u8) << 6)
+            | ((self.ad as u8) << 5)
+            | ((self.cd as u8) << 4)
+            | (u16::from(self.r_code) as u8 & 0b0000_1111);
         buffer.put_u8(fourth_bite);
         buffer.put_u16(self.qd_count);
         buffer.put_u16(self.an_count);
@@ -133,10 +183,153 @@ impl Header {
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash)]
+pub enum Opcode {
+    Query,
+    IQuery,
+    Status,
+    Notify,
+    Update,
+    DSO,
+    Unknown(u8),
+}
+
+impl From<u8> for Opcode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Query,
+            1 => Self::IQuery,
+            2 => Self::Status,
+            4 => Self::Notify,
+            5 => Self::Update,
+            6 => Self::DSO,
+            _ => Self::Unknown(value),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(value: Opcode) -> Self {
+        match value {
+            Opcode::Query => 0,
+            Opcode::IQuery => 1,
+            Opcode::Status => 2,
+            Opcode::Notify => 4,
+            Opcode::Update => 5,
+            Opcode::DSO => 6,
+            Opcode::Unknown(v) => v,
+        }
+    }
+}
+
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Opcode::Unknown(v) => write!(f, "OPCODE{}", v),
+            known => write!(f, "{}", format!("{:?}", known).to_uppercase()),
+        }
+    }
+}
+
+/// Response codes, including the extended ones that need the OPT record's
+/// upper 8 bits (RFC 6895 section 2.3).
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash)]
+pub enum Rcode {
+    NoError,
+    FormErr,
+    ServFail,
+    NXDomain,
+    NotImp,
+    Refused,
+    YXDomain,
+    YXRRSet,
+    NXRRSet,
+    NotAuth,
+    NotZone,
+    DSOTypeNI,
+    BadVers,
+    BadKey,
+    BadTime,
+    BadMode,
+    BadName,
+    BadAlg,
+    BadTrunc,
+    BadCookie,
+    Unknown(u16),
+}
+
+impl From<u16> for Rcode {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => Self::NoError,
+            1 => Self::FormErr,
+            2 => Self::ServFail,
+            3 => Self::NXDomain,
+            4 => Self::NotImp,
+            5 => Self::Refused,
+            6 => Self::YXDomain,
+            7 => Self::YXRRSet,
+            8 => Self::NXRRSet,
+            9 => Self::NotAuth,
+            10 => Self::NotZone,
+            11 => Self::DSOTypeNI,
+            16 => Self::BadVers,
+            17 => Self::BadKey,
+            18 => Self::BadTime,
+            19 => Self::BadMode,
+            20 => Self::BadName,
+            21 => Self::BadAlg,
+            22 => Self::BadTrunc,
+            23 => Self::BadCookie,
+            _ => Self::Unknown(value),
+        }
+    }
+}
+
+impl From<Rcode> for u16 {
+    fn from(value: Rcode) -> Self {
+        match value {
+            Rcode::NoError => 0,
+            Rcode::FormErr => 1,
+            Rcode::ServFail => 2,
+            Rcode::NXDomain => 3,
+            Rcode::NotImp => 4,
+            Rcode::Refused => 5,
+            Rcode::YXDomain => 6,
+            Rcode::YXRRSet => 7,
+            Rcode::NXRRSet => 8,
+            Rcode::NotAuth => 9,
+            Rcode::NotZone => 10,
+            Rcode::DSOTypeNI => 11,
+            Rcode::BadVers => 16,
+            Rcode::BadKey => 17,
+            Rcode::BadTime => 18,
+            Rcode::BadMode => 19,
+            Rcode::BadName => 20,
+            Rcode::BadAlg => 21,
+            Rcode::BadTrunc => 22,
+            Rcode::BadCookie => 23,
+            Rcode::Unknown(v) => v,
+        }
+    }
+}
+
+/// Mnemonic as printed by `dig`, e.g. `NXDOMAIN`.
+impl std::fmt::Display for Rcode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rcode::Unknown(v) => write!(f, "RCODE{}", v),
+            known => write!(f, "{}", format!("{:?}", known).to_uppercase()),
+        }
+    }
+}
 
 #[cfg(test)]
+#[allow(clippy::unusual_byte_groupings, clippy::identity_op)]
 mod tests {
-    use crate::header::Header;
+    use crate::error::ParseError;
+    use crate::header::{Header, Opcode, Rcode};
 
     #[test]
     fn byte_shift() {
@@ -235,4 +428,39 @@ mod tests {
         assert_eq!(bytes, result);
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn short_header_is_truncated() {
+        let bytes = [0u8; 7];
+        assert_eq!(Header::deserialize(&bytes), Err(ParseError::Truncated { offset: 7 }));
+    }
+
+    #[test]
+    fn opcode_and_rcode_bits() {
+        let mut header = Header::default();
+        header.set_opcode(Opcode::Notify);
+        header.set_r_code(Rcode::NXDomain);
+        let bytes = header.clone().serialize().unwrap();
+        assert_eq!(bytes[2] >> 3 & 0b1111, 4);
+        assert_eq!(bytes[3] & 0b1111, 3);
+        assert_eq!(Header::deserialize(&bytes), Ok(header));
+        assert_eq!(Opcode::from(3), Opcode::Unknown(3));
+        assert_eq!(Rcode::NXDomain.to_string(), "NXDOMAIN");
+        assert_eq!(Rcode::from(16), Rcode::BadVers);
+    }
+
+    #[test]
+    fn z_ad_cd_bits() {
+        let mut header = Header::default();
+        header.set_ad(true);
+        header.set_cd(true);
+        let bytes = header.clone().serialize().unwrap();
+        assert_eq!(bytes[3] & 0b0111_0000, 0b0011_0000);
+        let de = Header::deserialize(&bytes).unwrap();
+        assert!(de.ad && de.cd && !de.z);
+        let mut bytes = bytes.to_vec();
+        bytes[3] = 0b0100_0000;
+        let de = Header::deserialize(&bytes).unwrap();
+        assert!(de.z && !de.ad && !de.cd);
+    }
+}
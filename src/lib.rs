@@ -0,0 +1,28 @@
+//! A DNS server: authoritative for the zones it loads, and a forwarder or
+//! recursive resolver for everything else. The `dnsq` client shares its
+//! message codec.
+
+#[macro_use]
+pub mod log;
+pub mod error;
+pub mod header;
+pub mod question;
+pub mod message;
+pub mod answer;
+pub mod cli;
+pub mod edns;
+pub mod cache;
+pub mod server;
+pub mod recursor;
+pub mod pool;
+pub mod upstream;
+pub mod zone;
+pub mod crypto;
+pub mod encoding;
+pub mod dnssec;
+pub mod validator;
+pub mod signer;
+pub mod toml;
+pub mod acl;
+pub mod config;
+pub mod signal;
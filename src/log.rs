@@ -0,0 +1,96 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use anyhow::anyhow;
+
+/// How much we log, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    /// Every message we receive and send.
+    Debug,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Changes the level for every thread; safe to call while serving.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+impl FromStr for Level {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(anyhow!("unknown log level {:?}, expected error, warn, info or debug", s)),
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        })
+    }
+}
+
+/// Problems go to stderr, everything else to stdout.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::log::enabled($level) {
+            match $level <= $crate::log::Level::Warn {
+                true => eprintln!($($arg)*),
+                false => println!($($arg)*),
+            }
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Error, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Warn, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Info, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Debug, $($arg)*) };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::log::Level;
+
+    #[test]
+    fn parses_levels() {
+        assert_eq!("WARN".parse::<Level>().unwrap(), Level::Warn);
+        assert_eq!(Level::Debug.to_string(), "debug");
+        assert!(Level::Error < Level::Debug);
+        assert!("verbose".parse::<Level>().is_err());
+    }
+}
@@ -1,70 +1,150 @@
 // Uncomment this block to pass the first stage
-use std::net::{ SocketAddrV4, UdpSocket};
+use std::net::{TcpListener, UdpSocket};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use anyhow::Context;
 use clap::Parser;
-use crate::cli::Args;
-use crate::message::{Answers, Message};
+use dns_starter_rust::cache::Cache;
+use dns_starter_rust::cli::Args;
+use dns_starter_rust::config::{Config, ServerConfig};
+use dns_starter_rust::pool::ThreadPool;
+use dns_starter_rust::recursor::Recursor;
+use dns_starter_rust::server::{self, Handler, Mode, Service};
+use dns_starter_rust::signer::SigningKey;
+use dns_starter_rust::upstream::Upstreams;
+use dns_starter_rust::validator::Validator;
+use dns_starter_rust::zone::Catalog;
+use dns_starter_rust::{error, info, log, signal, warn};
 
-mod header;
-mod question;
-mod message;
-mod answer;
-mod cli;
-
-fn forwarding_server(udp_socket: &UdpSocket, message: Message, socket_addr: SocketAddrV4) -> Message {
-    Message::join(message.split().into_iter().map(|m|
-        {
-            println!("forwarding message : {:?}", m);
-            let s = m.serialize();
-            udp_socket.send_to(&s, socket_addr).expect("Failed to send response");
-            let mut buf = [0; 512];
-            let (n, _) = udp_socket.recv_from(&mut buf).expect("Failed to recv response");
-            Message::deserialize(&buf[..n])
-        }
-    ).collect::<Vec<_>>())
-
-}
+/// How often we check whether a SIGHUP asked us to reload.
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
 fn main() {
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     println!("Logs from your program will appear here!");
     let args = Args::parse();
-    let is_forwarded_server = args.resolver.is_some();
+    let config = Config::from_args(&args).unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {:#}", e);
+        process::exit(2)
+    });
+    log::set_level(config.log_level);
+    let resolvers = Arc::new(ThreadPool::new("resolve", server::RESOLVERS, server::RESOLVER_QUEUE));
+    let modes = config.server.listeners.iter().map(|l| config.mode(l)).collect::<Vec<_>>();
+    let handler = build_handler(&config, &modes, resolvers).unwrap_or_else(|e| {
+        error!("{:#}", e);
+        process::exit(1)
+    });
+    let service = Arc::new(Service::new(handler));
+
+    signal::catch_hangup();
+    let (reloaded, running) = (service.clone(), config.server.clone());
+    thread::spawn(move || loop {
+        thread::sleep(RELOAD_POLL_INTERVAL);
+        if signal::take_hangup() {
+            reload(&args, &running, &modes, &reloaded);
+        }
+    });
 
     // Uncomment this block to pass the first stage
-    let udp_socket = UdpSocket::bind("127.0.0.1:2053").expect("Failed to bind to address");
-    let mut buf = [0; 512];
+    let server = &config.server;
+    let udp_pool = Arc::new(ThreadPool::new("udp", server.workers, server.queue_size));
+    let tcp_pool = Arc::new(ThreadPool::new("tcp", server.max_tcp_connections, server.max_tcp_connections));
+    let mut listeners = vec![];
+    for listener in &server.listeners {
+        let (address, mode) = (listener.address, config.mode(listener));
+        if listener.udp {
+            let udp_socket = UdpSocket::bind(address)
+                .unwrap_or_else(|e| panic!("Failed to bind to {} over UDP: {}", address, e));
+            let (service, pool) = (service.clone(), udp_pool.clone());
+            listeners.push(thread::spawn(move || server::serve_udp(udp_socket, service, mode, &pool)));
+        }
+        if listener.tcp {
+            let tcp_listener = TcpListener::bind(address)
+                .unwrap_or_else(|e| panic!("Failed to bind to {} over TCP: {}", address, e));
+            let (service, pool, idle_timeout) = (service.clone(), tcp_pool.clone(), server.tcp_idle_timeout);
+            listeners.push(thread::spawn(move || server::serve_tcp(tcp_listener, service, mode, idle_timeout, &pool)));
+        }
+        info!("Listening on {} ({})", address, mode);
+    }
+    for listener in listeners {
+        let _ = listener.join();
+    }
+}
 
-    loop {
-        match udp_socket.recv_from(&mut buf) {
-            Ok((size, source)) => {
-                println!("Received {} bytes from {}", size, source);
+/// Builds everything that answers queries, which is all of `config` but
+/// the listeners and the server section, for listeners running in `modes`.
+fn build_handler(config: &Config, modes: &[Mode], resolvers: Arc<ThreadPool>) -> anyhow::Result<Handler> {
+    let keys = config
+        .signing
+        .keys
+        .iter()
+        .map(|path| SigningKey::load(path))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .context("Failed to load signing keys")?;
+    let catalog = Catalog::load(&config.zones)
+        .and_then(|catalog| catalog.sign(keys, config.signing.validity))
+        .context("Failed to load zones")?;
+    for ds in catalog.ds_records() {
+        info!("DS for the parent zone: {}", ds);
+    }
+    let cache = Cache::new(config.cache.entries, config.cache.bytes);
+    let upstream = &config.upstream;
+    let upstreams = (!upstream.servers.is_empty()).then(|| {
+        Upstreams::new(upstream.servers.clone())
+            .with_timeout(upstream.timeout)
+            .with_attempts(upstream.attempts)
+            .with_health(upstream.max_failures, upstream.probe_interval)
+    });
+    let mut handler = Handler::new(upstreams, resolvers)
+        .with_catalog(catalog)
+        .with_cache(cache)
+        .with_acl(config.acl.clone())
+        .with_deadline(upstream.deadline, upstream.partial_failure);
+    if modes.contains(&Mode::Recursive) {
+        let recursor = match &config.recursion.root_hints {
+            Some(path) => Recursor::from_hints(path).context("Failed to load root hints")?,
+            None => Recursor::default(),
+        };
+        handler = handler.with_recursor(recursor
+            .with_port(config.recursion.nameserver_port)
+            .with_timeout(upstream.timeout));
+    }
+    if config.validation.enabled {
+        let validator = match &config.validation.trust_anchor {
+            Some(path) => Validator::from_file(path).context("Failed to load trust anchors")?,
+            None => Validator::default(),
+        };
+        handler = handler.with_validator(validator);
+    }
+    if !config.signing.keys.is_empty() {
+        let catalog = Arc::downgrade(&handler.catalog());
+        thread::spawn(move || Catalog::keep_signed(catalog));
+    }
+    Ok(handler)
+}
 
-                let message = Message::deserialize(&buf[..size]);
-                println!("Recvd message : {:?}", message);
-                let response = if is_forwarded_server {
-                    let m = forwarding_server(&udp_socket, message, args.resolver.unwrap());
-                    println!("response message : {:?}", m);
-                    m.serialize()
-                } else {
-                    let (id, opcode, rd) = (message.id(), message.opcode(), message.rd());
-                    let m = message::MessageBuilder::new()
-                        .set_id(id)
-                        .set_opcode(opcode)
-                        .set_rd(rd)
-                        .add_answers(Answers::from_questions(&message.questions))
-                        .add_questions(message.questions)
-                        .finish();
-                    let response = m.serialize();
-                    response
-                };
-                udp_socket
-                    .send_to(&response, source)
-                    .expect("Failed to send response");
-            }
-            Err(e) => {
-                eprintln!("Error receiving data: {}", e);
-                break;
-            }
+/// Re-reads the configuration and swaps in a handler built from it for the
+/// listeners, which keep running in `modes`. On any error we keep serving
+/// with the old one.
+fn reload(args: &Args, server: &ServerConfig, modes: &[Mode], service: &Service) {
+    info!("Reloading configuration");
+    let built = Config::from_args(args).and_then(|config| {
+        config.supports(modes)?;
+        build_handler(&config, modes, service.resolvers()).map(|handler| (handler, config))
+    });
+    let (handler, config) = match built {
+        Ok(built) => built,
+        Err(e) => {
+            error!("Keeping the old configuration: {:#}", e);
+            return
         }
+    };
+    if config.server != *server {
+        warn!("Changes to listeners and the server section take effect on restart");
     }
+    log::set_level(config.log_level);
+    service.replace(handler);
+    info!("Configuration reloaded");
 }
@@ -1,10 +1,15 @@
 
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
 use anyhow::bail;
 use bytes::{BufMut, BytesMut};
 use nom::AsBytes;
 use crate::answer::Answer;
-use crate::header::Header;
+use crate::edns::Edns;
+use crate::error::{read_u16, read_u8, ParseError};
+use crate::header::{Header, Opcode, Rcode};
 use crate::question::Question;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -12,6 +17,10 @@ pub struct Message {
     header: Header,
     pub(crate) questions: Questions,
     pub(crate) answers: Answers,
+    pub(crate) authorities: Answers,
+    pub(crate) additionals: Answers,
+    /// OPT pseudo-record, kept out of `additionals` but counted in `ar_count`.
+    pub(crate) edns: Option<Edns>,
 }
 
 impl Default for Message {
@@ -19,7 +28,10 @@ impl Default for Message {
         let header = Header::default();
         Self {header,
             questions: Default::default(),
-            answers: Default::default()}
+            answers: Default::default(),
+            authorities: Default::default(),
+            additionals: Default::default(),
+            edns: None}
     }
 }
 
@@ -30,71 +42,159 @@ impl Message {
             header,
             questions: Questions(questions),
             answers: Answers(answers),
+            authorities: Default::default(),
+            additionals: Default::default(),
+            edns: None,
         }
     }
 
     pub fn serialize(self) -> BytesMut {
-        let (mut header, question, answer) = (
-            self.header.serialize().unwrap(),
-            self.questions.serialize(),
-            self.answers.serialize()
-        );
-        header.extend_from_slice(&question);
-        header.extend_from_slice(&answer);
-        header
+        self.serialize_with(true)
     }
 
-    pub fn deserialize(bytes: &[u8]) -> Self {
-        let header = Header::deserialize(&bytes[..12]).unwrap();
+    /// Serializes the message, sharing one compression table across all
+    /// sections. Pass `compress = false` for canonical (DNSSEC) output.
+    pub fn serialize_with(self, compress: bool) -> BytesMut {
+        let mut table = if compress { Compression::default() } else { Compression::disabled() };
+        let mut buf = self.header.serialize().unwrap();
+        self.questions.write(&mut buf, &mut table);
+        self.answers.write(&mut buf, &mut table);
+        self.authorities.write(&mut buf, &mut table);
+        self.additionals.write(&mut buf, &mut table);
+        if let Some(edns) = &self.edns {
+            edns.to_record().write(&mut buf, &mut table);
+        }
+        buf
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, ParseError> {
+        let header = Header::deserialize(bytes)?;
         let (qn, an) = (header.qd(), header.an());
         let mut start = 12usize;
         let mut question = Questions::default();
-        // println!("{:?}", header);
         for _ in 0..qn {
-            let (q, end) = Question::deserialize(bytes, start);
-            // println!("{:#?} \n end is {end}", q);
+            let (q, end) = Question::deserialize(bytes, start)?;
             start = end;
             question.push(q);
         }
         let mut answer = Answers::default();
         for _ in 0..an {
-            let (a, end) = Answer::deserialize(bytes, start);
-            start += end;
+            let (a, end) = Answer::deserialize(bytes, start)?;
+            start = end;
             answer.push(a);
         }
+        let mut authority = Answers::default();
+        for _ in 0..header.ns() {
+            let (a, end) = Answer::deserialize(bytes, start)?;
+            start = end;
+            authority.push(a);
+        }
+        let mut additional = Answers::default();
+        let mut edns = None;
+        for _ in 0..header.ar() {
+            let (a, end) = Answer::deserialize(bytes, start)?;
+            if a.ty() == Ty::OPT {
+                if edns.is_some() {
+                    return Err(ParseError::MultipleOpt { offset: start })
+                }
+                edns = Some(Edns::from_record(&a)?);
+            } else {
+                additional.push(a);
+            }
+            start = end;
+        }
 
-        Self {
+        Ok(Self {
             header,
             questions: question,
             answers: answer,
+            authorities: authority,
+            additionals: additional,
+            edns,
+        })
+    }
+
+    /// Builds a FORMERR response for a packet that could not be decoded,
+    /// echoing whatever part of the original header and question section is
+    /// still readable.
+    pub fn format_error(bytes: &[u8]) -> Self {
+        let mut builder = MessageBuilder::new().set_rcode(Rcode::FormErr);
+        if let Some(id) = bytes.get(..2) {
+            builder = builder.set_id(u16::from_be_bytes([id[0], id[1]]));
+        }
+        if let Some(flags) = bytes.get(2) {
+            builder = builder
+                .set_opcode(Opcode::from(flags >> 3 & 0b1111))
+                .set_rd(flags & 1 == 1);
         }
+        let Ok(header) = Header::deserialize(bytes) else {
+            return builder.finish()
+        };
+        let mut start = 12;
+        for _ in 0..header.qd() {
+            let Ok((question, end)) = Question::deserialize(bytes, start) else {
+                break
+            };
+            builder = builder.add_question(question);
+            start = end;
+        }
+        builder.finish()
     }
 
     pub fn split(self) -> Vec<Self> {
         self.questions.into_iter().map(|i| {
-            println!("question : {:?}", i);
+            debug!("question : {:?}", i);
             let mut m = Message::new(self.header.clone(), vec![i], vec![]);
             m.header.qd_count = 1;
             m.header.an_count = 0;
+            m.header.ns_count = 0;
+            m.header.ar_count = self.edns.is_some() as u16;
+            m.edns = self.edns.clone();
             m
         }).collect()
     }
-    pub fn join(v: Vec<Self>) -> Self {
-        let mut header = v[0].header.clone();
+    /// Joins the responses to the questions of a split query, in order. The
+    /// header is the first response's with the flags and RCODE merged across
+    /// all of them: the worst RCODE wins, AA, RA and AD hold only if every
+    /// response has them, and TC is set if any response was truncated.
+    /// There is no header to start from without a response.
+    pub fn join(v: Vec<Self>) -> anyhow::Result<Self> {
+        let Some(first) = v.first() else {
+            bail!("no responses to join")
+        };
+        let mut header = first.header.clone();
         header.an_count = 0;
         header.qd_count = 0;
-        MessageBuilder::new()
+        header.ns_count = 0;
+        header.ar_count = 0;
+        header.aa = v.iter().all(Message::aa);
+        header.ra = v.iter().all(Message::ra);
+        header.ad = v.iter().all(Message::ad);
+        header.tc = v.iter().any(Message::tc);
+        let rcode = v.iter().map(Message::rcode).max_by_key(|&rcode| severity(rcode)).unwrap_or(Rcode::NoError);
+        let mut builder = MessageBuilder::new()
             .set_header(header)
             .add_answers(v.iter().flat_map(|m| m.answers.clone()))
             .add_questions(v.iter().flat_map(|m| m.questions.clone()))
-            .finish()
+            .add_authorities(v.iter().flat_map(|m| m.authorities.clone()))
+            .add_additionals(v.iter().flat_map(|m| m.additionals.clone()));
+        if let Some(edns) = first.edns.clone() {
+            builder = builder.set_edns(edns);
+        }
+        let mut message = builder.finish();
+        message.set_rcode(rcode);
+        Ok(message)
+    }
 
+    pub fn qr(&self) -> bool {
+        self.header.qr
     }
+
     pub fn id(&self) -> u16 {
         self.header.id
     }
 
-    pub fn opcode(&self) -> u8{
+    pub fn opcode(&self) -> Opcode {
         self.header.opcode
     }
 
@@ -102,6 +202,145 @@ impl Message {
         self.header.rd
     }
 
+    pub fn tc(&self) -> bool {
+        self.header.tc
+    }
+
+    pub fn aa(&self) -> bool {
+        self.header.aa
+    }
+
+    pub fn ra(&self) -> bool {
+        self.header.ra
+    }
+
+    pub fn ad(&self) -> bool {
+        self.header.ad
+    }
+
+    pub fn cd(&self) -> bool {
+        self.header.cd
+    }
+
+    pub fn edns(&self) -> Option<&Edns> {
+        self.edns.as_ref()
+    }
+
+    pub fn questions(&self) -> &[Question] {
+        &self.questions
+    }
+
+    pub fn answers(&self) -> &[Answer] {
+        &self.answers
+    }
+
+    pub fn authorities(&self) -> &[Answer] {
+        &self.authorities
+    }
+
+    /// Additional records, without the OPT pseudo-record.
+    pub fn additionals(&self) -> &[Answer] {
+        &self.additionals
+    }
+
+    /// Full 12-bit RCODE: the header's 4 bits extended by the OPT record.
+    pub fn rcode(&self) -> Rcode {
+        let high = self.edns.as_ref().map_or(0, |e| e.extended_rcode as u16);
+        Rcode::from(high << 4 | u16::from(self.header.r_code))
+    }
+
+    /// Splits `rcode` between the header and the OPT record. Codes above 15
+    /// need EDNS, so an OPT record is added if the message has none.
+    pub fn set_rcode(&mut self, rcode: Rcode) {
+        let rcode = u16::from(rcode);
+        self.header.r_code = Rcode::from(rcode & 0b1111);
+        let high = (rcode >> 4) as u8;
+        match &mut self.edns {
+            Some(edns) => edns.extended_rcode = high,
+            None if high != 0 => {
+                self.edns = Some(Edns { extended_rcode: high, ..Default::default() });
+                self.header.increment_ar_count();
+            }
+            None => {}
+        }
+    }
+
+    /// Drops the records of every section that `keep` rejects.
+    pub fn retain(&mut self, keep: impl Fn(&Answer) -> bool) {
+        self.answers.retain(&keep);
+        self.authorities.retain(&keep);
+        self.additionals.retain(&keep);
+        self.header.an_count = self.answers.len() as u16;
+        self.header.ns_count = self.authorities.len() as u16;
+        self.header.ar_count = (self.additionals.len() + self.edns.is_some() as usize) as u16;
+    }
+
+    /// Largest UDP response the client said it can take.
+    pub fn udp_payload_size(&self) -> usize {
+        self.edns.as_ref().map_or(512, |e| e.payload_size.max(512) as usize)
+    }
+
+    /// Serializes the message into at most `limit` bytes, dropping whole
+    /// RRsets from the end. Losing additional data is fine, but once an
+    /// answer or authority RRset has to go TC is set so the client retries
+    /// over TCP. If not even the question fits, only the header is sent.
+    ///
+    /// Compression pointers only ever point back, so the message is written
+    /// once and cut at the last RRset boundary that leaves room for the OPT
+    /// record.
+    pub fn serialize_truncated(self, limit: usize) -> BytesMut {
+        let mut table = Compression::default();
+        let mut buf = self.header.clone().serialize().unwrap();
+        self.questions.write(&mut buf, &mut table);
+        // where we may cut, and how many records of each section are kept
+        let mut cuts = vec![(buf.len(), [0; 3])];
+        let sections = [&self.answers, &self.authorities, &self.additionals];
+        for (section, records) in sections.iter().enumerate() {
+            let last = records.iter().enumerate().map(|(i, r)| (r.rrset_key(), i)).collect::<HashMap<_, _>>();
+            let mut rrset_end = 0;
+            for (i, record) in records.iter().enumerate() {
+                record.write(&mut buf, &mut table);
+                // no RRset may straddle the cut, even one split up in the section
+                rrset_end = rrset_end.max(last[&record.rrset_key()]);
+                if rrset_end == i {
+                    let mut kept = cuts[cuts.len() - 1].1;
+                    kept[section] = i + 1;
+                    cuts.push((buf.len(), kept));
+                }
+            }
+        }
+        let mut opt = BytesMut::new();
+        if let Some(edns) = &self.edns {
+            edns.to_record().write(&mut opt, &mut Compression::default());
+        }
+        if buf.len() + opt.len() <= limit {
+            buf.extend_from_slice(&opt);
+            return buf
+        }
+
+        let mut header = self.header;
+        let (end, kept) = match cuts.iter().rev().find(|(end, _)| end + opt.len() <= limit) {
+            Some(&cut) => cut,
+            None => {
+                header.qd_count = 0;
+                (12, [0; 3])
+            }
+        };
+        let with_opt = self.edns.is_some() && end + opt.len() <= limit;
+        header.tc |= header.qd_count == 0 && !self.questions.is_empty()
+            || kept[0] < self.answers.len()
+            || kept[1] < self.authorities.len();
+        header.an_count = kept[0] as u16;
+        header.ns_count = kept[1] as u16;
+        header.ar_count = (kept[2] + with_opt as usize) as u16;
+        buf.truncate(end);
+        buf[..12].copy_from_slice(&header.serialize().unwrap());
+        if with_opt {
+            buf.extend_from_slice(&opt);
+        }
+        buf
+    }
+
 }
 
 
@@ -140,8 +379,8 @@ impl Answers {
     pub fn from_questions<'a,Q: IntoIterator<Item = &'a Question>>(questions: Q) -> Self {
         questions.into_iter().map(|q| Answer::from_domain_name(&q.domain())).collect()
     }
-    pub fn serialize(self) -> BytesMut {
-        self.0.into_iter().flat_map(|a| a.serialize()).collect()
+    pub fn write(&self, buf: &mut BytesMut, table: &mut Compression) {
+        self.0.iter().for_each(|a| a.write(buf, table))
     }
 }
 
@@ -181,14 +420,32 @@ impl IntoIterator for Questions {
 }
 
 impl Questions {
-    pub fn serialize(self) -> BytesMut {
-        self.0.into_iter().flat_map(|q| q.serialize()).collect()
+    pub fn write(&self, buf: &mut BytesMut, table: &mut Compression) {
+        self.0.iter().for_each(|q| q.write(buf, table))
     }
 }
 
+#[derive(Default)]
 pub struct MessageBuilder {
     message: Message
 }
+
+impl From<Message> for MessageBuilder {
+    fn from(message: Message) -> Self {
+        Self { message }
+    }
+}
+
+/// How bad an RCODE is when joining responses: success, then a name that
+/// does not exist, then any other error, higher codes first.
+fn severity(rcode: Rcode) -> (u8, u16) {
+    match rcode {
+        Rcode::NoError => (0, 0),
+        Rcode::NXDomain => (1, 0),
+        other => (2, u16::from(other)),
+    }
+}
+
 #[allow(unused)]
 impl MessageBuilder {
     pub fn new() -> Self {
@@ -198,22 +455,77 @@ impl MessageBuilder {
         self.message.header.set_id(id);
         self
     }
-    pub fn set_opcode(mut self, opcode: u8) -> Self {
+    /// Starts a response to `query`: same ID, opcode, RD, CD and question.
+    /// The RCODE is left at NOERROR for the caller to decide.
+    pub fn response_to(query: &Message) -> Self {
+        let mut header = Header { qr: true, ..Default::default() };
+        header.set_id(query.id());
+        header.set_opcode(query.opcode());
+        header.set_rd(query.rd());
+        header.set_cd(query.cd());
+        Self::new()
+            .set_header(header)
+            .add_questions(query.questions.iter().cloned())
+    }
+
+    pub fn set_opcode(mut self, opcode: Opcode) -> Self {
         self.message.header.set_opcode(opcode);
         self
     }
 
+    pub fn set_aa(mut self, aa: bool) -> Self {
+        self.message.header.set_aa(aa);
+        self
+    }
+
+    pub fn set_qr(mut self, qr: bool) -> Self {
+        self.message.header.set_qr(qr);
+        self
+    }
+
     pub fn set_rd(mut self, rd: bool) -> Self {
         self.message.header.set_rd(rd);
         self
     }
 
+    pub fn set_ra(mut self, ra: bool) -> Self {
+        self.message.header.set_ra(ra);
+        self
+    }
+
+    pub fn set_ad(mut self, ad: bool) -> Self {
+        self.message.header.set_ad(ad);
+        self
+    }
+
+    pub fn set_cd(mut self, cd: bool) -> Self {
+        self.message.header.set_cd(cd);
+        self
+    }
+
+    pub fn set_rcode(mut self, rcode: Rcode) -> Self {
+        self.message.set_rcode(rcode);
+        self
+    }
+
     pub fn add_answer(mut self, answer: Answer) -> Self {
         self.message.answers.push(answer);
         self.message.header.increment_an_count();
         self
     }
 
+    pub fn add_authority(mut self, authority: Answer) -> Self {
+        self.message.authorities.push(authority);
+        self.message.header.increment_ns_count();
+        self
+    }
+
+    pub fn add_additional(mut self, additional: Answer) -> Self {
+        self.message.additionals.push(additional);
+        self.message.header.increment_ar_count();
+        self
+    }
+
     pub fn add_question(mut self, question: Question) -> Self {
         self.message.questions.push(question);
         self.message.header.increment_qd_count();
@@ -240,6 +552,36 @@ impl MessageBuilder {
         }
         self
     }
+    pub fn add_authorities<I: IntoIterator<Item = Answer>>(mut self, iter: I) -> Self {
+        for a in iter {
+            self.message.authorities.push(a);
+            self.message.header.increment_ns_count();
+        }
+        self
+    }
+
+    pub fn add_additionals<I: IntoIterator<Item = Answer>>(mut self, iter: I) -> Self {
+        for a in iter {
+            self.message.additionals.push(a);
+            self.message.header.increment_ar_count();
+        }
+        self
+    }
+    /// Attaches an OPT record, replacing any previous one.
+    pub fn set_edns(mut self, edns: Edns) -> Self {
+        if self.message.edns.replace(edns).is_none() {
+            self.message.header.increment_ar_count();
+        }
+        self
+    }
+
+    pub fn clear_edns(mut self) -> Self {
+        if self.message.edns.take().is_some() {
+            self.message.header.ar_count -= 1;
+        }
+        self
+    }
+
     pub fn finish(self) -> Message {
         self.message
     }
@@ -247,63 +589,183 @@ impl MessageBuilder {
 
 
 
-#[repr(u16)]
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Class {
-    IN = 1,
-    CS = 2,
-    CH = 3,
-    HS = 4,
+    IN,
+    CS,
+    CH,
+    HS,
+    Unknown(u16),
 }
 
-impl TryFrom<u16> for Class {
-    type Error = anyhow::Error;
+impl Class {
+    const KNOWN: [Class; 4] = [Class::IN, Class::CS, Class::CH, Class::HS];
+}
 
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
+impl From<u16> for Class {
+    fn from(value: u16) -> Self {
         match value {
-            1 => Ok(Self::IN),
-            2 => Ok(Self::CS),
-            3 => Ok(Self::CH),
-            4 => Ok(Self::HS),
-            _ => bail!("can construct enum variants only from u16 from 1 to 4")
+            1 => Self::IN,
+            2 => Self::CS,
+            3 => Self::CH,
+            4 => Self::HS,
+            _ => Self::Unknown(value),
         }
     }
 }
 
-#[repr(u16)]
-#[derive(Debug, PartialEq, Eq, Clone)]
+impl From<Class> for u16 {
+    fn from(value: Class) -> Self {
+        match value {
+            Class::IN => 1,
+            Class::CS => 2,
+            Class::CH => 3,
+            Class::HS => 4,
+            Class::Unknown(v) => v,
+        }
+    }
+}
+
+/// Mnemonic, or the RFC 3597 `CLASSnnn` form for classes we don't know.
+impl Display for Class {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Class::Unknown(v) => write!(f, "CLASS{}", v),
+            known => write!(f, "{:?}", known),
+        }
+    }
+}
+
+impl FromStr for Class {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(known) = Self::KNOWN.into_iter().find(|c| c.to_string().eq_ignore_ascii_case(s)) {
+            return Ok(known)
+        }
+        match s.get(..5) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("CLASS") => Ok(Self::from(s[5..].parse::<u16>()?)),
+            _ => bail!("unknown class {:?}", s),
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Ty {
-    A = 1,
-    NS = 2,
-    CNAME = 5,
-    SOA = 6,
-    WKS = 11,
-    PTR = 12,
-    HINFO = 13,
-    MINFO = 14,
-    MX = 15,
-    TXT = 16,
-}
-
-impl TryFrom<u16> for Ty {
-    type Error = anyhow::Error;
-
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
+    A,
+    NS,
+    CNAME,
+    SOA,
+    WKS,
+    PTR,
+    HINFO,
+    MINFO,
+    MX,
+    TXT,
+    AAAA,
+    SRV,
+    OPT,
+    DS,
+    RRSIG,
+    NSEC,
+    DNSKEY,
+    NSEC3,
+    NSEC3PARAM,
+    CAA,
+    Unknown(u16),
+}
+
+impl Ty {
+    /// QTYPE 255, which matches every record at a name.
+    pub const ANY: Ty = Ty::Unknown(255);
+    const KNOWN: [Ty; 20] = [
+        Ty::A, Ty::NS, Ty::CNAME, Ty::SOA, Ty::WKS, Ty::PTR, Ty::HINFO,
+        Ty::MINFO, Ty::MX, Ty::TXT, Ty::AAAA, Ty::SRV, Ty::OPT, Ty::DS,
+        Ty::RRSIG, Ty::NSEC, Ty::DNSKEY, Ty::NSEC3, Ty::NSEC3PARAM, Ty::CAA,
+    ];
+}
+
+impl From<u16> for Ty {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => Self::A,
+            2 => Self::NS,
+            5 => Self::CNAME,
+            6 => Self::SOA,
+            11 => Self::WKS,
+            12 => Self::PTR,
+            13 => Self::HINFO,
+            14 => Self::MINFO,
+            15 => Self::MX,
+            16 => Self::TXT,
+            28 => Self::AAAA,
+            33 => Self::SRV,
+            41 => Self::OPT,
+            43 => Self::DS,
+            46 => Self::RRSIG,
+            47 => Self::NSEC,
+            48 => Self::DNSKEY,
+            50 => Self::NSEC3,
+            51 => Self::NSEC3PARAM,
+            257 => Self::CAA,
+            _ => Self::Unknown(value),
+        }
+    }
+}
+
+impl From<Ty> for u16 {
+    fn from(value: Ty) -> Self {
         match value {
-            1 => Ok(Self::A),
-            2 => Ok(Self::NS),
-            5 => Ok(Self::CNAME),
-            6 => Ok(Self::SOA),
-            11 => Ok(Self::WKS),
-            12 => Ok(Self::PTR),
-            13 => Ok(Self::HINFO),
-            14 => Ok(Self::MINFO),
-            15 => Ok(Self::MX),
-            16 => Ok(Self::TXT),
-            _ => bail!("unexpected value")
+            Ty::A => 1,
+            Ty::NS => 2,
+            Ty::CNAME => 5,
+            Ty::SOA => 6,
+            Ty::WKS => 11,
+            Ty::PTR => 12,
+            Ty::HINFO => 13,
+            Ty::MINFO => 14,
+            Ty::MX => 15,
+            Ty::TXT => 16,
+            Ty::AAAA => 28,
+            Ty::SRV => 33,
+            Ty::OPT => 41,
+            Ty::DS => 43,
+            Ty::RRSIG => 46,
+            Ty::NSEC => 47,
+            Ty::DNSKEY => 48,
+            Ty::NSEC3 => 50,
+            Ty::NSEC3PARAM => 51,
+            Ty::CAA => 257,
+            Ty::Unknown(v) => v,
         }
     }
 }
+
+/// Mnemonic, or the RFC 3597 `TYPEnnn` form for types we don't know.
+impl Display for Ty {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ty::Unknown(v) => write!(f, "TYPE{}", v),
+            known => write!(f, "{:?}", known),
+        }
+    }
+}
+
+impl FromStr for Ty {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(known) = Self::KNOWN.into_iter().find(|t| t.to_string().eq_ignore_ascii_case(s)) {
+            return Ok(known)
+        }
+        match s.get(..4) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("TYPE") => Ok(Self::from(s[4..].parse::<u16>()?)),
+            _ => bail!("unknown record type {:?}", s),
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Label {
     val: BytesMut,
@@ -319,42 +781,55 @@ impl Deref for Labels {
 }
 
 impl Labels {
-    pub fn parse(buf: &[u8], mut start: usize) -> (Labels, usize) {
-        // println!("default start is {start}");
+    /// Upper bound on compression pointers followed while reading one name.
+    const MAX_POINTERS: usize = 16;
+    /// Longest name allowed on the wire, length octets included (RFC 1035 2.3.4).
+    const MAX_NAME_LEN: usize = 255;
+
+    /// Reads a possibly compressed name starting at `start` and returns it
+    /// together with the offset of the first byte after the name.
+    ///
+    /// A pointer has to point strictly before the run of labels it ends, so
+    /// every jump moves backwards and a crafted packet can't make us cycle.
+    pub fn parse(buf: &[u8], start: usize) -> Result<(Labels, usize), ParseError> {
         let mut v : Labels = Default::default();
+        let mut pos = start;
+        let mut segment = start;
+        let mut after_name = None;
+        let mut hops = 0;
+        let mut name_len = 1;
         loop {
-            // println!("start is {start}");
-            // println!("byte is {:<02x}", buf[start]);
-            match buf[start] {
-                zero if zero == b'\0' => {
-                    if let Some(byte) = buf.get(start + 1) {
-                      if *byte  == b'\0' {
-                          start += 1;
-                      }
-                    };
-                    break
-                }
+            match read_u8(buf, pos)? {
+                0 => break,
                 x if x & 0b1100_0000 == 0b1100_0000 => {
-                    let ptr = u16::from_be_bytes([
-                        buf[start],
-                        buf[start+1],
-                    ]);
-                    let ptr = ptr ^ 0b11_00_0000_0000_0000u16;
-                    // println!("{ptr}");
-                    v.extend_from_slice(Self::parse(buf, ptr as usize).0.iter().as_slice());
-                    // println!("{:?}", v);
-                    start += 2;
+                    let ptr = (read_u16(buf, pos)? ^ 0b1100_0000_0000_0000u16) as usize;
+                    if ptr >= pos {
+                        return Err(ParseError::ForwardPointer { offset: pos })
+                    }
+                    hops += 1;
+                    if ptr >= segment || hops > Self::MAX_POINTERS {
+                        return Err(ParseError::PointerLoop { offset: pos })
+                    }
+                    after_name.get_or_insert(pos + 2);
+                    pos = ptr;
+                    segment = ptr;
                 },
+                len if len & 0b1100_0000 != 0 => {
+                    return Err(ParseError::BadLabelLength { offset: pos, len })
+                }
                 len => {
-                    let end = start + len as usize + 1;
-                    v.push(Label::from(&buf[start+1..end]));
-                    start += len as usize + 1;
+                    name_len += len as usize + 1;
+                    if name_len > Self::MAX_NAME_LEN {
+                        return Err(ParseError::NameTooLong { offset: start })
+                    }
+                    let end = pos + len as usize + 1;
+                    let label = buf.get(pos + 1..end).ok_or(ParseError::Truncated { offset: pos })?;
+                    v.push(Label::from(label));
+                    pos = end;
                 }
-
             }
         }
-        (v , start)
-
+        Ok((v, after_name.unwrap_or(pos + 1)))
     }
 }
 
@@ -366,28 +841,43 @@ impl DerefMut for Labels {
 
 
 impl Label {
-    #[allow(dead_code)]
+    /// Longest label allowed; longer lengths would read as pointers.
+    const MAX_LEN: usize = 63;
+
+    #[allow(dead_code, clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         self.val[0] as usize
     }
+}
 
+/// Presentation form (RFC 1035 5.1): dots and backslashes inside the label
+/// are escaped and other bytes outside printable ASCII written as `\DDD`, so
+/// no two names print the same.
+impl Display for Label {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for &c in &self.val[1..] {
+            match c {
+                b'.' | b'\\' => write!(f, "\\{}", c as char)?,
+                0x21..=0x7e => write!(f, "{}", c as char)?,
+                _ => write!(f, "\\{:03}", c)?,
+            }
+        }
+        Ok(())
+    }
+}
 
-
-    pub fn to_string(&self) -> String {
-        String::from_utf8_lossy(&self.val[1..]).to_string()
+impl Display for Labels {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.iter().map(|l| l.to_string()).collect::<Vec<_>>().join("."))
     }
 }
 
 impl Labels {
-
-    pub fn to_string(&self) -> String {
-        self.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(".")
-    }
     pub fn from_bytes(seq: &[u8]) -> Self {
-        let mut iter = seq.into_iter().copied();
+        let mut iter = seq.iter().copied();
         let mut vec = vec![];
         while let Some(len) = iter.next() {
-            if len == b'0' {
+            if len == 0 {
                 break
             }
             let label = Label::from(iter.by_ref().take(len as usize).collect::<Vec<_>>().as_bytes());
@@ -396,13 +886,143 @@ impl Labels {
         Self(vec)
     }
 
+    /// Parses a dotted name; a trailing dot is optional and `""` or `"."`
+    /// is the root. The escapes `Display` writes (`\.`, `\\` and `\DDD`) are
+    /// undone, so printing a name and parsing it back gives the same labels.
+    /// The name has to be valid already, e.g. from a zone file; names typed
+    /// by a user go through `parse_domain`.
     pub fn from_domain(domain: &str) -> Self {
-        Self(domain.split(".").map(|s| Label::from(s.as_bytes())).collect::<Vec<_>>())
+        Self(unescape(domain).iter().map(|label| Label::from(&label[..])).collect())
+    }
+
+    /// Like `from_domain`, but rejects empty labels, labels over 63 octets
+    /// and names over 255 (RFC 1035 2.3.4).
+    pub fn parse_domain(domain: &str) -> Result<Self, ParseError> {
+        let labels = unescape(domain);
+        let bad = |label: &[u8]| ParseError::BadLabel {
+            name: domain.to_string(),
+            label: String::from_utf8_lossy(label).into_owned(),
+        };
+        if let Some(label) = labels.iter().find(|l| l.is_empty() || l.len() > Label::MAX_LEN) {
+            return Err(bad(label))
+        }
+        if labels.iter().map(|l| l.len() + 1).sum::<usize>() + 1 > Self::MAX_NAME_LEN {
+            return Err(ParseError::DomainTooLong { name: domain.to_string() })
+        }
+        Ok(Self(labels.iter().map(|label| Label::from(&label[..])).collect()))
     }
 
+    /// The name with ASCII letters lowercased, the canonical form DNSSEC
+    /// signs (RFC 4034 section 6.2).
+    pub fn to_lowercase(&self) -> Self {
+        let lower = |label: &Label| {
+            let mut label = label.clone();
+            label.val[1..].make_ascii_lowercase();
+            label
+        };
+        Self(self.0.iter().map(lower).collect())
+    }
+
+    /// Uncompressed wire form, including the terminating root label.
+    pub fn to_bytes(&self) -> BytesMut {
+        let mut buf: BytesMut = self.0.iter().flat_map(|label| label.as_bytes()).collect();
+        buf.put_u8(0);
+        buf
+    }
+
+    #[allow(dead_code)]
     pub fn into_bytes_mut(self) -> BytesMut {
         self.0.iter().flat_map(|label| label.as_bytes()).collect()
     }
+
+    /// Appends the name to `buf`, replacing the longest suffix already present
+    /// in `table` with a pointer and remembering the suffixes written here.
+    pub fn write(&self, buf: &mut BytesMut, table: &mut Compression) {
+        for i in 0..self.len() {
+            let suffix = &self.0[i..];
+            if let Some(offset) = table.find(suffix) {
+                buf.put_u16(0b1100_0000_0000_0000 | offset);
+                return
+            }
+            table.insert(suffix, buf.len());
+            buf.extend_from_slice(&self.0[i]);
+        }
+        buf.put_u8(0);
+    }
+}
+
+/// The raw labels of a name in presentation form, without the root label.
+/// A backslash takes the next character literally, or three digits as the
+/// value of one octet.
+fn unescape(domain: &str) -> Vec<Vec<u8>> {
+    let bytes = domain.as_bytes();
+    let mut labels = vec![vec![]];
+    let mut i = 0;
+    while i < bytes.len() {
+        let label = labels.last_mut().unwrap();
+        match bytes[i] {
+            b'\\' => {
+                let decimal = bytes
+                    .get(i + 1..i + 4)
+                    .filter(|digits| digits.iter().all(u8::is_ascii_digit))
+                    .and_then(|digits| std::str::from_utf8(digits).ok()?.parse::<u8>().ok());
+                match (decimal, bytes.get(i + 1)) {
+                    (Some(octet), _) => (label.push(octet), i += 4),
+                    (None, Some(&c)) => (label.push(c), i += 2),
+                    (None, None) => (label.push(b'\\'), i += 1),
+                };
+            }
+            b'.' => {
+                labels.push(vec![]);
+                i += 1;
+            }
+            c => {
+                label.push(c);
+                i += 1;
+            }
+        }
+    }
+    // a trailing dot, or the root written as "" or "."
+    if labels.last().is_some_and(Vec::is_empty) {
+        labels.pop();
+    }
+    if labels == [Vec::<u8>::new()] {
+        labels.clear();
+    }
+    labels
+}
+
+/// Names written so far in a message, keyed by their lowercased wire form.
+#[derive(Debug, Default)]
+pub struct Compression {
+    disabled: bool,
+    names: HashMap<Vec<u8>, u16>,
+}
+
+impl Compression {
+    /// Pointers can only address the first 16K of a message.
+    const MAX_OFFSET: usize = 0b0011_1111_1111_1111;
+
+    pub fn disabled() -> Self {
+        Self { disabled: true, names: HashMap::new() }
+    }
+
+    fn key(suffix: &[Label]) -> Vec<u8> {
+        suffix.iter().flat_map(|l| l.iter().map(u8::to_ascii_lowercase)).collect()
+    }
+
+    fn find(&self, suffix: &[Label]) -> Option<u16> {
+        if self.disabled {
+            return None
+        }
+        self.names.get(&Self::key(suffix)).copied()
+    }
+
+    fn insert(&mut self, suffix: &[Label], offset: usize) {
+        if !self.disabled && offset <= Self::MAX_OFFSET {
+            self.names.entry(Self::key(suffix)).or_insert(offset as u16);
+        }
+    }
 }
 
 
@@ -436,9 +1056,11 @@ impl DerefMut for Label {
 mod tests{
     use bytes::{BufMut, BytesMut};
     use crate::answer::Answer;
-    use crate::header::Header;
+    use crate::edns::Edns;
+    use crate::error::ParseError;
+    use crate::header::{Header, Opcode, Rcode};
     use crate::message;
-    use crate::message::{Label, Labels, Message, MessageBuilder};
+    use crate::message::{Class, Compression, Label, Labels, Message, MessageBuilder, Ty};
     use crate::question::Question;
 
     #[test]
@@ -463,13 +1085,15 @@ mod tests{
         let parsed = Header {
             id: 27901,
             qr: false,
-            opcode: 0,
+            opcode: Opcode::Query,
             aa: false,
             tc: false,
             rd: true,
             ra: false,
-            reserved: 0,
-            r_code: 0,
+            z: false,
+            ad: false,
+            cd: false,
+            r_code: Rcode::NoError,
             qd_count: 1,
             an_count: 0,
             ns_count: 0,
@@ -489,7 +1113,7 @@ mod tests{
     fn ser_de() {
         let message = message::MessageBuilder::new()
             .set_id(1488)
-            .set_opcode(228)
+            .set_opcode(Opcode::from(228))
             .set_rd(true)
             .finish();
         let ser = message.serialize();
@@ -533,10 +1157,11 @@ mod tests{
     #[test]
     fn i_guess_it_broken() {
         let val = b"\x03abc\x11longassdomainname\x03com\0\x03def\xC0\x04\x05hello\0";
-        let (l, end) = Labels::parse(val, 0);
-        let _bytes = BytesMut::from(&val[end+1..]);
-        let (a, b) = Labels::parse(val, end + 1);
-        println!("{l:?}, rest is {:?}", a)
+        let (l, end) = Labels::parse(val, 0).unwrap();
+        let _bytes = BytesMut::from(&val[end..]);
+        let (a, _) = Labels::parse(val, end).unwrap();
+        println!("{l:?}, rest is {:?}", a);
+        assert_eq!(a.to_string(), "def.longassdomainname.com")
     }
 
     #[test]
@@ -544,8 +1169,350 @@ mod tests{
         let val = b"\xbf9\x01\0\0\x02\0\0\0\0\0\0\x03abc\x11longassdomainname\x03com\0\0\x01\0\x01\x03def\xc0\x10\0\x01\0\x01";
         let bytes = BytesMut::from(&val[49..]);
         println!("{:?}", bytes);
-        let de = Message::deserialize(val);
+        let de = Message::deserialize(val).unwrap();
         println!("{de:?}");
+        assert_eq!(de.questions[1].domain(), "def.longassdomainname.com")
+    }
+
+    #[test]
+    fn truncated_message_is_an_error() {
+        let val = b"\xbf9\x01\0\0\x01\0\0\0\0\0\0\x03abc\x03com\0\0\x01";
+        assert_eq!(Message::deserialize(val), Err(ParseError::Truncated { offset: 23 }));
+        assert!(Message::deserialize(&val[..5]).is_err());
+    }
+
+    #[test]
+    fn pointer_loop_is_an_error() {
+        let val = b"\x03abc\xc0\x00";
+        assert_eq!(Labels::parse(val, 0), Err(ParseError::PointerLoop { offset: 4 }));
+    }
+
+    #[test]
+    fn forward_pointer_is_an_error() {
+        let val = b"\x03abc\xc0\x06\x01x\0";
+        assert_eq!(Labels::parse(val, 0), Err(ParseError::ForwardPointer { offset: 4 }));
+    }
+
+    #[test]
+    fn pointer_chain_is_followed() {
+        // "c" -> "b" -> "a.io", each hop strictly backwards
+        let val = b"\x01a\x02io\0\x01b\xc0\x00\x01c\xc0\x06";
+        let (name, end) = Labels::parse(val, 10).unwrap();
+        assert_eq!(name.to_string(), "c.b.a.io");
+        assert_eq!(end, val.len());
+    }
+
+    #[test]
+    fn pointer_into_own_name_is_a_loop() {
+        // second pointer jumps back into the labels reached by the first
+        let val = b"\x01a\xc0\x00\x01b\xc0\x00";
+        assert_eq!(Labels::parse(val, 4), Err(ParseError::PointerLoop { offset: 2 }));
+    }
+
+    #[test]
+    fn long_names_are_rejected() {
+        // one more label on top of a name of exactly 255 octets
+        let mut val = BytesMut::from(&b"\x01x"[..]);
+        for len in [63, 63, 63, 61] {
+            val.put_u8(len);
+            val.extend_from_slice(&[b'x'; 63][..len as usize]);
+        }
+        val.put_u8(0);
+        assert_eq!(Labels::parse(&val, 0), Err(ParseError::NameTooLong { offset: 0 }));
+        assert_eq!(Labels::parse(&val[2..], 0).unwrap().1, 255);
+    }
+
+    #[test]
+    fn presentation_form_round_trips() {
+        let name = Labels::from_bytes(b"\x04a.b\x01\x02\\\\\x07example\0");
+        assert_eq!(name.to_string(), r"a\.b\001.\\\\.example");
+        assert_eq!(Labels::from_domain(&name.to_string()), name);
+        assert_eq!(Labels::from_domain(&format!("{}.", name)), name);
+        assert_eq!(Labels::parse_domain(&name.to_string()), Ok(name));
+        assert_eq!(Labels::from_domain("."), Labels::default());
+    }
+
+    #[test]
+    fn typed_names_are_checked() {
+        assert_eq!(Labels::parse_domain("Example.COM.").unwrap(), Labels::from_domain("Example.COM"));
+        assert_eq!(Labels::parse_domain(".").unwrap(), Labels::default());
+        let long = "x".repeat(64);
+        assert_eq!(
+            Labels::parse_domain(&format!("{long}.example")),
+            Err(ParseError::BadLabel { name: format!("{long}.example"), label: long })
+        );
+        assert!(matches!(Labels::parse_domain("a..example"), Err(ParseError::BadLabel { .. })));
+        // labels of 63, 63, 63 and 61 octets make a name of exactly 255
+        let label = |len| "x".repeat(len);
+        let longest = [label(63), label(63), label(63), label(61)].join(".");
+        assert_eq!(Labels::parse_domain(&longest).unwrap().to_bytes().len(), 255);
+        let name = format!("x.{longest}");
+        assert_eq!(Labels::parse_domain(&name), Err(ParseError::DomainTooLong { name }));
+    }
+
+    #[test]
+    fn reserved_label_types_are_rejected() {
+        assert_eq!(Labels::parse(b"\x41a\0", 0), Err(ParseError::BadLabelLength { offset: 0, len: 0x41 }));
+        assert_eq!(Labels::parse(b"\x01a\x80", 0), Err(ParseError::BadLabelLength { offset: 2, len: 0x80 }));
+    }
+
+    #[test]
+    fn unknown_type_is_carried_through() {
+        let val = b"\xbf9\x01\0\0\x01\0\0\0\0\0\0\x03abc\x03com\0\x01\x02\0\x01";
+        let de = Message::deserialize(val).unwrap();
+        assert_eq!(de.questions[0].ty(), Ty::Unknown(258));
+        assert_eq!(&de.serialize()[..], &val[..]);
+    }
+
+    #[test]
+    fn type_and_class_mnemonics() {
+        assert_eq!("TYPE65".parse::<Ty>().unwrap(), Ty::Unknown(65));
+        assert_eq!("type1".parse::<Ty>().unwrap(), Ty::A);
+        assert_eq!("aaaa".parse::<Ty>().unwrap(), Ty::AAAA);
+        assert_eq!(Ty::Unknown(65).to_string(), "TYPE65");
+        assert_eq!("CLASS255".parse::<Class>().unwrap(), Class::Unknown(255));
+        assert!("BOGUS".parse::<Ty>().is_err());
+    }
+
+    #[test]
+    fn all_sections_round_trip() {
+        let message = MessageBuilder::new()
+            .set_id(4242)
+            .add_question(Question::from_domain_name("example.com"))
+            .add_answer(Answer::from_domain_name("example.com"))
+            .add_authority(Answer::from_domain_name("ns1.example.com"))
+            .add_additional(Answer::from_domain_name("ns1.example.com"))
+            .add_additional(Answer::from_domain_name("ns2.example.com"))
+            .finish();
+        assert_eq!((message.header.ns(), message.header.ar()), (1, 2));
+        let de = Message::deserialize(&message.clone().serialize()).unwrap();
+        assert_eq!(de, message);
+    }
+
+    #[test]
+    fn repeated_names_are_compressed() {
+        let message = MessageBuilder::new()
+            .add_question(Question::from_domain_name("www.example.com"))
+            .add_answer(Answer::from_domain_name("www.example.com"))
+            .add_answer(Answer::from_domain_name("www.example.com"))
+            .add_authority(Answer::from_domain_name("ns1.example.com"))
+            .finish();
+        let compressed = message.clone().serialize();
+        let plain = message.clone().serialize_with(false);
+        // question 17 + 2 * (2 + 14) answers + "ns1" and a pointer (6 + 14)
+        assert_eq!(compressed.len(), 12 + 21 + 2 * 16 + 20);
+        assert_eq!(plain.len(), 12 + 21 + 2 * 31 + 31);
+        assert_eq!(&compressed[33..35], b"\xc0\x0c");
+        assert_eq!(Message::deserialize(&compressed).unwrap(), message);
+        assert_eq!(Message::deserialize(&plain).unwrap(), message);
+    }
+
+    #[test]
+    fn compression_ignores_case() {
+        let mut table = Compression::default();
+        let mut buf = BytesMut::from(&[0u8; 12][..]);
+        Labels::from_domain("Example.COM").write(&mut buf, &mut table);
+        Labels::from_domain("mail.example.com").write(&mut buf, &mut table);
+        assert_eq!(&buf[25..], b"\x04mail\xc0\x0c");
+    }
+
+    fn answer_with_address(name: &str, address: u32) -> Answer {
+        Answer::new(name, 1, 1, 60, address)
+    }
+
+    #[test]
+    fn truncation_drops_whole_rrsets() {
+        let message = MessageBuilder::new()
+            .add_question(Question::from_domain_name("a.example"))
+            .add_answers((0..20).map(|i| answer_with_address("a.example", i)))
+            .add_answers((0..20).map(|i| answer_with_address("B.example", i)))
+            .add_answer(answer_with_address("b.EXAMPLE", 99))
+            .finish();
+        let full = message.clone().serialize();
+        assert!(full.len() > 512);
+        let truncated = Message::deserialize(&message.serialize_truncated(512)).unwrap();
+        assert!(truncated.tc());
+        assert_eq!(truncated.header.an(), 20);
+        assert!(truncated.answers.iter().all(|a| a.domain() == "a.example"));
+    }
+
+    #[test]
+    fn truncation_keeps_the_opt_record() {
+        let message = MessageBuilder::new()
+            .add_question(Question::from_domain_name("a.example"))
+            .add_answers((0..40).map(|i| answer_with_address(&format!("host{i}.example"), i)))
+            .set_edns(Edns::default())
+            .finish();
+        let truncated = message.clone().serialize_truncated(512);
+        assert!(truncated.len() <= 512);
+        let truncated = Message::deserialize(&truncated).unwrap();
+        assert!(truncated.tc());
+        assert_eq!(truncated.edns(), message.edns());
+        assert_eq!(truncated.answers[..], message.answers[..truncated.answers.len()]);
+    }
+
+    #[test]
+    fn truncation_below_the_question_sends_the_header() {
+        let message = MessageBuilder::new()
+            .set_id(0x7c)
+            .add_question(Question::from_domain_name("a-rather-long-name.example"))
+            .add_answer(answer_with_address("a-rather-long-name.example", 1))
+            .finish();
+        let truncated = message.serialize_truncated(20);
+        assert_eq!(truncated.len(), 12);
+        let truncated = Message::deserialize(&truncated).unwrap();
+        assert_eq!(truncated.id(), 0x7c);
+        assert!(truncated.tc());
+        assert!(truncated.questions.is_empty() && truncated.answers.is_empty());
+    }
+
+    #[test]
+    fn dropping_additionals_does_not_set_tc() {
+        let message = MessageBuilder::new()
+            .add_question(Question::from_domain_name("a.example"))
+            .add_answer(answer_with_address("a.example", 1))
+            .add_additionals((0..40).map(|i| answer_with_address(&format!("ns{i}.example"), i)))
+            .finish();
+        let truncated = message.clone().serialize_truncated(512);
+        assert!(truncated.len() <= 512);
+        let truncated = Message::deserialize(&truncated).unwrap();
+        assert!(!truncated.tc());
+        assert_eq!(truncated.answers, message.answers);
+        assert!(truncated.header.ar() < 40);
+        assert_eq!(message.clone().serialize_truncated(65535), message.serialize());
+    }
+
+    #[test]
+    fn edns_is_split_from_additionals() {
+        let message = MessageBuilder::new()
+            .add_question(Question::from_domain_name("a.example"))
+            .add_additional(answer_with_address("ns.example", 1))
+            .set_edns(Edns { payload_size: 4096, ..Default::default() })
+            .finish();
+        assert_eq!(message.header.ar(), 2);
+        let de = Message::deserialize(&message.clone().serialize()).unwrap();
+        assert_eq!(de, message);
+        assert_eq!(de.additionals.len(), 1);
+        assert_eq!(de.udp_payload_size(), 4096);
+        assert_eq!(Message::default().udp_payload_size(), 512);
+    }
+
+    #[test]
+    fn extended_rcode_spans_header_and_opt() {
+        let mut message = MessageBuilder::new().finish();
+        message.set_rcode(Rcode::NXDomain);
+        assert_eq!((message.rcode(), message.edns().is_none()), (Rcode::NXDomain, true));
+        message.set_rcode(Rcode::BadVers);
+        assert_eq!(message.header.r_code, Rcode::NoError);
+        assert_eq!(message.edns().unwrap().extended_rcode, 1);
+        let de = Message::deserialize(&message.serialize()).unwrap();
+        assert_eq!(de.rcode(), Rcode::BadVers);
+        assert_eq!(de.header.ar(), 1);
+    }
+
+    #[test]
+    fn two_opt_records_are_an_error() {
+        let opt = b"\0\0\x29\x10\0\0\0\0\0\0\0";
+        let mut val = b"\xbf9\x01\0\0\0\0\0\0\0\0\x02".to_vec();
+        val.extend_from_slice(opt);
+        val.extend_from_slice(opt);
+        assert_eq!(Message::deserialize(&val), Err(ParseError::MultipleOpt { offset: 23 }));
+    }
+
+    #[test]
+    fn root_name() {
+        assert!(Labels::from_domain(".").is_empty());
+        assert_eq!(Labels::from_domain("example.com."), Labels::from_domain("example.com"));
+    }
+
+    #[test]
+    fn response_to_copies_query_fields() {
+        let query = MessageBuilder::new()
+            .set_id(99)
+            .set_opcode(Opcode::Status)
+            .set_rd(true)
+            .add_question(Question::from_domain_name("a.example"))
+            .finish();
+        let response = MessageBuilder::response_to(&query).finish();
+        assert_eq!((response.id(), response.opcode(), response.rd()), (99, Opcode::Status, true));
+        assert!(!response.cd());
+        let query = MessageBuilder::from(query).set_cd(true).set_ad(true).finish();
+        let response = MessageBuilder::response_to(&query).finish();
+        assert!(response.cd() && !response.ad());
+        assert_eq!(response.questions, query.questions);
+        assert_eq!(response.header.qd(), 1);
+        assert_eq!(response.rcode(), Rcode::NoError);
+    }
+
+    #[test]
+    fn format_error_echoes_id() {
+        let m = Message::format_error(b"\xbf9\x01");
+        assert_eq!(m.id(), 0xbf39);
+        assert!(m.rd());
+        assert_eq!(m.header.r_code, Rcode::FormErr);
+        assert_eq!(Message::format_error(b"\xbf9\x21").opcode(), Opcode::Notify);
+    }
+
+    #[test]
+    fn format_error_echoes_readable_questions() {
+        let mut bytes = MessageBuilder::new()
+            .set_id(0xbf39)
+            .add_question(Question::from_domain_name("a.example"))
+            .add_answer(Answer::new("a.example", 1, 1, 60, 0xc0000201))
+            .finish()
+            .serialize();
+        bytes.truncate(bytes.len() - 3);
+        assert!(Message::deserialize(&bytes).is_err());
+        let m = Message::format_error(&bytes);
+        assert_eq!((m.id(), m.rcode()), (0xbf39, Rcode::FormErr));
+        assert_eq!(m.questions.len(), 1);
+        assert_eq!(m.header.qd(), 1);
+        assert_eq!(m.questions[0].domain(), "a.example");
+
+        // a question cut short is left out
+        let m = Message::format_error(&bytes[..20]);
+        assert!(m.questions.is_empty());
+        assert_eq!(m.header.qd(), 0);
+    }
+
+    #[test]
+    fn join_merges_headers() {
+        let query = MessageBuilder::new()
+            .set_id(0x5eed)
+            .set_rd(true)
+            .add_question(Question::from_domain_name("a.example"))
+            .add_question(Question::from_domain_name("b.example"))
+            .add_question(Question::from_domain_name("c.example"))
+            .finish();
+        let parts = query.clone().split();
+        let found = MessageBuilder::response_to(&parts[0])
+            .set_aa(true)
+            .set_ra(true)
+            .set_ad(true)
+            .add_answer(Answer::new("a.example", 1, 1, 60, 0xc0000201))
+            .finish();
+        let mut missing = MessageBuilder::response_to(&parts[1])
+            .set_aa(true)
+            .set_ra(true)
+            .set_rcode(Rcode::NXDomain)
+            .add_authority(Answer::new("example", 1, 1, 60, 0xc0000202))
+            .finish();
+        missing.header.tc = true;
+        let refused = MessageBuilder::response_to(&parts[2]).set_ra(true).set_rcode(Rcode::Refused).finish();
+
+        let joined = Message::join(vec![found.clone(), missing.clone()]).unwrap();
+        assert_eq!((joined.id(), joined.rd()), (0x5eed, true));
+        assert_eq!(joined.rcode(), Rcode::NXDomain);
+        assert!(joined.aa() && joined.ra() && joined.tc());
+        assert!(!joined.ad());
+        assert_eq!((joined.questions.len(), joined.answers.len(), joined.authorities.len()), (2, 1, 1));
+        assert_eq!((joined.header.qd(), joined.header.an(), joined.header.ns()), (2, 1, 1));
+
+        let joined = Message::join(vec![refused.clone(), found.clone(), missing.clone()]).unwrap();
+        assert_eq!(joined.rcode(), Rcode::Refused);
+        assert!(!joined.aa());
+        assert_eq!(Message::join(vec![missing, refused, found]).unwrap().rcode(), Rcode::Refused);
+        assert!(Message::join(vec![]).is_err());
     }
 }
 
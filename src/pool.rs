@@ -0,0 +1,123 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of worker threads fed from a bounded queue.
+///
+/// At most `workers` jobs run at once and at most `queue` wait for a worker;
+/// beyond that `execute` blocks, which pushes back on whoever is producing
+/// the work (for a listener: it stops reading its socket).
+pub struct ThreadPool {
+    sender: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    pub fn new(name: &str, workers: usize, queue: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..workers.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("{}-{}", name, i))
+                    .spawn(move || loop {
+                        let job = receiver.lock().unwrap().recv();
+                        let Ok(job) = job else {
+                            break
+                        };
+                        // a panicking job must not take the worker down with it
+                        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            error!("Job on {} panicked", thread::current().name().unwrap_or("worker"));
+                        }
+                    })
+                    .expect("Failed to spawn worker thread")
+            })
+            .collect();
+        Self { sender: Some(sender), workers }
+    }
+
+    /// Runs `job` on a worker if there is room in the queue, without
+    /// waiting; whether it was queued.
+    pub fn try_execute<F: FnOnce() + Send + 'static>(&self, job: F) -> bool {
+        self.sender.as_ref().is_some_and(|sender| sender.try_send(Box::new(job)).is_ok())
+    }
+
+    /// Runs `job` on a worker, waiting for room in the queue if necessary.
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        if let Some(sender) = &self.sender {
+            sender.send(Box::new(job)).expect("worker threads are gone");
+        }
+    }
+}
+
+/// Lets queued jobs finish, then joins the workers.
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+    use crate::pool::ThreadPool;
+
+    #[test]
+    fn runs_every_job_with_bounded_concurrency() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new("test", 3, 1);
+        for _ in 0..12 {
+            let (running, peak, done) = (running.clone(), peak.clone(), done.clone());
+            pool.execute(move || {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+        assert_eq!(done.load(Ordering::SeqCst), 12);
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn try_execute_does_not_wait_for_room() {
+        let pool = ThreadPool::new("test", 1, 1);
+        let (started, running) = mpsc::channel();
+        let (release, held) = mpsc::channel::<()>();
+        assert!(pool.try_execute(move || {
+            started.send(()).unwrap();
+            let _ = held.recv();
+        }));
+        running.recv().unwrap();
+        assert!(pool.try_execute(|| {}));
+        assert!(!pool.try_execute(|| {}));
+        drop(release);
+    }
+
+    #[test]
+    fn workers_survive_panicking_jobs() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new("test", 1, 4);
+        pool.execute(|| panic!("boom"));
+        let counter = done.clone();
+        pool.execute(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        drop(pool);
+        assert_eq!(done.load(Ordering::SeqCst), 1);
+    }
+}
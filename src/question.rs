@@ -1,8 +1,8 @@
 
 
 use bytes::{BufMut, BytesMut};
-use nom::AsBytes;
-use crate::message::{Class, Ty, Labels};
+use crate::error::{read_u16, ParseError};
+use crate::message::{Class, Compression, Labels, Ty};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Question {
@@ -30,47 +30,65 @@ impl Question {
             class: Class::IN,
         }
     }
+
+    pub fn with_type(name: &str, ty: Ty, class: Class) -> Self {
+        Self {
+            name: Labels::from_domain(name),
+            ty,
+            class,
+        }
+    }
+
     pub fn new(buf: &[u8], ty: u16, class: u16) -> Self {
         Self {
             name: Labels::from_bytes(buf),
-            ty: ty.try_into().unwrap(),
-            class: class.try_into().unwrap(),
+            ty: ty.into(),
+            class: class.into(),
         }
     }
     pub fn serialize(self) -> BytesMut {
         let mut buf = BytesMut::new();
-        let v = self.name;
-        buf.extend(v.iter().flat_map(|l| l.as_bytes()));
-        buf.put_u8(0);
-        buf.put_u16(self.ty as u16);
-        buf.put_u16(self.class as u16);
+        self.write(&mut buf, &mut Compression::disabled());
         buf
     }
 
-    pub fn deserialize(bytes: &[u8], start: usize) -> (Self, usize) {
-        let (name, end) = Labels::parse(bytes, start);
-        // println!("{end}");
-        let t = [bytes[end], bytes[end+1]];
-        let ty = u16::from_be_bytes(t);
-        let ty = Ty::try_from(ty).unwrap();
-        let c = [bytes[end + 2], bytes[end + 3]];
-        let class = u16::from_be_bytes(c);
-        let class = Class::try_from(class).unwrap();
+    pub fn write(&self, buf: &mut BytesMut, table: &mut Compression) {
+        self.name.write(buf, table);
+        buf.put_u16(self.ty.into());
+        buf.put_u16(self.class.into());
+    }
+
+    pub fn deserialize(bytes: &[u8], start: usize) -> Result<(Self, usize), ParseError> {
+        let (name, end) = Labels::parse(bytes, start)?;
+        let ty = Ty::from(read_u16(bytes, end)?);
+        let class = Class::from(read_u16(bytes, end + 2)?);
         let len = end + 4;
-        (Self {
+        Ok((Self {
             name,
             ty,
             class,
-        }, len)
+        }, len))
     }
 
-
     pub fn domain(&self) -> String {
         self.name.to_string()
     }
+
+    pub fn labels(&self) -> &Labels {
+        &self.name
+    }
+
+    pub fn ty(&self) -> Ty {
+        self.ty
+    }
+
+    pub fn class(&self) -> Class {
+        self.class
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)]
 mod test {
     use bytes::BytesMut;
     use crate::question::Question;
@@ -78,7 +96,7 @@ mod test {
     #[test]
     fn test_serialize_question() {
         let question: Question = Default::default();
-        let expected = b"\x0ccodecrafters\x02io\011";
+        let expected = b"\x0ccodecrafters\x02io\x0011";
         let _expected = BytesMut::from(&expected[..]);
         let got = question.serialize();
         println!("{:?}", got)
@@ -87,8 +105,8 @@ mod test {
     #[test]
     fn test_deserialize_question() {
         let val = b"\x0ccodecrafters\x02io\0\x00\x01\x00\x01\xC0\x00\x00\x01\x00\x01";
-        let (_q, s) = Question::deserialize(val, 0);
-        let (_q, e) = Question::deserialize(val, s);
+        let (_q, s) = Question::deserialize(val, 0).unwrap();
+        let (_q, e) = Question::deserialize(val, s).unwrap();
         assert_eq!(e, val.len())
     }
 
@@ -115,7 +133,8 @@ mod test {
     fn test_de() {
         let q = Question::from_domain_name("hello.world.i.am.here");
         let buf = q.serialize();
-        let (bytes, len) = Question::deserialize(&buf, 0);
-        assert_eq!(len, buf.len())
+        let (de, len) = Question::deserialize(&buf, 0).unwrap();
+        assert_eq!(len, buf.len());
+        assert_eq!(de.domain(), "hello.world.i.am.here")
     }
 }
\ No newline at end of file
@@ -0,0 +1,482 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail, Context};
+use crate::answer::{Answer, Data};
+use crate::edns::Edns;
+use crate::header::{Opcode, Rcode};
+use crate::message::{Class, Message, MessageBuilder, Ty};
+use crate::question::Question;
+use crate::upstream::forward;
+use crate::zone::{is_subdomain, key, load_records, parent, MAX_CNAME_CHAIN};
+
+/// Addresses of a.root-servers.net through m.root-servers.net, used when no
+/// hints file is given. IPv4 ones come first, so hosts without IPv6
+/// connectivity don't start out waiting on servers they can't reach.
+const ROOT_SERVERS: [IpAddr; 26] = [
+    IpAddr::V4(Ipv4Addr::new(198, 41, 0, 4)),
+    IpAddr::V4(Ipv4Addr::new(170, 247, 170, 2)),
+    IpAddr::V4(Ipv4Addr::new(192, 33, 4, 12)),
+    IpAddr::V4(Ipv4Addr::new(199, 7, 91, 13)),
+    IpAddr::V4(Ipv4Addr::new(192, 203, 230, 10)),
+    IpAddr::V4(Ipv4Addr::new(192, 5, 5, 241)),
+    IpAddr::V4(Ipv4Addr::new(192, 112, 36, 4)),
+    IpAddr::V4(Ipv4Addr::new(198, 97, 190, 53)),
+    IpAddr::V4(Ipv4Addr::new(192, 36, 148, 17)),
+    IpAddr::V4(Ipv4Addr::new(192, 58, 128, 30)),
+    IpAddr::V4(Ipv4Addr::new(193, 0, 14, 129)),
+    IpAddr::V4(Ipv4Addr::new(199, 7, 83, 42)),
+    IpAddr::V4(Ipv4Addr::new(202, 12, 27, 33)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x503, 0xba3e, 0, 0, 0, 0x2, 0x30)),
+    IpAddr::V6(Ipv6Addr::new(0x2801, 0x1b8, 0x10, 0, 0, 0, 0, 0xb)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x2, 0, 0, 0, 0, 0xc)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x2d, 0, 0, 0, 0, 0xd)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0xa8, 0, 0, 0, 0, 0xe)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x2f, 0, 0, 0, 0, 0xf)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x12, 0, 0, 0, 0, 0xd0d)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x1, 0, 0, 0, 0, 0x53)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x7fe, 0, 0, 0, 0, 0, 0x53)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x503, 0xc27, 0, 0, 0, 0x2, 0x30)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x7fd, 0, 0, 0, 0, 0, 0x1)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x9f, 0, 0, 0, 0, 0x42)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0xdc3, 0, 0, 0, 0, 0, 0x35)),
+];
+/// Referrals followed for one name before we suspect a loop.
+const MAX_REFERRALS: usize = 16;
+/// How deep nameserver address lookups may nest inside each other.
+const MAX_DEPTH: usize = 6;
+
+/// Resolves names iteratively from the root, like a full-service resolver.
+pub struct Recursor {
+    roots: Vec<IpAddr>,
+    /// Port nameservers are queried on; only stand-in servers use another.
+    port: u16,
+    timeout: Duration,
+    /// Nameserver addresses by zone, learned from referrals.
+    delegations: Mutex<HashMap<String, Delegation>>,
+}
+
+struct Delegation {
+    servers: Vec<IpAddr>,
+    expires: Instant,
+}
+
+/// The outcome for one question: the answer (including any CNAME chain),
+/// or NXDOMAIN/NODATA with the authority section that proves it.
+#[derive(Debug)]
+pub struct Resolution {
+    pub rcode: Rcode,
+    pub answers: Vec<Answer>,
+    pub authorities: Vec<Answer>,
+}
+
+impl Default for Recursor {
+    fn default() -> Self {
+        Self::new(ROOT_SERVERS.to_vec())
+    }
+}
+
+impl Recursor {
+    pub fn new(roots: Vec<IpAddr>) -> Self {
+        Self {
+            roots,
+            port: 53,
+            timeout: Duration::from_secs(2),
+            delegations: Mutex::default(),
+        }
+    }
+
+    /// Reads root server addresses from a hints file in master-file format,
+    /// like IANA's named.root.
+    pub fn from_hints(path: &Path) -> anyhow::Result<Self> {
+        let records = load_records(path)?;
+        let names = records
+            .iter()
+            .filter_map(|r| match r.data() {
+                Data::NS(name) if r.domain().is_empty() => Some(key(&name.to_string())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let roots = addresses(records.iter().filter(|r| names.contains(&key(&r.domain()))));
+        if roots.is_empty() {
+            bail!("{} has no addresses for root nameservers", path.display())
+        }
+        Ok(Self::new(roots))
+    }
+
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Answers a single-question query, giving up at `deadline`.
+    pub fn answer(&self, query: &Message, deadline: Instant) -> anyhow::Result<Message> {
+        let [question] = &query.questions[..] else {
+            bail!("expected exactly one question")
+        };
+        if query.opcode() != Opcode::Query {
+            return Ok(MessageBuilder::response_to(query).set_rcode(Rcode::NotImp).finish())
+        }
+        let found = self.resolve(question, deadline)?;
+        Ok(MessageBuilder::response_to(query)
+            .set_ra(true)
+            .set_rcode(found.rcode)
+            .add_answers(found.answers)
+            .add_authorities(found.authorities)
+            .finish())
+    }
+
+    pub fn resolve(&self, question: &Question, deadline: Instant) -> anyhow::Result<Resolution> {
+        self.lookup(&question.domain(), question.ty(), question.class(), 0, deadline)
+    }
+
+    /// Resolves `name`, restarting at the target of every CNAME on the way.
+    fn lookup(&self, name: &str, ty: Ty, class: Class, depth: usize, deadline: Instant) -> anyhow::Result<Resolution> {
+        if depth > MAX_DEPTH {
+            bail!("nameserver lookups for {} nest too deeply", name)
+        }
+        let mut answers = vec![];
+        let mut name = key(name);
+        for _ in 0..=MAX_CNAME_CHAIN {
+            let response = self.iterate(&name, ty, class, depth, deadline)?;
+            // only records owned by the name we asked about are trustworthy
+            let owned = response.answers.iter().filter(|a| key(&a.domain()) == name).cloned().collect::<Vec<_>>();
+            if owned.iter().any(|a| a.ty() == ty) {
+                answers.extend(owned.into_iter().filter(|a| a.ty() == ty || covers(a, ty)));
+                // NSEC or NSEC3 records here prove a wildcard expansion
+                let authorities = response
+                    .authorities
+                    .iter()
+                    .filter(|a| matches!(a.ty(), Ty::NSEC | Ty::NSEC3) || covers(a, Ty::NSEC) || covers(a, Ty::NSEC3))
+                    .cloned()
+                    .collect();
+                return Ok(Resolution { rcode: Rcode::NoError, answers, authorities })
+            }
+            match owned.iter().find_map(|a| match a.data() {
+                Data::CNAME(target) => Some((a, key(&target.to_string()))),
+                _ => None,
+            }) {
+                Some((cname, target)) if !answers.iter().any(|a: &Answer| key(&a.domain()) == target) => {
+                    answers.push(cname.clone());
+                    answers.extend(owned.iter().filter(|a| covers(a, Ty::CNAME)).cloned());
+                    name = target;
+                }
+                Some(_) => bail!("CNAME loop at {}", name),
+                None => {
+                    return Ok(Resolution {
+                        rcode: response.rcode(),
+                        answers,
+                        authorities: response.authorities.to_vec(),
+                    })
+                }
+            }
+        }
+        bail!("CNAME chain for {} is longer than {}", name, MAX_CNAME_CHAIN)
+    }
+
+    /// Walks down the delegation tree from the closest zone we know servers
+    /// for, until some server answers `name` itself.
+    ///
+    /// DS records live on the parent side of a zone cut, so for them the
+    /// walk stops above `name`.
+    fn iterate(&self, name: &str, ty: Ty, class: Class, depth: usize, deadline: Instant) -> anyhow::Result<Message> {
+        let target = match ty {
+            Ty::DS => parent(name),
+            _ => name,
+        };
+        let (mut zone, mut servers) = self.closest_delegation(target);
+        // DO, so that signed zones send their signatures along
+        let mut edns = Edns::default();
+        edns.set_dnssec_ok(true);
+        let query = MessageBuilder::new()
+            .set_qr(false)
+            .add_question(Question::with_type(name, ty, class))
+            .set_edns(edns)
+            .finish();
+        for _ in 0..MAX_REFERRALS {
+            let response = self.ask(&servers, &query, deadline).with_context(|| format!("servers for {}.", zone))?;
+            let Some((cut, ns)) = referral(&response, &zone, target) else {
+                return Ok(response)
+            };
+            debug!("{}. is delegated to {:?}", cut, ns.iter().map(|n| n.0.as_str()).collect::<Vec<_>>());
+            servers = self.nameserver_addresses(&response, &zone, &cut, &ns, depth, deadline)?;
+            let ttl = ns.iter().map(|n| n.1).min().unwrap_or(0);
+            self.delegations.lock().unwrap_or_else(PoisonError::into_inner).insert(cut.clone(), Delegation {
+                servers: servers.clone(),
+                expires: Instant::now() + Duration::from_secs(ttl as u64),
+            });
+            zone = cut;
+        }
+        bail!("more than {} referrals for {}", MAX_REFERRALS, name)
+    }
+
+    /// Addresses for the nameservers of `cut`: glue from the referral if the
+    /// referring server is authoritative for it, otherwise looked up.
+    fn nameserver_addresses(
+        &self,
+        referral: &Message,
+        zone: &str,
+        cut: &str,
+        ns: &[(String, u32)],
+        depth: usize,
+        deadline: Instant,
+    ) -> anyhow::Result<Vec<IpAddr>> {
+        let glue = addresses(referral.additionals.iter().filter(|r| {
+            let owner = key(&r.domain());
+            is_subdomain(&owner, zone) && ns.iter().any(|(name, _)| *name == owner)
+        }));
+        if !glue.is_empty() {
+            return Ok(glue)
+        }
+        for (name, _) in ns {
+            // a server inside the zone it serves can only be reached via glue
+            if is_subdomain(name, cut) {
+                continue
+            }
+            for ty in [Ty::A, Ty::AAAA] {
+                match self.lookup(name, ty, Class::IN, depth + 1, deadline) {
+                    Ok(resolution) => {
+                        let addresses = addresses(resolution.answers.iter());
+                        if !addresses.is_empty() {
+                            return Ok(addresses)
+                        }
+                    }
+                    Err(e) => warn!("Can't resolve {} for nameserver {}: {:#}", ty, name, e),
+                }
+            }
+        }
+        Err(anyhow!("no reachable nameserver for {}.", cut))
+    }
+
+    /// The deepest zone above `name` whose servers we know, or the root.
+    fn closest_delegation(&self, name: &str) -> (String, Vec<IpAddr>) {
+        let now = Instant::now();
+        let mut delegations = self.delegations.lock().unwrap_or_else(PoisonError::into_inner);
+        delegations.retain(|_, d| d.expires > now);
+        let mut zone = name;
+        while !zone.is_empty() {
+            if let Some(delegation) = delegations.get(zone) {
+                return (zone.to_string(), delegation.servers.clone())
+            }
+            zone = parent(zone);
+        }
+        (String::new(), self.roots.clone())
+    }
+
+    /// Tries each server in turn until one gives a usable answer or the
+    /// deadline passes.
+    fn ask(&self, servers: &[IpAddr], query: &Message, deadline: Instant) -> anyhow::Result<Message> {
+        let mut last_error = anyhow!("no servers to ask");
+        for &server in servers {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(last_error.context("out of time"))
+            }
+            let addr = SocketAddr::new(server, self.port);
+            match forward(query, addr, Instant::now() + self.timeout.min(remaining)) {
+                Ok(response) if matches!(response.rcode(), Rcode::NoError | Rcode::NXDomain) => return Ok(response),
+                Ok(response) => last_error = anyhow!("{} answered {}", addr, response.rcode()),
+                Err(e) => last_error = e.context(format!("asking {}", addr)),
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// The IPv4 and IPv6 addresses among `records`, IPv4 first.
+fn addresses<'a>(records: impl Iterator<Item = &'a Answer>) -> Vec<IpAddr> {
+    let mut addresses = records
+        .filter_map(|r| match r.data() {
+            Data::A(address) => Some(IpAddr::from(Ipv4Addr::from(*address))),
+            Data::AAAA(address) => Some(IpAddr::from(Ipv6Addr::from(*address))),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    addresses.sort_by_key(IpAddr::is_ipv6);
+    addresses
+}
+
+/// Whether `record` is an RRSIG over records of type `ty`.
+fn covers(record: &Answer, ty: Ty) -> bool {
+    matches!(record.data(), Data::RRSIG { type_covered, .. } if *type_covered == ty)
+}
+
+/// The zone cut and its nameservers (with TTLs) if `response` refers us
+/// further down towards `name`. NS records for `zone` itself or for zones
+/// outside it are ignored, which also rules out referral loops.
+fn referral(response: &Message, zone: &str, name: &str) -> Option<(String, Vec<(String, u32)>)> {
+    if !response.answers.is_empty() || response.rcode() != Rcode::NoError {
+        return None
+    }
+    let ns = response
+        .authorities
+        .iter()
+        .filter_map(|r| match r.data() {
+            Data::NS(host) => Some((key(&r.domain()), key(&host.to_string()), r.ttl())),
+            _ => None,
+        })
+        .filter(|(cut, _, _)| cut != zone && is_subdomain(cut, zone) && is_subdomain(name, cut))
+        .collect::<Vec<_>>();
+    let cut = ns.first()?.0.clone();
+    let hosts = ns.into_iter().filter(|n| n.0 == cut).map(|(_, host, ttl)| (host, ttl)).collect();
+    Some((cut, hosts))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::header::Rcode;
+    use crate::message::{Class, Ty};
+    use crate::pool::ThreadPool;
+    use crate::question::Question;
+    use crate::recursor::Recursor;
+    use crate::server::{Handler, Mode};
+    use crate::zone::{Catalog, Zone};
+
+    const ROOT: &str = "$TTL 3600
+@ SOA a.root. hostmaster.root. 1 2 3 4 300
+@ NS a.root.
+a.root. A 127.0.0.1
+example. NS ns.example.
+ns.example. A 127.0.0.2
+other. NS ns.other.
+ns.other. A 127.0.0.3
+six. NS ns.six.
+ns.six. AAAA ::1
+";
+    const EXAMPLE: &str = "$TTL 3600
+@ SOA ns hostmaster 1 2 3 4 300
+@ NS ns
+ns A 127.0.0.2
+www CNAME host.other.
+delegated NS ns.elsewhere.other.
+";
+    const OTHER: &str = "$TTL 3600
+@ SOA ns hostmaster 1 2 3 4 300
+@ NS ns
+ns A 127.0.0.3
+host A 192.0.2.7
+ns.elsewhere A 127.0.0.4
+";
+    const DELEGATED: &str = "$TTL 3600
+@ SOA ns.elsewhere.other. hostmaster 1 2 3 4 300
+@ NS ns.elsewhere.other.
+target A 192.0.2.44
+";
+
+    const SIX: &str = "$TTL 3600
+@ SOA ns hostmaster 1 2 3 4 300
+@ NS ns
+ns AAAA ::1
+host A 192.0.2.6
+";
+
+    fn soon() -> Instant {
+        Instant::now() + Duration::from_secs(10)
+    }
+
+    /// Runs stand-in authoritative servers on 127.0.0.1-4 and ::1, all on
+    /// one port, and returns a recursor whose only root is 127.0.0.1.
+    fn stand_ins() -> Recursor {
+        let zones = [(ROOT, "."), (EXAMPLE, "example"), (OTHER, "other"), (DELEGATED, "delegated.example"), (SIX, "six")];
+        let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = first.local_addr().unwrap().port();
+        let mut sockets = vec![first];
+        for i in 2..zones.len() {
+            sockets.push(UdpSocket::bind(SocketAddr::new(IpAddr::from([127, 0, 0, i as u8]), port)).unwrap());
+        }
+        sockets.push(UdpSocket::bind(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), port)).unwrap());
+        for (socket, (text, origin)) in sockets.into_iter().zip(zones) {
+            let catalog = Catalog::new(vec![Zone::parse(text, origin).unwrap()]).unwrap();
+            let handler = Handler::new(None, Arc::new(ThreadPool::new("resolve", 1, 1))).with_catalog(catalog);
+            thread::spawn(move || loop {
+                let mut buf = [0; 4096];
+                let (n, client) = socket.recv_from(&mut buf).unwrap();
+                if let Some(response) = handler.handle_udp(&buf[..n], client.ip(), Mode::Authoritative) {
+                    socket.send_to(&response, client).unwrap();
+                }
+            });
+        }
+        Recursor::new(vec![Ipv4Addr::LOCALHOST.into()]).with_port(port)
+    }
+
+    #[test]
+    fn follows_referrals_and_cnames() {
+        let recursor = stand_ins();
+        let found = recursor.resolve(&Question::from_domain_name("WWW.example"), soon()).unwrap();
+        assert_eq!(found.rcode, Rcode::NoError);
+        let answers = found.answers.iter().map(|a| (a.domain(), a.ty())).collect::<Vec<_>>();
+        assert_eq!(answers, [("www.example".to_string(), Ty::CNAME), ("host.other".to_string(), Ty::A)]);
+        assert_eq!(found.answers[1].data().to_string(), "192.0.2.7");
+
+        let delegations = recursor.delegations.lock().unwrap();
+        let mut zones = delegations.keys().cloned().collect::<Vec<_>>();
+        zones.sort();
+        assert_eq!(zones, ["example", "other"]);
+        assert_eq!(delegations["other"].servers, [IpAddr::from([127, 0, 0, 3])]);
+    }
+
+    #[test]
+    fn resolves_out_of_bailiwick_nameservers() {
+        let recursor = stand_ins();
+        let found = recursor.resolve(&Question::from_domain_name("target.delegated.example"), soon()).unwrap();
+        assert_eq!(found.answers[0].data().to_string(), "192.0.2.44");
+        assert_eq!(recursor.delegations.lock().unwrap()["delegated.example"].servers, [IpAddr::from([127, 0, 0, 4])]);
+    }
+
+    #[test]
+    fn follows_ipv6_glue() {
+        let recursor = stand_ins();
+        let found = recursor.resolve(&Question::from_domain_name("host.six"), soon()).unwrap();
+        assert_eq!(found.answers[0].data().to_string(), "192.0.2.6");
+        assert_eq!(recursor.delegations.lock().unwrap()["six"].servers, [IpAddr::from(Ipv6Addr::LOCALHOST)]);
+    }
+
+    #[test]
+    fn negative_answers_carry_the_soa() {
+        let recursor = stand_ins();
+        let missing = recursor.resolve(&Question::from_domain_name("missing.other"), soon()).unwrap();
+        assert_eq!(missing.rcode, Rcode::NXDomain);
+        assert_eq!((missing.authorities[0].domain(), missing.authorities[0].ty()), ("other".to_string(), Ty::SOA));
+
+        let nodata = recursor.resolve(&Question::with_type("host.other", Ty::AAAA, Class::IN), soon()).unwrap();
+        assert_eq!((nodata.rcode, nodata.answers.len()), (Rcode::NoError, 0));
+    }
+
+    #[test]
+    fn ds_records_come_from_the_parent() {
+        let recursor = stand_ins();
+        recursor.resolve(&Question::from_domain_name("www.example"), soon()).unwrap();
+        // example.'s own servers are known by now, but only the root can
+        // speak for the DS records at the cut
+        let found = recursor.resolve(&Question::with_type("example", Ty::DS, Class::IN), soon()).unwrap();
+        assert_eq!((found.rcode, found.answers.len()), (Rcode::NoError, 0));
+        assert_eq!((found.authorities[0].domain(), found.authorities[0].ty()), (String::new(), Ty::SOA));
+    }
+
+    #[test]
+    fn reads_root_hints() {
+        let path = std::env::temp_dir().join(format!("named-{}.root", std::process::id()));
+        let hints = ".                        3600000      NS    A.ROOT-SERVERS.NET.
+A.ROOT-SERVERS.NET.      3600000      A     198.41.0.4
+A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30
+; not a root server
+EXAMPLE.                 3600000      A     192.0.2.1
+";
+        std::fs::write(&path, hints).unwrap();
+        let recursor = Recursor::from_hints(&path);
+        std::fs::remove_file(&path).unwrap();
+        let roots = ["198.41.0.4", "2001:503:ba3e::2:30"].map(|a| a.parse::<IpAddr>().unwrap());
+        assert_eq!(recursor.unwrap().roots, roots);
+    }
+}
@@ -0,0 +1,1156 @@
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream, UdpSocket};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail};
+use bytes::{BufMut, BytesMut};
+use crate::acl::Acl;
+use crate::cache::Cache;
+use crate::edns::Edns;
+use crate::header::{Opcode, Rcode};
+use crate::message::{Answers, Message, MessageBuilder, Ty};
+use crate::pool::ThreadPool;
+use crate::recursor::Recursor;
+use crate::upstream::Upstreams;
+use crate::validator::Validator;
+use crate::zone::{key, parent, Catalog};
+
+/// How a listener answers names outside the zones we serve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// It doesn't; they are refused.
+    Authoritative,
+    /// By forwarding to the upstream resolvers.
+    Forward,
+    /// By resolving them iteratively from the root.
+    Recursive,
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "authoritative" => Ok(Mode::Authoritative),
+            "forward" => Ok(Mode::Forward),
+            "recursive" => Ok(Mode::Recursive),
+            _ => Err(anyhow!("unknown mode {:?}, expected authoritative, forward or recursive", s)),
+        }
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Mode::Authoritative => "authoritative",
+            Mode::Forward => "forward",
+            Mode::Recursive => "recursive",
+        })
+    }
+}
+
+/// What to answer when some questions of a multi-question query could be
+/// resolved and others could not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialFailure {
+    /// SERVFAIL for the whole query.
+    ServFail,
+    /// The answers we have; the failed questions get none.
+    Partial,
+}
+
+impl FromStr for PartialFailure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "servfail" => Ok(PartialFailure::ServFail),
+            "partial" => Ok(PartialFailure::Partial),
+            _ => Err(anyhow!("unknown policy {:?}, expected servfail or partial", s)),
+        }
+    }
+}
+
+/// Sends a single-question query on, giving up at the deadline; shared with
+/// the workers resolving the questions of a query.
+type Resolve = Arc<dyn Fn(&Message, Instant) -> anyhow::Result<Message> + Send + Sync>;
+
+/// Workers resolving the questions of multi-question queries, shared by all
+/// queries and every handler a reload swaps in, and how many questions may
+/// wait for one. Questions beyond that fail at once rather than piling up.
+pub const RESOLVERS: usize = 32;
+pub const RESOLVER_QUEUE: usize = 256;
+
+/// Query handling shared by the UDP and TCP listeners.
+pub struct Handler {
+    upstreams: Option<Arc<Upstreams>>,
+    recursor: Option<Arc<Recursor>>,
+    validator: Option<Arc<Validator>>,
+    catalog: Arc<Catalog>,
+    cache: Arc<Mutex<Cache>>,
+    acl: Acl,
+    resolvers: Arc<ThreadPool>,
+    deadline: Duration,
+    partial_failure: PartialFailure,
+}
+
+impl Handler {
+    /// A handler resolving the questions of multi-question queries on
+    /// `resolvers`.
+    pub fn new(upstreams: Option<Upstreams>, resolvers: Arc<ThreadPool>) -> Self {
+        Self {
+            upstreams: upstreams.map(Arc::new),
+            recursor: None,
+            validator: None,
+            catalog: Arc::default(),
+            cache: Arc::default(),
+            acl: Acl::default(),
+            resolvers,
+            deadline: Duration::from_secs(5),
+            partial_failure: PartialFailure::ServFail,
+        }
+    }
+
+    /// Resolves queries iteratively from the root when there is nothing to
+    /// forward them to.
+    pub fn with_recursor(mut self, recursor: Recursor) -> Self {
+        self.recursor = Some(Arc::new(recursor));
+        self
+    }
+
+    /// Validates forwarded and recursive answers with DNSSEC, unless the
+    /// client sets CD.
+    pub fn with_validator(mut self, validator: Validator) -> Self {
+        self.validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Replaces the default cache of forwarded answers.
+    pub fn with_cache(mut self, cache: Cache) -> Self {
+        self.cache = Arc::new(Mutex::new(cache));
+        self
+    }
+
+    /// How long the questions of one query may take to resolve, together,
+    /// and what to answer when only some of them make it.
+    pub fn with_deadline(mut self, deadline: Duration, partial_failure: PartialFailure) -> Self {
+        self.deadline = deadline;
+        self.partial_failure = partial_failure;
+        self
+    }
+
+    /// Serves the zones in `catalog` authoritatively; names outside them are
+    /// forwarded if there is a resolver and refused otherwise.
+    pub fn with_catalog(mut self, catalog: Catalog) -> Self {
+        self.catalog = Arc::new(catalog);
+        self
+    }
+
+    /// Refuses clients the ACL doesn't permit.
+    pub fn with_acl(mut self, acl: Acl) -> Self {
+        self.acl = acl;
+        self
+    }
+
+    /// The zones we serve, shared with whatever keeps their signatures
+    /// fresh.
+    pub fn catalog(&self) -> Arc<Catalog> {
+        self.catalog.clone()
+    }
+
+    /// Answers one wire-format query of our own, which the ACL doesn't apply
+    /// to, forwarding it if we can and recursing otherwise. Only tests ask
+    /// questions without a listener.
+    #[cfg(test)]
+    pub(crate) fn handle(&self, query: &[u8]) -> Message {
+        let mode = match (&self.upstreams, &self.recursor) {
+            (Some(_), _) => Mode::Forward,
+            (None, Some(_)) => Mode::Recursive,
+            (None, None) => Mode::Authoritative,
+        };
+        self.answer(query, None, mode).0
+    }
+
+    /// Answers one wire-format query from `client` of a listener in `mode`.
+    /// Responses get no answer: replying to one could set two servers
+    /// answering each other forever, e.g. when the source is spoofed.
+    pub fn handle_from(&self, query: &[u8], client: IpAddr, mode: Mode) -> Option<Message> {
+        if is_response(query) {
+            debug!("Dropping a response from {}", client);
+            return None
+        }
+        Some(self.answer(query, Some(client), mode).0)
+    }
+
+    /// Like `handle_from`, but serialized to fit the UDP payload size the
+    /// client advertised, capped at our own.
+    pub fn handle_udp(&self, query: &[u8], client: IpAddr, mode: Mode) -> Option<BytesMut> {
+        if is_response(query) {
+            debug!("Dropping a response from {}", client);
+            return None
+        }
+        let (response, limit) = self.answer(query, Some(client), mode);
+        Some(response.serialize_truncated(limit.min(Edns::PAYLOAD_SIZE as usize)))
+    }
+
+    fn answer(&self, query: &[u8], client: Option<IpAddr>, mode: Mode) -> (Message, usize) {
+        let message = match Message::deserialize(query) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Malformed message: {}", e);
+                return (Message::format_error(query), 512);
+            }
+        };
+        debug!("Recvd message : {:?}", message);
+        let limit = message.udp_payload_size();
+        if let Some(client) = client.filter(|&c| !self.acl.permits(c)) {
+            info!("Refusing query from {}", client);
+            return (MessageBuilder::response_to(&message).set_rcode(Rcode::Refused).finish(), limit);
+        }
+        let query_edns = message.edns().cloned();
+        if let Some(edns) = query_edns.as_ref().filter(|e| e.version != 0) {
+            debug!("Unsupported EDNS version {}", edns.version);
+            let response = MessageBuilder::response_to(&message)
+                .set_edns(Edns::default())
+                .set_rcode(Rcode::BadVers)
+                .finish();
+            return (response, limit);
+        }
+        let mut response = self.respond(message, mode);
+        if let Some(query_edns) = query_edns {
+            // only the extended RCODE carries over; upstream options are
+            // between the upstream and us
+            let extended_rcode = response.edns().map_or(0, |e| e.extended_rcode);
+            let mut edns = Edns { extended_rcode, ..Default::default() };
+            edns.set_dnssec_ok(query_edns.dnssec_ok());
+            response = MessageBuilder::from(response).set_edns(edns).finish();
+        }
+        (response, limit)
+    }
+
+    fn respond(&self, message: Message, mode: Mode) -> Message {
+        let query = message.clone();
+        answer_or_servfail(&query, || self.respond_to(message, mode))
+    }
+
+    fn respond_to(&self, message: Message, mode: Mode) -> Message {
+        if let Some(response) = self.authoritative(&message) {
+            return response
+        }
+        if message.opcode() != Opcode::Query {
+            return MessageBuilder::response_to(&message).set_rcode(Rcode::NotImp).finish()
+        }
+        match (mode, &self.upstreams, &self.recursor) {
+            (Mode::Forward, Some(upstreams), _) => {
+                let upstreams = upstreams.clone();
+                return self.resolve(message, Arc::new(move |m, deadline| upstreams.query(m, deadline)))
+            }
+            (Mode::Recursive, _, Some(recursor)) => {
+                let recursor = recursor.clone();
+                return self.resolve(message, Arc::new(move |m, deadline| recursor.answer(m, deadline)))
+            }
+            // only a reload that took the resolver away from a running
+            // listener gets us here; guessing would be worse than failing
+            (Mode::Forward | Mode::Recursive, _, _) => {
+                return MessageBuilder::response_to(&message).set_rcode(Rcode::ServFail).finish()
+            }
+            _ => {}
+        }
+        let rcode = match self.catalog.is_empty() {
+            true => Rcode::NoError,
+            false => Rcode::Refused,
+        };
+        let response = MessageBuilder::response_to(&message).set_rcode(rcode);
+        match self.catalog.is_empty() {
+            true => response.add_answers(Answers::from_questions(&message.questions)).finish(),
+            false => response.finish(),
+        }
+    }
+
+    /// Answers each question of `message` with `resolve`, or from the cache.
+    fn resolve(&self, message: Message, resolve: Resolve) -> Message {
+        let failure = MessageBuilder::response_to(&message).set_rcode(Rcode::ServFail).finish();
+        // AD is our own verdict when we validate and only upstream's claim
+        // otherwise; either way only clients that asked for it get it
+        // (RFC 6840 5.7).
+        let dnssec_ok = message.edns().is_some_and(Edns::dnssec_ok);
+        let wants_ad = message.ad() || dnssec_ok;
+        let dnssec_types = message.questions.iter().any(|q| matches!(q.ty(), Ty::RRSIG | Ty::NSEC | Ty::NSEC3));
+        let (validated, resolve): (bool, Resolve) = match self.validator.clone().filter(|_| !message.cd()) {
+            Some(validator) => (true, Arc::new(move |m, deadline| validator.resolve(m, &|q| resolve(q, deadline)))),
+            None => (false, resolve),
+        };
+        let result = self.resolve_split(message, validated, resolve);
+        match result {
+            Ok(mut m) => {
+                debug!("response message : {:?}", m);
+                // we asked for signatures on the client's behalf
+                if !dnssec_ok && !dnssec_types {
+                    m.retain(|r| !matches!(r.ty(), Ty::RRSIG | Ty::NSEC | Ty::NSEC3));
+                }
+                let ad = m.ad() && wants_ad;
+                MessageBuilder::from(m).set_ad(ad).finish()
+            }
+            Err(e) => {
+                warn!("Upstream query failed: {:#}", e);
+                failure
+            }
+        }
+    }
+
+    /// Answers the questions from the cache, or else all at once on the
+    /// resolver pool, joining the responses in the order of the questions. A
+    /// lone question is resolved on the caller's thread. Whatever isn't
+    /// resolved by the deadline has failed, and the work on it gives up then
+    /// too.
+    ///
+    /// Unless `validated`, responses from upstream lack RRSIGs and haven't
+    /// been checked, so DNSSEC-aware clients always go upstream; with CD they
+    /// want unchecked answers in any case.
+    fn resolve_split(&self, message: Message, validated: bool, resolve: Resolve) -> anyhow::Result<Message> {
+        let deadline = Instant::now() + self.deadline;
+        let cacheable = !message.cd() && (validated || !message.edns().is_some_and(Edns::dnssec_ok));
+        let queries = message.split();
+        let mut responses = queries.iter().map(|_| None).collect::<Vec<_>>();
+        let (sender, receiver) = mpsc::channel();
+        for (i, m) in queries.iter().enumerate() {
+            if cacheable {
+                if let Some(hit) = self.cache.lock().unwrap_or_else(PoisonError::into_inner).get(m) {
+                    debug!("cache hit : {:?}", hit);
+                    responses[i] = Some(Ok(hit));
+                    continue
+                }
+            }
+            let (m, sender, resolve, cache) = (m.clone(), sender.clone(), resolve.clone(), self.cache.clone());
+            let job = move || {
+                let response = resolve(&m, deadline);
+                if let (true, Ok(response)) = (cacheable && Instant::now() < deadline, &response) {
+                    cache.lock().unwrap_or_else(PoisonError::into_inner).insert(response);
+                }
+                // the receiver is gone if we missed the deadline
+                let _ = sender.send((i, response));
+            };
+            if queries.len() == 1 {
+                job()
+            } else if !self.resolvers.try_execute(job) {
+                responses[i] = Some(Err(anyhow!("too many questions waiting to be resolved")));
+            }
+        }
+        drop(sender);
+        while responses.iter().any(Option::is_none) {
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((i, response)) => responses[i] = Some(response),
+                Err(_) => break,
+            }
+        }
+        let mut joined = vec![];
+        let mut failed = 0;
+        for (query, response) in queries.iter().zip(responses) {
+            let question = &query.questions[0];
+            match response.unwrap_or_else(|| Err(anyhow!("no answer within {:?}", self.deadline))) {
+                Ok(mut response) => {
+                    // the client's own spelling of the name
+                    response.questions = query.questions.clone();
+                    joined.push(response);
+                }
+                Err(e) if self.partial_failure == PartialFailure::ServFail || queries.len() == 1 => {
+                    return Err(e.context(format!("{}. {}", question.domain(), question.ty())))
+                }
+                Err(e) => {
+                    warn!("Leaving {}. {} unanswered: {:#}", question.domain(), question.ty(), e);
+                    failed += 1;
+                    joined.push(MessageBuilder::response_to(query).set_ra(true).finish());
+                }
+            }
+        }
+        if failed == queries.len() {
+            bail!("none of the {} questions could be resolved", failed)
+        }
+        Message::join(joined)
+    }
+
+    /// Answers a single-question query from the zone that contains it.
+    fn authoritative(&self, message: &Message) -> Option<Message> {
+        let [question] = &message.questions[..] else {
+            return None
+        };
+        if message.opcode() != Opcode::Query {
+            return None
+        }
+        let name = question.domain();
+        let mut zone = self.catalog.find(&name);
+        // the DS records of a zone we serve live in its parent, if we have that
+        if question.ty() == Ty::DS && zone.as_ref().is_some_and(|z| key(&z.apex()) == key(&name)) {
+            let parent = parent(&key(&name)).to_string();
+            zone = self.catalog.find(&parent).filter(|z| key(&z.apex()) != key(&name)).or(zone);
+        }
+        let zone = zone.filter(|z| z.class() == question.class())?;
+        let dnssec_ok = message.edns().is_some_and(Edns::dnssec_ok);
+        let found = zone.lookup(&name, question.ty(), dnssec_ok);
+        Some(MessageBuilder::response_to(message)
+            .set_aa(found.authoritative)
+            .set_rcode(found.rcode)
+            .add_answers(found.answers)
+            .add_authorities(found.authorities)
+            .add_additionals(found.additionals)
+            .finish())
+    }
+}
+
+/// The per-query error boundary: whatever goes wrong while answering
+/// `query`, even a panic, the client gets a response with its ID and
+/// question. The locks a panic may leave poisoned are all recovered, so the
+/// next query is answered as usual.
+fn answer_or_servfail(query: &Message, answer: impl FnOnce() -> Message) -> Message {
+    match panic::catch_unwind(AssertUnwindSafe(answer)) {
+        Ok(response) => response,
+        Err(_) => {
+            error!("Answering {:?} panicked", query.questions.iter().map(|q| q.domain()).collect::<Vec<_>>());
+            MessageBuilder::response_to(query).set_rcode(Rcode::ServFail).finish()
+        }
+    }
+}
+
+/// Whether the QR bit is set, read from the raw header so malformed messages
+/// are caught too.
+fn is_response(message: &[u8]) -> bool {
+    message.get(2).is_some_and(|flags| flags & 0b1000_0000 != 0)
+}
+
+/// Writes `message` with the 2-byte length prefix DNS over TCP uses, which
+/// can't describe messages over 65535 bytes.
+pub fn write_framed(stream: &mut TcpStream, message: &[u8]) -> std::io::Result<()> {
+    if message.len() > u16::MAX as usize {
+        let e = format!("a {} byte message doesn't fit a TCP frame", message.len());
+        return Err(std::io::Error::new(ErrorKind::InvalidInput, e))
+    }
+    let mut framed = BytesMut::with_capacity(message.len() + 2);
+    framed.put_u16(message.len() as u16);
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)
+}
+
+/// Reads one message written by `write_framed`, or `None` if the peer
+/// closed the connection instead of starting another.
+pub fn read_framed(stream: &mut TcpStream) -> std::io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 2];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+/// The handler the listeners answer with. Reloading the configuration
+/// swaps in a new one; queries already being answered finish with the one
+/// they started with.
+pub struct Service(RwLock<Arc<Handler>>);
+
+impl Service {
+    pub fn new(handler: Handler) -> Self {
+        Self(RwLock::new(Arc::new(handler)))
+    }
+
+    pub fn handler(&self) -> Arc<Handler> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// The resolver pool of the current handler, for the next one to share.
+    pub fn resolvers(&self) -> Arc<ThreadPool> {
+        self.handler().resolvers.clone()
+    }
+
+    pub fn replace(&self, handler: Handler) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(handler);
+    }
+}
+
+/// Reads datagrams and answers each one on `pool`, so a slow upstream only
+/// holds up its own query. When the pool is saturated we stop reading until
+/// a worker frees up. Errors on the socket are logged and reading goes on.
+pub fn serve_udp(udp_socket: UdpSocket, service: Arc<Service>, mode: Mode, pool: &ThreadPool) {
+    let udp_socket = Arc::new(udp_socket);
+    let mut buf = [0; Edns::MAX_UDP_PAYLOAD];
+    loop {
+        match udp_socket.recv_from(&mut buf) {
+            Ok((size, source)) => {
+                debug!("Received {} bytes from {}", size, source);
+                let query = buf[..size].to_vec();
+                let (socket, handler) = (udp_socket.clone(), service.handler());
+                pool.execute(move || {
+                    let Some(response) = handler.handle_udp(&query, source.ip(), mode) else {
+                        return
+                    };
+                    if let Err(e) = socket.send_to(&response, source) {
+                        warn!("Failed to send response to {}: {}", source, e);
+                    }
+                });
+            }
+            // e.g. an ICMP error for an earlier response; other clients are
+            // unaffected, so keep serving them
+            Err(e) => warn!("Error receiving data: {}", e),
+        }
+    }
+}
+
+/// Accepts TCP connections, each served by a worker of `pool` until the
+/// client closes it or stays silent for `idle_timeout`. The pool size caps
+/// the connections being served; further ones are accepted and wait, open,
+/// in the pool's queue. Only once that is full too do we stop accepting and
+/// leave them to the listen backlog.
+pub fn serve_tcp(listener: TcpListener, service: Arc<Service>, mode: Mode, idle_timeout: Duration, pool: &ThreadPool) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let service = service.clone();
+                pool.execute(move || {
+                    if let Err(e) = handle_connection(stream, &service, mode, idle_timeout) {
+                        warn!("TCP connection failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Error accepting connection: {}", e),
+        }
+    }
+}
+
+/// Reads 2-byte length prefixed queries (RFC 1035 4.2.2) until EOF, answering
+/// each one in order so pipelined queries get pipelined responses. Each
+/// query is answered by whichever handler is current when it arrives.
+fn handle_connection(mut stream: TcpStream, service: &Service, mode: Mode, idle_timeout: Duration) -> std::io::Result<()> {
+    let peer = stream.peer_addr()?;
+    stream.set_read_timeout(Some(idle_timeout))?;
+    loop {
+        let query = match read_framed(&mut stream) {
+            Ok(Some(query)) => query,
+            Ok(None) => return Ok(()),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                debug!("Closing idle connection from {}", peer);
+                return Ok(())
+            }
+            Err(e) => return Err(e),
+        };
+        debug!("Received {} bytes from {} over TCP", query.len(), peer);
+        if let Some(response) = service.handler().handle_from(&query, peer.ip(), mode) {
+            write_framed(&mut stream, &response.serialize_truncated(u16::MAX as usize))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use bytes::Bytes;
+    use crate::acl::Acl;
+    use crate::answer::Answer;
+    use crate::edns::{Edns, EdnsOption};
+    use crate::header::{Header, Opcode, Rcode};
+    use crate::message::{Message, MessageBuilder, Ty};
+    use crate::question::Question;
+    use crate::pool::ThreadPool;
+    use crate::server::{answer_or_servfail, read_framed, serve_tcp, serve_udp, write_framed, Handler, Mode, PartialFailure, Service};
+    use crate::upstream::Upstreams;
+    use crate::validator::Validator;
+    use crate::zone::{Catalog, Zone};
+
+    fn resolvers() -> Arc<ThreadPool> {
+        Arc::new(ThreadPool::new("resolve", 4, 16))
+    }
+
+    fn framed_query(id: u16, name: &str) -> Vec<u8> {
+        let query = MessageBuilder::new()
+            .set_qr(false)
+            .set_id(id)
+            .add_question(Question::from_domain_name(name))
+            .finish()
+            .serialize();
+        let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&query);
+        framed
+    }
+
+    fn read_response(stream: &mut TcpStream) -> Message {
+        Message::deserialize(&read_framed(stream).unwrap().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn pipelined_tcp_queries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = Arc::new(Service::new(Handler::new(None, resolvers())));
+        thread::spawn(move || serve_tcp(listener, service, Mode::Authoritative, Duration::from_millis(200), &ThreadPool::new("tcp", 2, 2)));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut both = framed_query(1, "one.example");
+        both.extend(framed_query(2, "two.example"));
+        stream.write_all(&both).unwrap();
+        let (first, second) = (read_response(&mut stream), read_response(&mut stream));
+        assert_eq!((first.id(), second.id()), (1, 2));
+        assert_eq!(second.answers[0].domain(), "two.example");
+
+        // the server hangs up once the connection has been idle for a while
+        let mut rest = vec![];
+        assert_eq!(stream.read_to_end(&mut rest).unwrap(), 0);
+    }
+
+    #[test]
+    fn truncated_upstream_response_is_retried_over_tcp() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (n, client) = udp.recv_from(&mut buf).unwrap();
+            let query = Message::deserialize(&buf[..n]).unwrap();
+            let header = Header { id: query.id(), tc: true, ..Default::default() };
+            let truncated = MessageBuilder::new().set_header(header).add_questions(query.questions).finish();
+            udp.send_to(&truncated.serialize(), client).unwrap();
+
+            let (mut stream, _) = tcp.accept().unwrap();
+            let query = Message::deserialize(&read_framed(&mut stream).unwrap().unwrap()).unwrap();
+            let full = MessageBuilder::new()
+                .set_id(query.id())
+                .add_answer(Answer::new("big.example", 1, 1, 60, 0x0a000001))
+                .add_questions(query.questions)
+                .finish();
+            write_framed(&mut stream, &full.serialize()).unwrap();
+        });
+
+        let handler = Handler::new(Some(Upstreams::new(vec![addr])), resolvers());
+        let query = MessageBuilder::new()
+            .set_id(7)
+            .add_question(Question::from_domain_name("big.example"))
+            .finish()
+            .serialize();
+        let response = handler.handle(&query);
+        assert!(!response.tc());
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].data().to_string(), "10.0.0.1");
+    }
+
+    #[test]
+    fn oversized_messages_are_not_framed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        let e = write_framed(&mut stream, &vec![0; u16::MAX as usize + 1]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        write_framed(&mut stream, &vec![7; u16::MAX as usize]).unwrap();
+        assert_eq!(read_framed(&mut peer).unwrap(), Some(vec![7; u16::MAX as usize]));
+        drop(stream);
+        assert_eq!(read_framed(&mut peer).unwrap(), None);
+    }
+
+    #[test]
+    fn edns_queries_get_edns_responses() {
+        let handler = Handler::new(None, resolvers());
+        let mut edns = Edns { payload_size: 4096, ..Default::default() };
+        edns.set_dnssec_ok(true);
+        let query = MessageBuilder::new()
+            .set_qr(false)
+            .add_questions((0..40).map(|i| Question::from_domain_name(&format!("host{i}.example"))))
+            .set_edns(edns.clone())
+            .finish();
+        let response = handler.handle(&query.clone().serialize());
+        let response_edns = response.edns().unwrap();
+        assert_eq!(response_edns.payload_size, Edns::PAYLOAD_SIZE);
+        assert!(response_edns.dnssec_ok());
+
+        // 40 answers need more than 512 bytes but fit into our 1232
+        let udp = handler.handle_udp(&query.serialize(), Ipv4Addr::LOCALHOST.into(), Mode::Authoritative).unwrap();
+        assert!(udp.len() > 512 && udp.len() <= Edns::PAYLOAD_SIZE as usize);
+        assert!(!Message::deserialize(&udp).unwrap().tc());
+
+        edns.version = 1;
+        let query = MessageBuilder::new().set_edns(edns).finish().serialize();
+        assert_eq!(handler.handle(&query).rcode(), Rcode::BadVers);
+    }
+
+    #[test]
+    fn upstream_edns_options_are_not_relayed() {
+        let addr = fake_upstream(|query| {
+            let cookie = EdnsOption { code: 10, data: Bytes::from_static(b"upstream-cookie!") };
+            Some(MessageBuilder::response_to(&query)
+                .add_answer(Answer::new("a.example", 1, 1, 60, 1))
+                .set_edns(Edns { payload_size: 4096, options: vec![cookie], ..Default::default() })
+                .finish())
+        });
+
+        let handler = Handler::new(Some(Upstreams::new(vec![addr])), resolvers());
+        let query = MessageBuilder::new()
+            .add_question(Question::from_domain_name("a.example"))
+            .set_edns(Edns::default())
+            .finish();
+        let response = handler.handle(&query.serialize());
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.edns(), Some(&Edns::default()));
+    }
+
+    #[test]
+    fn clients_outside_the_acl_are_refused() {
+        let acl = Acl { allow: vec!["192.0.2.0/24".parse().unwrap()], deny: vec![] };
+        let handler = Handler::new(None, resolvers()).with_acl(acl);
+        let query = MessageBuilder::new()
+            .set_qr(false)
+            .set_id(5)
+            .add_question(Question::from_domain_name("a.example"))
+            .finish()
+            .serialize();
+        let response = handler.handle_from(&query, "198.51.100.1".parse().unwrap(), Mode::Authoritative).unwrap();
+        assert_eq!((response.id(), response.rcode()), (5, Rcode::Refused));
+        assert_eq!(response.questions[0].domain(), "a.example");
+        assert!(response.answers.is_empty());
+        assert_eq!(handler.handle_from(&query, "192.0.2.1".parse().unwrap(), Mode::Authoritative).unwrap().rcode(), Rcode::NoError);
+        assert_eq!(handler.handle(&query).rcode(), Rcode::NoError);
+    }
+
+    #[test]
+    fn reloads_keep_in_flight_queries_on_the_old_handler() {
+        let service = Service::new(Handler::new(None, resolvers()));
+        let in_flight = service.handler();
+        service.replace(Handler::new(None, service.resolvers()).with_acl(Acl { allow: vec![], deny: vec!["0.0.0.0/0".parse().unwrap()] }));
+        assert!(Arc::ptr_eq(&in_flight.resolvers, &service.resolvers()));
+        let query = MessageBuilder::new().set_qr(false).add_question(Question::from_domain_name("a.example")).finish().serialize();
+        let client = Ipv4Addr::LOCALHOST.into();
+        assert_eq!(in_flight.handle_from(&query, client, Mode::Authoritative).unwrap().rcode(), Rcode::NoError);
+        assert_eq!(service.handler().handle_from(&query, client, Mode::Authoritative).unwrap().rcode(), Rcode::Refused);
+    }
+
+    #[test]
+    fn listeners_left_without_a_resolver_fail() {
+        let handler = Handler::new(None, resolvers());
+        let query = MessageBuilder::new().set_qr(false).add_question(Question::from_domain_name("a.example")).finish().serialize();
+        for mode in [Mode::Forward, Mode::Recursive] {
+            let response = handler.handle_from(&query, Ipv4Addr::LOCALHOST.into(), mode).unwrap();
+            assert_eq!((response.rcode(), response.answers.len()), (Rcode::ServFail, 0), "{}", mode);
+        }
+    }
+
+    /// A fake upstream sending back whatever `respond` makes of each query,
+    /// if anything. Queries are answered on threads of their own, so a slow
+    /// one doesn't hold up the rest.
+    fn fake_upstream(respond: impl Fn(Message) -> Option<Message> + Send + Sync + 'static) -> SocketAddr {
+        let udp = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let addr = udp.local_addr().unwrap();
+        let respond = Arc::new(respond);
+        thread::spawn(move || loop {
+            let mut buf = [0; 512];
+            let (n, client) = udp.recv_from(&mut buf).unwrap();
+            let query = Message::deserialize(&buf[..n]).unwrap();
+            let (udp, respond) = (udp.clone(), respond.clone());
+            thread::spawn(move || {
+                if let Some(response) = respond(query) {
+                    udp.send_to(&response.serialize(), client).unwrap();
+                }
+            });
+        });
+        addr
+    }
+
+    /// A fake resolver answering each name with 192.0.2.1 after the delay
+    /// `delay` gives for it, or not at all.
+    fn delayed_resolver(delay: impl Fn(&str) -> Option<Duration> + Send + Sync + 'static) -> SocketAddr {
+        fake_upstream(move |query| {
+            let name = query.questions[0].domain();
+            thread::sleep(delay(&name)?);
+            Some(MessageBuilder::response_to(&query).add_answer(Answer::new(&name, 1, 1, 60, 0xc0000201)).finish())
+        })
+    }
+
+    fn questions(names: &[&str]) -> Vec<u8> {
+        MessageBuilder::new()
+            .add_questions(names.iter().map(|name| Question::from_domain_name(name)))
+            .finish()
+            .serialize().to_vec()
+    }
+
+    #[test]
+    fn questions_are_forwarded_in_parallel_and_answered_in_order() {
+        let delays = [("one.example", 500), ("two.example", 300), ("three.example", 100)];
+        let addr = delayed_resolver(move |name| {
+            delays.iter().find(|(n, _)| *n == name).map(|(_, millis)| Duration::from_millis(*millis))
+        });
+        let handler = Handler::new(Some(Upstreams::new(vec![addr])), resolvers());
+        let start = Instant::now();
+        let response = handler.handle(&questions(&["one.example", "two.example", "three.example"]));
+        assert!(start.elapsed() < Duration::from_millis(850), "took {:?}", start.elapsed());
+        let asked = response.questions.iter().map(|q| q.domain()).collect::<Vec<_>>();
+        assert_eq!(asked, ["one.example", "two.example", "three.example"]);
+        let answered = response.answers.iter().map(|a| a.domain()).collect::<Vec<_>>();
+        assert_eq!(answered, ["one.example", "two.example", "three.example"]);
+    }
+
+    #[test]
+    fn partial_failures_are_servfail_or_partial_answers() {
+        let addr = delayed_resolver(|name| (name != "dead.example").then_some(Duration::ZERO));
+        let upstreams = || Some(Upstreams::new(vec![addr]).with_timeout(Duration::from_secs(2)));
+        let query = questions(&["dead.example", "live.example"]);
+
+        let strict = Handler::new(upstreams(), resolvers()).with_deadline(Duration::from_millis(200), PartialFailure::ServFail);
+        let start = Instant::now();
+        let response = strict.handle(&query);
+        assert!(start.elapsed() < Duration::from_secs(1), "took {:?}", start.elapsed());
+        assert_eq!((response.rcode(), response.questions.len()), (Rcode::ServFail, 2));
+        assert!(response.answers.is_empty());
+
+        let lenient = Handler::new(upstreams(), resolvers()).with_deadline(Duration::from_millis(200), PartialFailure::Partial);
+        let response = lenient.handle(&query);
+        assert_eq!(response.rcode(), Rcode::NoError);
+        let asked = response.questions.iter().map(|q| q.domain()).collect::<Vec<_>>();
+        assert_eq!(asked, ["dead.example", "live.example"]);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].domain(), "live.example");
+
+        let response = lenient.handle(&questions(&["dead.example", "dead.example"]));
+        assert_eq!(response.rcode(), Rcode::ServFail);
+    }
+
+    #[test]
+    fn work_past_the_deadline_is_abandoned() {
+        let asked = Arc::new(AtomicUsize::new(0));
+        let counter = asked.clone();
+        let addr = delayed_resolver(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            None
+        });
+        let upstreams = Upstreams::new(vec![addr]).with_timeout(Duration::from_millis(300)).with_attempts(3);
+        let handler = Handler::new(Some(upstreams), resolvers()).with_deadline(Duration::from_millis(200), PartialFailure::ServFail);
+        let response = handler.handle(&questions(&["one.example", "two.example"]));
+        assert_eq!(response.rcode(), Rcode::ServFail);
+        // without the deadline each question would go on to a second and third attempt
+        thread::sleep(Duration::from_millis(800));
+        assert_eq!(asked.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn questions_beyond_the_resolver_pool_fail_at_once() {
+        let addr = delayed_resolver(|_| Some(Duration::from_millis(200)));
+        let handler = Handler::new(Some(Upstreams::new(vec![addr])), Arc::new(ThreadPool::new("test", 1, 1)))
+            .with_deadline(Duration::from_secs(2), PartialFailure::Partial);
+        let names = ["a.example", "b.example", "c.example", "d.example"];
+        let response = handler.handle(&questions(&names));
+        assert_eq!((response.rcode(), response.questions.len()), (Rcode::NoError, 4));
+        // one question on the worker and at most one waiting for it
+        assert!((1..=2).contains(&response.answers.len()), "{} answers", response.answers.len());
+    }
+
+    #[test]
+    fn unsupported_opcode_is_not_implemented() {
+        let handler = Handler::new(None, resolvers());
+        let query = |opcode| MessageBuilder::new()
+            .set_opcode(opcode)
+            .add_question(Question::from_domain_name("a.example"))
+            .finish()
+            .serialize();
+        let response = handler.handle(&query(Opcode::Update));
+        assert_eq!((response.opcode(), response.rcode()), (Opcode::Update, Rcode::NotImp));
+        assert_eq!(handler.handle(&query(Opcode::Query)).rcode(), Rcode::NoError);
+    }
+
+    #[test]
+    fn unsupported_opcodes_are_not_forwarded() {
+        let asked = Arc::new(AtomicUsize::new(0));
+        let counter = asked.clone();
+        let addr = delayed_resolver(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Some(Duration::ZERO)
+        });
+        let handler = Handler::new(Some(Upstreams::new(vec![addr])), resolvers());
+        let query = MessageBuilder::new()
+            .set_id(0x1d)
+            .set_opcode(Opcode::Notify)
+            .add_question(Question::from_domain_name("a.example"))
+            .finish()
+            .serialize();
+        let response = handler.handle(&query);
+        assert_eq!((response.id(), response.rcode()), (0x1d, Rcode::NotImp));
+        assert_eq!(response.questions[0].domain(), "a.example");
+        assert_eq!(asked.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn a_panicking_query_gets_servfail() {
+        let query = MessageBuilder::new().set_id(0x2e).add_question(Question::from_domain_name("a.example")).finish();
+        let response = answer_or_servfail(&query, || panic!("answering failed"));
+        assert_eq!((response.id(), response.rcode()), (0x2e, Rcode::ServFail));
+        assert_eq!(response.questions[0].domain(), "a.example");
+    }
+
+    #[test]
+    fn a_poisoned_cache_keeps_answering() {
+        let asked = Arc::new(AtomicUsize::new(0));
+        let counter = asked.clone();
+        let addr = delayed_resolver(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Some(Duration::ZERO)
+        });
+        let handler = Arc::new(Handler::new(Some(Upstreams::new(vec![addr])), resolvers()));
+        let poisoner = handler.clone();
+        let _ = thread::spawn(move || {
+            let _cache = poisoner.cache.lock().unwrap();
+            panic!("poisoning the cache");
+        }).join();
+        assert!(handler.cache.is_poisoned());
+        for _ in 0..2 {
+            let response = handler.handle(&questions(&["a.example"]));
+            assert_eq!(response.rcode(), Rcode::NoError);
+            assert_eq!(response.answers[0].data().to_string(), "192.0.2.1");
+        }
+        // the second answer came from the cache
+        assert_eq!(asked.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn zones_are_served_authoritatively() {
+        let zone = Zone::parse("$TTL 60\n@ SOA ns hostmaster 1 2 3 4 5\nwww A 192.0.2.1\n", "example").unwrap();
+        let handler = Handler::new(None, resolvers()).with_catalog(Catalog::new(vec![zone]).unwrap());
+        let query = |name| MessageBuilder::new()
+            .add_question(Question::from_domain_name(name))
+            .finish()
+            .serialize();
+
+        let response = handler.handle(&query("www.example"));
+        assert!(response.aa());
+        assert_eq!(response.answers[0].data().to_string(), "192.0.2.1");
+
+        let response = handler.handle(&query("missing.example"));
+        assert_eq!((response.aa(), response.rcode()), (true, Rcode::NXDomain));
+        assert_eq!(response.authorities[0].domain(), "example");
+
+        let response = handler.handle(&query("www.elsewhere"));
+        assert_eq!((response.aa(), response.rcode()), (false, Rcode::Refused));
+
+        // the single label "www.example" is a name at the root, not www in example
+        let dotted = MessageBuilder::new()
+            .add_question(Question::new(b"\x0bwww.example\0", 1, 1))
+            .finish()
+            .serialize();
+        let response = handler.handle(&dotted);
+        assert_eq!((response.rcode(), response.answers.len()), (Rcode::Refused, 0));
+    }
+
+    #[test]
+    fn responses_are_never_answered() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap();
+        let service = Arc::new(Service::new(Handler::new(None, resolvers())));
+        thread::spawn(move || serve_udp(listener, service, Mode::Authoritative, &ThreadPool::new("udp", 1, 1)));
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+        let query = MessageBuilder::new().set_qr(false).set_id(3).add_question(Question::from_domain_name("a.example")).finish();
+
+        let response = MessageBuilder::response_to(&query).finish();
+        client.send_to(&response.serialize(), server).unwrap();
+        // a malformed one with QR set doesn't get FORMERR either
+        client.send_to(b"\x00\x04\x80", server).unwrap();
+        client.send_to(&query.serialize(), server).unwrap();
+        let mut buf = [0; 512];
+        let (n, _) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(Message::deserialize(&buf[..n]).unwrap().id(), 3);
+        assert!(client.recv_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn listeners_answer_in_their_own_mode() {
+        let elsewhere = Zone::parse("$TTL 60\n@ SOA ns hostmaster 1 2 3 4 5\nwww A 192.0.2.9\n", "elsewhere").unwrap();
+        let upstream = UdpSocket::bind("[::1]:0").unwrap();
+        let addr = upstream.local_addr().unwrap();
+        let authority = Arc::new(Service::new(Handler::new(None, resolvers()).with_catalog(Catalog::new(vec![elsewhere]).unwrap())));
+        thread::spawn(move || serve_udp(upstream, authority, Mode::Authoritative, &ThreadPool::new("auth", 1, 1)));
+
+        let example = Zone::parse("$TTL 60\n@ SOA ns hostmaster 1 2 3 4 5\nwww A 192.0.2.1\n", "example").unwrap();
+        let handler = Handler::new(Some(Upstreams::new(vec![addr])), resolvers()).with_catalog(Catalog::new(vec![example]).unwrap());
+        let service = Arc::new(Service::new(handler));
+        let ask = |listen: &str, mode, name| {
+            let listener = UdpSocket::bind(listen).unwrap();
+            let server = listener.local_addr().unwrap();
+            let service = service.clone();
+            thread::spawn(move || serve_udp(listener, service, mode, &ThreadPool::new("udp", 1, 1)));
+            let client = UdpSocket::bind(if server.is_ipv6() { "[::1]:0" } else { "127.0.0.1:0" }).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            let query = MessageBuilder::new().set_qr(false).add_question(Question::from_domain_name(name)).finish();
+            client.send_to(&query.serialize(), server).unwrap();
+            let mut buf = [0; 512];
+            let (n, _) = client.recv_from(&mut buf).unwrap();
+            Message::deserialize(&buf[..n]).unwrap()
+        };
+
+        let forwarded = ask("[::1]:0", Mode::Forward, "www.elsewhere");
+        assert_eq!(forwarded.answers[0].data().to_string(), "192.0.2.9");
+        let refused = ask("127.0.0.1:0", Mode::Authoritative, "www.elsewhere");
+        assert_eq!(refused.rcode(), Rcode::Refused);
+        let local = ask("127.0.0.1:0", Mode::Authoritative, "www.example");
+        assert_eq!(local.answers[0].data().to_string(), "192.0.2.1");
+    }
+
+    #[test]
+    fn forwarder_propagates_cd_and_filters_ad() {
+        // answers with AD set and echoes whether the query had CD in the answer
+        let addr = fake_upstream(|query| {
+            Some(MessageBuilder::response_to(&query)
+                .set_ad(true)
+                .add_answer(Answer::new("a.example", 1, 1, 60, query.cd() as u32))
+                .finish())
+        });
+
+        let handler = Handler::new(Some(Upstreams::new(vec![addr])), resolvers());
+        let query = |ad, cd| MessageBuilder::new()
+            .set_ad(ad)
+            .set_cd(cd)
+            .add_question(Question::from_domain_name("a.example"))
+            .finish()
+            .serialize();
+        let response = handler.handle(&query(false, true));
+        assert!(response.cd() && !response.ad());
+        assert_eq!(response.answers[0].data().to_string(), "0.0.0.1");
+        let response = handler.handle(&query(true, false));
+        assert!(!response.cd() && response.ad());
+        assert_eq!(response.answers[0].data().to_string(), "0.0.0.0");
+    }
+
+    #[test]
+    fn validated_answers_get_ad_and_bogus_ones_servfail() {
+        let dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/testdata/dnssec");
+        let zones = ["root", "example", "ed.example", "bogus"]
+            .iter()
+            .map(|name| Zone::load(&dir.join(format!("{}.zone", name)), None).unwrap())
+            .collect::<Vec<_>>();
+        let authority = Arc::new(Service::new(Handler::new(None, resolvers()).with_catalog(Catalog::new(zones).unwrap())));
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        thread::spawn(move || serve_udp(udp, authority, Mode::Authoritative, &ThreadPool::new("auth", 2, 8)));
+
+        let validator = Validator::from_file(&dir.join("anchor")).unwrap();
+        let handler = Handler::new(Some(Upstreams::new(vec![addr])), resolvers()).with_validator(validator);
+        let query = |name, dnssec_ok, cd| {
+            let mut edns = Edns::default();
+            edns.set_dnssec_ok(dnssec_ok);
+            let query = MessageBuilder::new()
+                .set_cd(cd)
+                .add_question(Question::from_domain_name(name))
+                .set_edns(edns)
+                .finish();
+            handler.handle(&query.serialize())
+        };
+        let response = query("www.example", true, false);
+        assert!(response.ad());
+        assert!(response.answers.iter().any(|a| a.ty() == Ty::RRSIG));
+        // the signatures were only for us
+        let response = query("www.example", false, false);
+        assert!(!response.ad());
+        assert_eq!(response.answers.len(), 1);
+
+        assert_eq!(query("www.bogus", true, false).rcode(), Rcode::ServFail);
+        // CD asks for the data regardless
+        let response = query("www.bogus", true, true);
+        assert_eq!((response.rcode(), response.ad()), (Rcode::NoError, false));
+        assert_eq!(response.answers[0].data().to_string(), "192.0.2.66");
+    }
+
+    #[test]
+    fn repeated_queries_are_answered_from_the_cache() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let counter = seen.clone();
+        let addr = fake_upstream(move |query| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Some(MessageBuilder::response_to(&query)
+                .add_answer(Answer::new("a.example", 1, 1, 300, 0x7f000001))
+                .finish())
+        });
+
+        let handler = Handler::new(Some(Upstreams::new(vec![addr])), resolvers());
+        let query = |id| MessageBuilder::new()
+            .set_id(id)
+            .add_question(Question::from_domain_name("a.example"))
+            .finish()
+            .serialize();
+        assert_eq!(handler.handle(&query(1)).answers.len(), 1);
+        let cached = handler.handle(&query(2));
+        assert_eq!((cached.id(), cached.answers[0].ttl()), (2, 300));
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn forwarder_drops_spoofed_and_mismatched_responses() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        thread::spawn(move || {
+            let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let mut buf = [0; 512];
+            let (n, client) = udp.recv_from(&mut buf).unwrap();
+            let query = Message::deserialize(&buf[..n]).unwrap();
+            let answer = |id, name: &str, data| MessageBuilder::response_to(&query)
+                .set_id(id)
+                .add_answer(Answer::new(name, 1, 1, 60, data))
+                .finish()
+                .serialize();
+            spoofer.send_to(&answer(query.id(), "a.example", 1), client).unwrap();
+            udp.send_to(&answer(query.id().wrapping_add(1), "a.example", 2), client).unwrap();
+            let mut other = query.clone();
+            other.questions[0] = Question::from_domain_name("b.example");
+            let wrong_question = MessageBuilder::response_to(&other).set_id(query.id()).finish();
+            udp.send_to(&wrong_question.serialize(), client).unwrap();
+            udp.send_to(&answer(query.id(), "A.EXAMPLE", 3), client).unwrap();
+        });
+
+        let handler = Handler::new(Some(Upstreams::new(vec![addr])), resolvers());
+        let query = MessageBuilder::new()
+            .set_id(4242)
+            .add_question(Question::from_domain_name("a.example"))
+            .finish();
+        let response = handler.handle(&query.serialize());
+        assert_eq!(response.id(), 4242);
+        assert_eq!(response.answers[0].data().to_string(), "0.0.0.3");
+    }
+
+    #[test]
+    fn unreachable_upstreams_mean_servfail() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = silent.local_addr().unwrap();
+        let upstreams = Upstreams::new(vec![addr]).with_timeout(Duration::from_millis(50));
+        let handler = Handler::new(Some(upstreams), resolvers());
+        let query = MessageBuilder::new()
+            .set_id(77)
+            .add_question(Question::from_domain_name("a.example"))
+            .finish();
+        let response = handler.handle(&query.serialize());
+        assert_eq!((response.id(), response.rcode()), (77, Rcode::ServFail));
+        assert_eq!(response.questions.len(), 1);
+    }
+
+    #[test]
+    fn slow_upstream_queries_do_not_block_others() {
+        let addr = delayed_resolver(|name| Some(Duration::from_millis(if name == "slow.example" { 500 } else { 0 })));
+
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap();
+        let service = Arc::new(Service::new(Handler::new(Some(Upstreams::new(vec![addr])), resolvers())));
+        thread::spawn(move || serve_udp(listener, service, Mode::Forward, &ThreadPool::new("udp", 4, 4)));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        for (id, name) in [(1, "slow.example"), (2, "fast.example")] {
+            let query = MessageBuilder::new().set_qr(false).set_id(id).add_question(Question::from_domain_name(name)).finish();
+            client.send_to(&query.serialize(), server).unwrap();
+        }
+        let mut buf = [0; 512];
+        let ids = (0..2)
+            .map(|_| {
+                let (n, _) = client.recv_from(&mut buf).unwrap();
+                Message::deserialize(&buf[..n]).unwrap().id()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [2, 1]);
+    }
+}
@@ -0,0 +1,51 @@
+//! SIGHUP handling without a libc binding. The handler only sets a flag,
+//! which whoever reloads the configuration polls.
+
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const SIGHUP: c_int = 1;
+
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+}
+
+extern "C" fn on_hangup(_: c_int) {
+    HANGUP.store(true, Ordering::SeqCst);
+}
+
+/// Records SIGHUPs from now on instead of letting them terminate us.
+pub fn catch_hangup() {
+    #[cfg(unix)]
+    // SAFETY: the handler only touches an atomic, which is async-signal-safe
+    unsafe {
+        signal(SIGHUP, on_hangup);
+    }
+}
+
+/// Whether a SIGHUP arrived since the last call.
+pub fn take_hangup() -> bool {
+    HANGUP.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::raw::c_int;
+    use crate::signal::{catch_hangup, take_hangup, SIGHUP};
+
+    extern "C" {
+        fn raise(signum: c_int) -> c_int;
+    }
+
+    #[test]
+    fn hangups_are_recorded_once() {
+        catch_hangup();
+        take_hangup();
+        assert_eq!(unsafe { raise(SIGHUP) }, 0);
+        assert!(take_hangup());
+        assert!(!take_hangup());
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context};
+use bytes::Bytes;
+use crate::answer::{Answer, Data};
+use crate::crypto::rsa;
+use crate::crypto::sha::{sha256, sha384};
+use crate::dnssec::{
+    canonical_cmp, curve25519, ds_digest, is_zone_key, key_tag, label_count, nsec3_hash, p256, p384, signed_data,
+    verify_signature, ECDSAP256SHA256, ECDSAP384SHA384, ED25519, MAX_NSEC3_ITERATIONS, NSEC3_SHA1, RSASHA256,
+    SECURE_ENTRY_POINT, SHA256,
+};
+use crate::encoding::{base32hex, from_base64};
+use crate::message::{Class, Labels, Ty};
+use crate::zone::{is_subdomain, key, parent, parse_records};
+
+/// How far back signatures are dated, for validators whose clocks are
+/// a little behind ours.
+const INCEPTION_SKEW: u32 = 60 * 60;
+/// TTL of a DNSKEY whose `.key` file gives none.
+const DNSKEY_TTL: u32 = 60 * 60;
+
+/// A zone key together with its private half.
+#[derive(Debug)]
+pub struct SigningKey {
+    dnskey: Answer,
+    private: PrivateKey,
+}
+
+enum PrivateKey {
+    Rsa(rsa::PrivateKey),
+    /// The big-endian scalar, for either curve.
+    Ecdsa(Vec<u8>),
+    /// The 32-byte seed of RFC 8032.
+    Ed25519(Vec<u8>),
+}
+
+impl Debug for PrivateKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PrivateKey(..)")
+    }
+}
+
+impl SigningKey {
+    /// Reads the `K<zone>.+<alg>+<tag>.key` and `.private` pair that BIND's
+    /// dnssec-keygen writes, given either file or their common base name.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let path = path.to_string_lossy();
+        let base = path.strip_suffix(".key").or_else(|| path.strip_suffix(".private")).unwrap_or(&path);
+        let read = |extension: &str| {
+            let path = format!("{}.{}", base, extension);
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path))
+        };
+        Self::parse(&read("key")?, &read("private")?).with_context(|| base.to_string())
+    }
+
+    /// Builds a key from the text of its `.key` file, which holds the DNSKEY
+    /// record, and of its `.private` file (`Private-key-format: v1.3`).
+    pub fn parse(public: &str, private: &str) -> anyhow::Result<Self> {
+        let dnskeys = parse_records(public, DNSKEY_TTL)?
+            .into_iter()
+            .filter(|r| r.ty() == Ty::DNSKEY)
+            .collect::<Vec<_>>();
+        let [dnskey] = &dnskeys[..] else {
+            bail!("expected one DNSKEY record, found {}", dnskeys.len())
+        };
+        let Data::DNSKEY { algorithm, public_key, .. } = dnskey.data() else {
+            unreachable!("filtered on the type")
+        };
+        if !is_zone_key(dnskey) {
+            bail!("the DNSKEY is not a usable zone key")
+        }
+        let fields = private
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect::<BTreeMap<_, _>>();
+        let field = |name: &str| match fields.get(name) {
+            Some(value) => from_base64(value).with_context(|| name.to_string()),
+            None => Err(anyhow!("the private key has no {} field", name)),
+        };
+        // "13 (ECDSAP256SHA256)"
+        let listed = fields.get("Algorithm").and_then(|a| a.split_whitespace().next()?.parse::<u8>().ok());
+        if listed != Some(*algorithm) {
+            bail!("the private key is not for algorithm {}", algorithm)
+        }
+        let private = match *algorithm {
+            RSASHA256 => {
+                let key = rsa::PrivateKey::new(&field("Modulus")?, &field("PublicExponent")?, &field("PrivateExponent")?);
+                PrivateKey::Rsa(key.ok_or_else(|| anyhow!("unusable RSA key"))?)
+            }
+            ECDSAP256SHA256 | ECDSAP384SHA384 => PrivateKey::Ecdsa(field("PrivateKey")?),
+            ED25519 => PrivateKey::Ed25519(field("PrivateKey")?),
+            other => bail!("can't sign with algorithm {}", other),
+        };
+        let key = Self { dnskey: dnskey.clone(), private };
+        // a mismatched pair would otherwise only show up as bogus answers
+        let probe = b"signing key check";
+        match key.sign(probe) {
+            Some(signature) if verify_signature(*algorithm, public_key, probe, &signature) => Ok(key),
+            _ => bail!("the private key does not belong to the DNSKEY"),
+        }
+    }
+
+    /// The zone the key belongs to, in `key` form.
+    pub fn owner(&self) -> String {
+        key(&self.dnskey.domain())
+    }
+
+    pub fn tag(&self) -> u16 {
+        key_tag(self.dnskey.data())
+    }
+
+    /// The DS record, with a SHA-256 digest, that points at this key.
+    pub fn ds(&self) -> Answer {
+        let digest = ds_digest(&self.owner(), self.dnskey.data(), SHA256).expect("SHA-256 is a known digest type");
+        let ds = Data::DS { key_tag: self.tag(), algorithm: self.algorithm(), digest_type: SHA256, digest: digest.into() };
+        Answer::with_data(self.dnskey.domain(), self.dnskey.class(), self.dnskey.ttl(), ds)
+    }
+
+    fn algorithm(&self) -> u8 {
+        match self.dnskey.data() {
+            Data::DNSKEY { algorithm, .. } => *algorithm,
+            _ => 0,
+        }
+    }
+
+    fn is_ksk(&self) -> bool {
+        matches!(self.dnskey.data(), Data::DNSKEY { flags, .. } if flags & SECURE_ENTRY_POINT != 0)
+    }
+
+    fn sign(&self, data: &[u8]) -> Option<Vec<u8>> {
+        match &self.private {
+            PrivateKey::Rsa(key) => Some(key.sign_sha256(&sha256(data))),
+            PrivateKey::Ecdsa(scalar) if self.algorithm() == ECDSAP256SHA256 => p256().sign(scalar, &sha256(data)),
+            PrivateKey::Ecdsa(scalar) => p384().sign(scalar, &sha384(data)),
+            PrivateKey::Ed25519(seed) => curve25519().sign(seed, data),
+        }
+    }
+}
+
+/// Signs a zone with its keys (RFC 4035 section 2): publishes the DNSKEYs,
+/// links the names with NSEC records, or NSEC3 ones if the apex has an
+/// NSEC3PARAM, and signs every authoritative RRset.
+#[derive(Debug)]
+pub struct Signer {
+    keys: Vec<SigningKey>,
+    /// Seconds the signatures stay valid.
+    validity: u32,
+}
+
+impl Signer {
+    /// `keys` must all belong to the zone this signer is used for.
+    pub fn new(keys: Vec<SigningKey>, validity: Duration) -> Self {
+        Self { keys, validity: validity.as_secs().min(u32::MAX as u64 / 2) as u32 }
+    }
+
+    /// When signatures made at `now` are due for a refresh: once only a
+    /// quarter of their validity is left.
+    pub fn refresh_at(&self, now: u32) -> u32 {
+        now.wrapping_add(self.validity - self.validity / 4)
+    }
+
+    /// The DS records to hand to the parent zone, for the keys that sign
+    /// the DNSKEY RRset.
+    pub fn ds_records(&self) -> Vec<Answer> {
+        self.keys_for(Ty::DNSKEY).map(SigningKey::ds).collect()
+    }
+
+    /// The records of the zone at `origin` with DNSKEYs, an NSEC or NSEC3
+    /// chain and RRSIGs added. Signatures and chains already in `records`
+    /// are dropped and made afresh.
+    pub fn sign(&self, origin: &str, records: Vec<Answer>, now: u32) -> anyhow::Result<Vec<Answer>> {
+        let mut records = records
+            .into_iter()
+            .filter(|r| !matches!(r.ty(), Ty::RRSIG | Ty::NSEC | Ty::NSEC3))
+            .collect::<Vec<_>>();
+        for signing_key in &self.keys {
+            let dnskey = &signing_key.dnskey;
+            if !records.iter().any(|r| r.data() == dnskey.data() && key(&r.domain()) == origin) {
+                records.push(dnskey.clone());
+            }
+        }
+        let soa = records
+            .iter()
+            .find(|r| r.ty() == Ty::SOA)
+            .ok_or_else(|| anyhow!("zone has no SOA record"))?;
+        let class = soa.class();
+        // NSEC and NSEC3 records live as long as a negative answer (RFC 9077)
+        let negative_ttl = match soa.data() {
+            Data::SOA { minimum, .. } => soa.ttl().min(*minimum),
+            _ => soa.ttl(),
+        };
+        let cuts = records
+            .iter()
+            .filter(|r| r.ty() == Ty::NS)
+            .map(|r| key(&r.domain()))
+            .filter(|name| name != origin)
+            .collect::<BTreeSet<_>>();
+        // glue below a zone cut is neither signed nor part of the chain
+        let is_glue = |name: &str| cuts.iter().any(|cut| name != cut && is_subdomain(name, cut));
+        let mut rrsets = BTreeMap::<(String, u16), Vec<Answer>>::new();
+        for record in records.iter().filter(|r| !is_glue(&key(&r.domain()))) {
+            rrsets.entry((key(&record.domain()), record.ty().into())).or_default().push(record.clone());
+        }
+        let mut owners = BTreeMap::<String, Vec<Ty>>::new();
+        for (name, ty) in rrsets.keys() {
+            owners.entry(name.clone()).or_default().push(Ty::from(*ty));
+        }
+        // at a cut only the DS belongs to us
+        rrsets.retain(|(name, ty), _| !cuts.contains(name) || *ty == u16::from(Ty::DS));
+        let signed_owners = rrsets.keys().map(|(name, _)| name.clone()).collect::<BTreeSet<_>>();
+
+        let nsec3param = records.iter().find_map(|r| match r.data() {
+            Data::NSEC3PARAM { hash_algorithm, iterations, salt, .. } if key(&r.domain()) == origin => {
+                Some((*hash_algorithm, *iterations, salt.clone()))
+            }
+            _ => None,
+        });
+        let chain = match nsec3param {
+            None => nsec_chain(&owners, class, negative_ttl),
+            Some((hash_algorithm, _, _)) if hash_algorithm != NSEC3_SHA1 => {
+                bail!("NSEC3PARAM has unknown hash algorithm {}", hash_algorithm)
+            }
+            Some((_, iterations, _)) if iterations > MAX_NSEC3_ITERATIONS => {
+                bail!("NSEC3PARAM has {} iterations, validators give up above {}", iterations, MAX_NSEC3_ITERATIONS)
+            }
+            Some((_, iterations, salt)) => {
+                nsec3_chain(origin, &owners, &signed_owners, (iterations, salt), class, negative_ttl)
+            }
+        };
+        for record in &chain {
+            rrsets.insert((key(&record.domain()), record.ty().into()), vec![record.clone()]);
+        }
+
+        let mut signatures = vec![];
+        for ((owner, ty), rrset) in &rrsets {
+            let ttl = rrset.iter().map(Answer::ttl).min().unwrap_or_default();
+            for signing_key in self.keys_for(Ty::from(*ty)) {
+                let mut rrsig = Data::RRSIG {
+                    type_covered: Ty::from(*ty),
+                    algorithm: signing_key.algorithm(),
+                    labels: label_count(owner) as u8,
+                    original_ttl: ttl,
+                    expiration: now.wrapping_add(self.validity),
+                    inception: now.wrapping_sub(INCEPTION_SKEW),
+                    key_tag: signing_key.tag(),
+                    signer: Labels::from_domain(origin),
+                    signature: Bytes::new(),
+                };
+                let signed = signing_key
+                    .sign(&signed_data(&rrsig, rrset))
+                    .ok_or_else(|| anyhow!("key {} failed to sign", signing_key.tag()))?;
+                if let Data::RRSIG { signature, .. } = &mut rrsig {
+                    *signature = signed.into();
+                }
+                signatures.push(Answer::with_data(rrset[0].domain(), class, ttl, rrsig));
+            }
+        }
+        records.extend(chain);
+        records.extend(signatures);
+        Ok(records)
+    }
+
+    /// Every algorithm signs every RRset (RFC 6840 section 5.11). Within
+    /// one, KSKs sign just the DNSKEY RRset and ZSKs everything else,
+    /// unless there are only keys of the one kind.
+    fn keys_for(&self, ty: Ty) -> impl Iterator<Item = &SigningKey> {
+        self.keys.iter().filter(move |signing_key| {
+            let has = |ksk: bool| self.keys.iter().any(|k| k.algorithm() == signing_key.algorithm() && k.is_ksk() == ksk);
+            match ty == Ty::DNSKEY {
+                true => signing_key.is_ksk() || !has(true),
+                false => !signing_key.is_ksk() || !has(false),
+            }
+        })
+    }
+}
+
+/// One NSEC per name in canonical order, the last pointing back at the
+/// apex (RFC 4034 section 4.1.1).
+fn nsec_chain(owners: &BTreeMap<String, Vec<Ty>>, class: Class, ttl: u32) -> Vec<Answer> {
+    let mut names = owners.keys().collect::<Vec<_>>();
+    names.sort_by(|a, b| canonical_cmp(a, b));
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let mut types = owners[*name].clone();
+            types.extend([Ty::RRSIG, Ty::NSEC]);
+            types.sort_by_key(|&t| u16::from(t));
+            let next = Labels::from_domain(names[(i + 1) % names.len()]);
+            Answer::with_data(name, class, ttl, Data::NSEC { next, types })
+        })
+        .collect()
+}
+
+/// One NSEC3 per name, empty non-terminals included, in hash order (RFC
+/// 5155 section 7.1). Only names with signed RRsets have the RRSIG bit,
+/// which leaves it off unsigned delegations.
+fn nsec3_chain(
+    origin: &str,
+    owners: &BTreeMap<String, Vec<Ty>>,
+    signed_owners: &BTreeSet<String>,
+    (iterations, salt): (u16, Bytes),
+    class: Class,
+    ttl: u32,
+) -> Vec<Answer> {
+    let mut names = owners.keys().cloned().collect::<BTreeSet<_>>();
+    for name in owners.keys() {
+        let mut ancestor = name.as_str();
+        while ancestor.len() > origin.len() {
+            ancestor = parent(ancestor);
+            names.insert(ancestor.to_string());
+        }
+    }
+    let mut hashed = names
+        .iter()
+        .map(|name| {
+            let mut types = owners.get(name).cloned().unwrap_or_default();
+            if signed_owners.contains(name) {
+                types.push(Ty::RRSIG);
+            }
+            types.sort_by_key(|&t| u16::from(t));
+            (nsec3_hash(name, &salt, iterations), types)
+        })
+        .collect::<Vec<_>>();
+    hashed.sort_by(|a, b| a.0.cmp(&b.0));
+    (0..hashed.len())
+        .map(|i| {
+            let (hash, types) = &hashed[i];
+            let owner = match origin {
+                "" => base32hex(hash).to_ascii_lowercase(),
+                origin => format!("{}.{}", base32hex(hash).to_ascii_lowercase(), origin),
+            };
+            let nsec3 = Data::NSEC3 {
+                hash_algorithm: NSEC3_SHA1,
+                flags: 0,
+                iterations,
+                salt: salt.clone(),
+                next_hashed: hashed[(i + 1) % hashed.len()].0.clone().into(),
+                types: types.clone(),
+            };
+            Answer::with_data(owner, class, ttl, nsec3)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::answer::{Answer, Data};
+    use crate::dnssec::{self, ds_matches};
+    use crate::edns::Edns;
+    use crate::header::Rcode;
+    use crate::message::{Class, Message, MessageBuilder, Ty};
+    use crate::pool::ThreadPool;
+    use crate::question::Question;
+    use crate::server::Handler;
+    use crate::signer::SigningKey;
+    use crate::validator::Validator;
+    use crate::zone::{Catalog, Zone};
+
+    const VALIDITY: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+    /// BIND-style key files made with pyca/cryptography: a P-256 KSK and
+    /// ZSK for example., an Ed25519 key for example.org. and an RSA one
+    /// for example.net.
+    fn testdata() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/testdata/signing")
+    }
+
+    fn keys(owner: &str) -> Vec<SigningKey> {
+        let mut keys = fs::read_dir(testdata())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|e| e == "key"))
+            .filter(|path| path.file_name().unwrap().to_string_lossy().starts_with(&format!("K{}.+", owner)))
+            .map(|path| SigningKey::load(&path).unwrap())
+            .collect::<Vec<_>>();
+        keys.sort_by_key(SigningKey::tag);
+        keys
+    }
+
+    fn ask(validator: &Validator, authority: &Handler, name: &str, ty: Ty) -> Message {
+        let mut edns = Edns::default();
+        edns.set_dnssec_ok(true);
+        let query = MessageBuilder::new()
+            .add_question(Question::with_type(name, ty, Class::IN))
+            .set_edns(edns)
+            .finish();
+        validator.resolve(&query, &|m| Ok(authority.handle(&m.clone().serialize()))).unwrap()
+    }
+
+    fn signatures<'a>(records: &'a [Answer], name: &'a str, ty: Ty) -> impl Iterator<Item = &'a Data> {
+        records
+            .iter()
+            .filter(move |r| r.domain() == name)
+            .map(Answer::data)
+            .filter(move |data| matches!(data, Data::RRSIG { type_covered, .. } if *type_covered == ty))
+    }
+
+    #[test]
+    fn loads_bind_key_files() {
+        let example = keys("example");
+        assert_eq!(example.iter().map(|k| (k.owner(), k.tag(), k.is_ksk())).collect::<Vec<_>>(), [
+            ("example".to_string(), 32741, true),
+            ("example".to_string(), 63602, false),
+        ]);
+        assert_eq!(keys("example.org")[0].owner(), "example.org");
+        // the base name works as well as either file, and RSA keys load too
+        let rsa = SigningKey::load(&testdata().join("Kexample.net.+008+45910")).unwrap();
+        assert_eq!(rsa.tag(), 45910);
+
+        let ds = example[0].ds();
+        assert!(ds_matches(ds.data(), &example[0].dnskey));
+        assert_eq!(ds.to_string().split(' ').take(6).collect::<Vec<_>>(), ["example.", "3600", "IN", "DS", "32741", "13"]);
+
+        let public = fs::read_to_string(testdata().join("Kexample.+013+32741.key")).unwrap();
+        let private = fs::read_to_string(testdata().join("Kexample.+013+63602.private")).unwrap();
+        let mismatched = SigningKey::parse(&public, &private).unwrap_err();
+        assert!(mismatched.to_string().contains("does not belong"), "{}", mismatched);
+        let private = private.replace("Algorithm: 13", "Algorithm: 14");
+        assert!(SigningKey::parse(&public, &private).is_err());
+    }
+
+    #[test]
+    fn signed_zones_validate() {
+        let zone = Zone::parse(
+            "$TTL 3600\n\
+            @ SOA ns hostmaster 1 7200 900 1209600 300\n\
+            @ NS ns\n\
+            @ NSEC3PARAM 1 0 0 ab12\n\
+            ns A 192.0.2.1\n\
+            www A 192.0.2.10\n\
+            alias CNAME www\n\
+            deep.a A 192.0.2.11\n\
+            sub NS ns.sub\n\
+            ns.sub A 192.0.2.53\n",
+            "example",
+        ).unwrap();
+        let catalog = Catalog::new(vec![zone]).unwrap().sign(keys("example"), VALIDITY).unwrap();
+        let anchors = catalog.ds_records();
+        // only the KSK signs the DNSKEY RRset, so only it goes to the parent
+        assert_eq!(anchors.len(), 1);
+        let validator = Validator::new(anchors);
+        let authority = Handler::new(None, Arc::new(ThreadPool::new("resolve", 1, 1))).with_catalog(catalog);
+
+        for (name, ty, rcode) in [
+            ("www.example", Ty::A, Rcode::NoError),
+            ("alias.example", Ty::A, Rcode::NoError),
+            ("example", Ty::DNSKEY, Rcode::NoError),
+            ("missing.example", Ty::A, Rcode::NXDomain),
+            ("www.example", Ty::AAAA, Rcode::NoError),
+            ("a.example", Ty::A, Rcode::NoError),
+            ("sub.example", Ty::DS, Rcode::NoError),
+        ] {
+            let response = ask(&validator, &authority, name, ty);
+            assert_eq!(response.rcode(), rcode, "{} {}", name, ty);
+            assert!(response.ad(), "{} {}", name, ty);
+        }
+
+        let zone = authority.catalog().find("example").unwrap();
+        let found = zone.lookup("example", Ty::DNSKEY, true);
+        let tags = signatures(&found.answers, "example", Ty::DNSKEY)
+            .map(|data| match data {
+                Data::RRSIG { key_tag, .. } => *key_tag,
+                _ => 0,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(tags, [32741]);
+        // the delegation and its glue belong to the child
+        let referral = zone.lookup("www.sub.example", Ty::A, true);
+        assert!(signatures(&referral.authorities, "sub.example", Ty::NS).next().is_none());
+        assert!(referral.authorities.iter().any(|r| r.ty() == Ty::NSEC3));
+        assert!(zone.lookup("ns.sub.example", Ty::A, true).answers.is_empty());
+    }
+
+    #[test]
+    fn wildcard_answers_validate() {
+        let records = "$TTL 3600\n@ SOA ns hostmaster 1 2 3 4 300\n@ NS ns\nns A 192.0.2.1\n*.wild A 192.0.2.20\n";
+        for (origin, chain) in [("example", "@ NSEC3PARAM 1 0 0 ab12\n"), ("example.org", "")] {
+            let zone = Zone::parse(&format!("{records}{chain}"), origin).unwrap();
+            let catalog = Catalog::new(vec![zone]).unwrap().sign(keys(origin), VALIDITY).unwrap();
+            let validator = Validator::new(catalog.ds_records());
+            let authority = Handler::new(None, Arc::new(ThreadPool::new("resolve", 1, 1))).with_catalog(catalog);
+            for (ty, answers) in [(Ty::A, 1), (Ty::AAAA, 0)] {
+                let name = format!("a.b.wild.{origin}");
+                let response = ask(&validator, &authority, &name, ty);
+                assert_eq!((response.rcode(), response.ad()), (Rcode::NoError, true), "{} {}", name, ty);
+                assert_eq!(response.answers.iter().filter(|a| a.ty() == ty).count(), answers, "{} {}", name, ty);
+            }
+        }
+    }
+
+    #[test]
+    fn nsec_signed_zones_are_refreshed() {
+        let zone = Zone::parse("$TTL 3600\n@ SOA ns hostmaster 1 2 3 4 300\n@ NS ns\nns A 192.0.2.1\n", "example.org").unwrap();
+        let catalog = Catalog::new(vec![zone]).unwrap().sign(keys("example.org"), VALIDITY).unwrap();
+        let validator = Validator::new(catalog.ds_records());
+        let authority = Handler::new(None, Arc::new(ThreadPool::new("resolve", 1, 1))).with_catalog(catalog);
+        let response = ask(&validator, &authority, "nothing.example.org", Ty::A);
+        assert_eq!((response.rcode(), response.ad()), (Rcode::NXDomain, true));
+        let chain = response.authorities.iter().filter(|r| r.ty() == Ty::NSEC).map(|r| r.data().to_string()).collect::<Vec<_>>();
+        assert_eq!(chain, ["ns.example.org. NS SOA RRSIG NSEC DNSKEY"]);
+
+        let inception = |catalog: &Catalog| {
+            let found = catalog.find("ns.example.org").unwrap().lookup("ns.example.org", Ty::A, true);
+            let inception = match signatures(&found.answers, "ns.example.org", Ty::A).next() {
+                Some(Data::RRSIG { inception, .. }) => *inception,
+                _ => panic!("no signature"),
+            };
+            inception
+        };
+        let catalog = authority.catalog();
+        let signed = inception(&catalog);
+        let now = dnssec::now();
+        catalog.refresh_signatures(now + 60);
+        assert_eq!(inception(&catalog), signed);
+        // a quarter of the validity left
+        let due = now + VALIDITY.as_secs() as u32 * 3 / 4;
+        catalog.refresh_signatures(due);
+        assert!(inception(&catalog) > signed);
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use anyhow::{anyhow, bail, Context};
+
+pub type Table = BTreeMap<String, Value>;
+
+/// The subset of TOML our configuration needs: tables, arrays of tables,
+/// strings, integers, booleans, arrays and inline tables. Dotted keys,
+/// floats and dates are rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    /// What kind of value this is, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+            Value::Table(_) => "a table",
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { ", " }, value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Table(table) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in table.iter().enumerate() {
+                    write!(f, "{}{} = {}", if i == 0 { " " } else { ", " }, key, value)?;
+                }
+                write!(f, " }}")
+            }
+        }
+    }
+}
+
+/// Parses a document into its root table.
+pub fn parse(text: &str) -> anyhow::Result<Table> {
+    let mut parser = Parser { text, pos: 0, line: 1 };
+    parser.document().with_context(|| format!("line {}", parser.line))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+/// Where `key = value` pairs currently go.
+enum Target {
+    Root,
+    Table(String),
+    /// The last table of an array of tables.
+    ArrayTable(String),
+}
+
+impl Parser<'_> {
+    fn document(&mut self) -> anyhow::Result<Table> {
+        let mut root = Table::new();
+        let mut target = Target::Root;
+        loop {
+            self.skip_blank_lines();
+            let Some(c) = self.peek() else {
+                return Ok(root)
+            };
+            if c == '[' {
+                self.pos += 1;
+                let array = self.eat('[');
+                self.skip_spaces();
+                let name = self.key()?;
+                self.skip_spaces();
+                self.expect(']')?;
+                if array {
+                    self.expect(']')?;
+                }
+                self.end_of_line()?;
+                target = match array {
+                    true => {
+                        let entry = root.entry(name.clone()).or_insert_with(|| Value::Array(vec![]));
+                        let Value::Array(tables) = entry else {
+                            bail!("{} is already defined", name)
+                        };
+                        if tables.iter().any(|t| !matches!(t, Value::Table(_))) {
+                            bail!("{} is already defined", name)
+                        }
+                        tables.push(Value::Table(Table::new()));
+                        Target::ArrayTable(name)
+                    }
+                    false => {
+                        if root.insert(name.clone(), Value::Table(Table::new())).is_some() {
+                            bail!("table {} is defined twice", name)
+                        }
+                        Target::Table(name)
+                    }
+                };
+                continue
+            }
+            let key = self.key()?;
+            self.skip_spaces();
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            self.end_of_line()?;
+            let table = match &target {
+                Target::Root => &mut root,
+                Target::Table(name) => match root.get_mut(name) {
+                    Some(Value::Table(table)) => table,
+                    _ => unreachable!("tables are created by their header"),
+                },
+                Target::ArrayTable(name) => match root.get_mut(name) {
+                    Some(Value::Array(tables)) => match tables.last_mut() {
+                        Some(Value::Table(table)) => table,
+                        _ => unreachable!("arrays of tables end in a table"),
+                    },
+                    _ => unreachable!("arrays of tables are created by their header"),
+                },
+            };
+            if table.insert(key.clone(), value).is_some() {
+                bail!("{} is defined twice", key)
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> anyhow::Result<()> {
+        match self.peek() {
+            Some(found) if found == c => {
+                self.next();
+                Ok(())
+            }
+            Some(found) => bail!("expected {:?}, found {:?}", c, found),
+            None => bail!("expected {:?} at end of input", c),
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.next();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.next();
+            }
+        }
+    }
+
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            if !(self.eat('\n') || self.eat('\r')) {
+                return
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> anyhow::Result<()> {
+        self.skip_spaces();
+        self.skip_comment();
+        self.eat('\r');
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.next();
+                Ok(())
+            }
+            Some(c) => bail!("unexpected {:?} after value", c),
+        }
+    }
+
+    fn key(&mut self) -> anyhow::Result<String> {
+        let key = match self.peek() {
+            Some('"') => self.basic_string()?,
+            Some('\'') => self.literal_string()?,
+            _ => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                    self.next();
+                }
+                if start == self.pos {
+                    match self.peek() {
+                        Some(c) => bail!("expected a key, found {:?}", c),
+                        None => bail!("expected a key at end of input"),
+                    }
+                }
+                self.text[start..self.pos].to_string()
+            }
+        };
+        if self.peek() == Some('.') {
+            bail!("dotted keys are not supported")
+        }
+        Ok(key)
+    }
+
+    fn value(&mut self) -> anyhow::Result<Value> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.basic_string()?)),
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some(_) => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || "+-_.:".contains(c)) {
+                    self.next();
+                }
+                let word = &self.text[start..self.pos];
+                match word {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    "" => bail!("expected a value, found {:?}", self.peek().unwrap_or_default()),
+                    _ => integer(word).map(Value::Integer),
+                }
+            }
+            None => bail!("expected a value at end of input"),
+        }
+    }
+
+    fn basic_string(&mut self) -> anyhow::Result<String> {
+        self.expect('"')?;
+        let mut out = String::new();
+        loop {
+            let c = match self.peek() {
+                Some('\n') | None => bail!("unterminated string"),
+                Some(c) => c,
+            };
+            self.next();
+            match c {
+                '"' => return Ok(out),
+                '\\' => out.push(match self.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some(c @ ('u' | 'U')) => {
+                        let digits = if c == 'u' { 4 } else { 8 };
+                        let hex = self.text.get(self.pos..self.pos + digits).ok_or_else(|| anyhow!("truncated escape"))?;
+                        self.pos += digits;
+                        u32::from_str_radix(hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| anyhow!("bad unicode escape \\{}{}", c, hex))?
+                    }
+                    Some(c) => bail!("unknown escape \\{}", c),
+                    None => bail!("unterminated string"),
+                }),
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> anyhow::Result<String> {
+        self.expect('\'')?;
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                Some('\n') | None => bail!("unterminated string"),
+                Some('\'') => break,
+                Some(_) => self.next(),
+            };
+        }
+        let text = self.text[start..self.pos].to_string();
+        self.next();
+        Ok(text)
+    }
+
+    fn array(&mut self) -> anyhow::Result<Value> {
+        self.expect('[')?;
+        let mut values = vec![];
+        loop {
+            self.skip_blank_lines();
+            if self.eat(']') {
+                return Ok(Value::Array(values))
+            }
+            values.push(self.value()?);
+            self.skip_blank_lines();
+            if !self.eat(',') {
+                self.skip_blank_lines();
+                self.expect(']')?;
+                return Ok(Value::Array(values))
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> anyhow::Result<Value> {
+        self.expect('{')?;
+        let mut table = Table::new();
+        self.skip_spaces();
+        if self.eat('}') {
+            return Ok(Value::Table(table))
+        }
+        loop {
+            self.skip_spaces();
+            let key = self.key()?;
+            self.skip_spaces();
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            if table.insert(key.clone(), value).is_some() {
+                bail!("{} is defined twice", key)
+            }
+            self.skip_spaces();
+            if !self.eat(',') {
+                self.expect('}')?;
+                return Ok(Value::Table(table))
+            }
+        }
+    }
+}
+
+/// Decimal, or hex, octal and binary with a `0x`, `0o` or `0b` prefix;
+/// underscores may separate digits.
+fn integer(word: &str) -> anyhow::Result<i64> {
+    let digits = word.replace('_', "");
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, digits.strip_prefix('+').unwrap_or(&digits)),
+    };
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x") => (16, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        Some("0b") => (2, &digits[2..]),
+        _ => (10, digits),
+    };
+    let n = i64::from_str_radix(digits, radix).map_err(|_| anyhow!("bad value {:?}", word))?;
+    Ok(if negative { -n } else { n })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toml::{parse, Table, Value};
+
+    #[test]
+    fn parses_tables_and_values() {
+        let root = parse(r#"
+            # comment
+            title = "dns"   # trailing comment
+            [cache]
+            entries = 10_000
+            enabled = true
+            mask = 0xff
+
+            [[zone]]
+            file = 'zones\example.zone'
+            [[zone]]
+            file = "b.zone"
+            origin = "bé."
+            servers = [
+                "1.1.1.1:53",  # first
+                "8.8.8.8:53",
+            ]
+            inline = { a = 1, b = [] }
+        "#).unwrap();
+        assert_eq!(root["title"], Value::String("dns".into()));
+        let Value::Table(cache) = &root["cache"] else { panic!() };
+        assert_eq!(cache["entries"], Value::Integer(10_000));
+        assert_eq!(cache["enabled"], Value::Boolean(true));
+        assert_eq!(cache["mask"], Value::Integer(255));
+        let Value::Array(zones) = &root["zone"] else { panic!() };
+        assert_eq!(zones.len(), 2);
+        let Value::Table(first) = &zones[0] else { panic!() };
+        assert_eq!(first["file"], Value::String(r"zones\example.zone".into()));
+        let Value::Table(second) = &zones[1] else { panic!() };
+        assert_eq!(second["origin"], Value::String("bé.".into()));
+        assert_eq!(second["servers"].to_string(), r#"["1.1.1.1:53", "8.8.8.8:53"]"#);
+        let mut inline = Table::new();
+        inline.insert("a".into(), Value::Integer(1));
+        inline.insert("b".into(), Value::Array(vec![]));
+        assert_eq!(second["inline"], Value::Table(inline));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = |text: &str| format!("{:#}", parse(text).unwrap_err());
+        assert_eq!(error("a = 1\nb = \"open\n"), "line 2: unterminated string");
+        assert_eq!(error("a = 1\na = 2"), "line 2: a is defined twice");
+        assert_eq!(error("[a]\n[a]"), "line 2: table a is defined twice");
+        assert_eq!(error("a.b = 1"), "line 1: dotted keys are not supported");
+        assert_eq!(error("a = 1.5"), "line 1: bad value \"1.5\"");
+        assert_eq!(error("a = 1 b"), "line 1: unexpected 'b' after value");
+    }
+}
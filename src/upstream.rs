@@ -0,0 +1,399 @@
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail};
+use crate::edns::Edns;
+use crate::header::Rcode;
+use crate::message::{Message, MessageBuilder};
+use crate::question::Question;
+use crate::server::{read_framed, write_framed};
+
+/// The resolvers we forward to, tried in order with failover.
+///
+/// Each attempt gets `timeout`; a query makes at most `attempts` of them,
+/// moving on to the next server after every failure. A server that fails
+/// `max_failures` times in a row is considered dead and skipped, except for
+/// one probe query every `probe_interval` that revives it if it answers.
+pub struct Upstreams {
+    servers: Vec<Upstream>,
+    timeout: Duration,
+    attempts: usize,
+    max_failures: u32,
+    probe_interval: Duration,
+}
+
+struct Upstream {
+    addr: SocketAddr,
+    health: Mutex<Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    /// Consecutive failures; reset by any success.
+    failures: u32,
+    last_attempt: Option<Instant>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum State {
+    Live,
+    /// Dead, but due for a probe.
+    Probe,
+    Dead,
+}
+
+impl Upstreams {
+    pub fn new(servers: Vec<SocketAddr>) -> Self {
+        Self {
+            servers: servers
+                .into_iter()
+                .map(|addr| Upstream { addr, health: Mutex::default() })
+                .collect(),
+            timeout: Duration::from_secs(2),
+            attempts: 3,
+            max_failures: 3,
+            probe_interval: Duration::from_secs(30),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_attempts(mut self, attempts: usize) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    pub fn with_health(mut self, max_failures: u32, probe_interval: Duration) -> Self {
+        self.max_failures = max_failures.max(1);
+        self.probe_interval = probe_interval;
+        self
+    }
+
+    /// Sends a single-question query to the first server that answers it
+    /// before `deadline`. SERVFAIL and REFUSED count as failures, so the next
+    /// server gets a go; an attempt cut short by the deadline doesn't count
+    /// against the server.
+    pub fn query(&self, query: &Message, deadline: Instant) -> anyhow::Result<Message> {
+        let mut last_error = anyhow!("no upstream servers configured");
+        for upstream in self.order().iter().cycle().take(self.attempts) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(last_error.context("out of time for upstream attempts"))
+            }
+            let timeout = self.timeout.min(remaining);
+            let result = forward(query, upstream.addr, Instant::now() + timeout).and_then(|response| match response.rcode() {
+                rcode @ (Rcode::ServFail | Rcode::Refused) => bail!("answered {}", rcode),
+                _ => Ok(response),
+            });
+            match result {
+                Ok(response) => {
+                    upstream.succeeded();
+                    return Ok(response)
+                }
+                Err(e) => {
+                    warn!("Upstream {} failed: {}", upstream.addr, e);
+                    if timeout == self.timeout {
+                        upstream.failed();
+                    }
+                    last_error = e.context(format!("upstream {}", upstream.addr));
+                }
+            }
+        }
+        Err(last_error.context("all upstream attempts failed"))
+    }
+
+    /// Servers due for a probe, then live ones, each in configured order.
+    /// The probe goes first because `state` has claimed it: were a live
+    /// server to answer before we got to it, it would be wasted and the dead
+    /// server never revived. Dead servers are only used when nothing else is
+    /// left.
+    fn order(&self) -> Vec<&Upstream> {
+        let now = Instant::now();
+        let states = self
+            .servers
+            .iter()
+            .map(|u| (u, u.state(now, self.max_failures, self.probe_interval)))
+            .collect::<Vec<_>>();
+        let with = |state| states.iter().filter(move |(_, s)| *s == state).map(|(u, _)| *u);
+        let order = with(State::Probe).chain(with(State::Live)).collect::<Vec<_>>();
+        match order.is_empty() {
+            true => with(State::Dead).collect(),
+            false => order,
+        }
+    }
+}
+
+impl Upstream {
+    /// Claims the probe when one is due, so concurrent queries don't all
+    /// pile onto a dead server at once.
+    fn state(&self, now: Instant, max_failures: u32, probe_interval: Duration) -> State {
+        let mut health = self.health.lock().unwrap_or_else(PoisonError::into_inner);
+        if health.failures < max_failures {
+            return State::Live
+        }
+        match health.last_attempt {
+            Some(last) if now.saturating_duration_since(last) < probe_interval => State::Dead,
+            _ => {
+                health.last_attempt = Some(now);
+                State::Probe
+            }
+        }
+    }
+
+    fn succeeded(&self) {
+        let mut health = self.health.lock().unwrap_or_else(PoisonError::into_inner);
+        if health.failures > 0 {
+            info!("Upstream {} is answering again", self.addr);
+        }
+        health.failures = 0;
+        health.last_attempt = Some(Instant::now());
+    }
+
+    fn failed(&self) {
+        let mut health = self.health.lock().unwrap_or_else(PoisonError::into_inner);
+        health.failures = health.failures.saturating_add(1);
+        health.last_attempt = Some(Instant::now());
+    }
+}
+
+/// Sends one single-question query upstream over UDP, falling back to TCP
+/// when the answer comes back truncated. Both have to be done by `deadline`,
+/// so the TCP retry only gets what the UDP attempt left over. The upstream
+/// query gets a fresh random ID, which is swapped back for the client's in
+/// the response.
+pub(crate) fn forward(m: &Message, socket_addr: SocketAddr, deadline: Instant) -> anyhow::Result<Message> {
+    debug!("forwarding message to {} : {:?}", socket_addr, m);
+    let query = MessageBuilder::from(m.clone()).set_id(rand::random()).set_qr(false).finish();
+    let remaining = || match deadline.saturating_duration_since(Instant::now()) {
+        remaining if remaining.is_zero() => Err(anyhow!("out of time")),
+        remaining => Ok(remaining),
+    };
+    let (mut response, _) = query_udp(&query, socket_addr, remaining()?)?;
+    if response.tc() {
+        debug!("upstream response truncated, retrying over TCP");
+        (response, _) = query_tcp(&query, socket_addr, remaining()?)?;
+    }
+    Ok(MessageBuilder::from(response).set_id(m.id()).finish())
+}
+
+/// Sends `query` from a socket of its own on a random ephemeral port and
+/// waits for the matching response, dropping any datagram that doesn't
+/// come from the resolver or doesn't answer this query. The response comes
+/// with the datagram it arrived in.
+pub fn query_udp(query: &Message, socket_addr: SocketAddr, timeout: Duration) -> anyhow::Result<(Message, Vec<u8>)> {
+    let local: SocketAddr = match socket_addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.send_to(&query.clone().serialize(), socket_addr)?;
+    let deadline = Instant::now() + timeout;
+    // room for whatever the query allows, should that be more than usual
+    let advertised = query.edns().map_or(0, |edns| edns.payload_size as usize);
+    let mut buf = vec![0; Edns::MAX_UDP_PAYLOAD.max(advertised)];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            bail!("no response within {:?}", timeout)
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let (n, source) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if source != socket_addr {
+            warn!("Dropping datagram from unexpected source {}", source);
+            continue
+        }
+        match Message::deserialize(&buf[..n]) {
+            Ok(response) if is_response_to(&response, query) => return Ok((response, buf[..n].to_vec())),
+            Ok(response) => warn!("Dropping response {} that doesn't match query {}", response.id(), query.id()),
+            Err(e) => warn!("Dropping malformed response from {}: {}", source, e),
+        }
+    }
+}
+
+/// Sends `query` over a fresh TCP connection and reads back one response,
+/// along with its wire format.
+pub fn query_tcp(query: &Message, socket_addr: SocketAddr, timeout: Duration) -> anyhow::Result<(Message, Vec<u8>)> {
+    let mut stream = TcpStream::connect_timeout(&socket_addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    write_framed(&mut stream, &query.clone().serialize())?;
+    let buf = read_framed(&mut stream)?.ok_or_else(|| anyhow!("connection closed without a response"))?;
+    let response = Message::deserialize(&buf)?;
+    if !is_response_to(&response, query) {
+        bail!("TCP response {} doesn't match query {}", response.id(), query.id())
+    }
+    Ok((response, buf))
+}
+
+/// A response matches when it has the query's ID and repeats its question;
+/// names are compared case-insensitively, label by label.
+fn is_response_to(response: &Message, query: &Message) -> bool {
+    let same = |a: &Question, b: &Question| {
+        a.labels().to_lowercase().to_bytes() == b.labels().to_lowercase().to_bytes()
+            && a.ty() == b.ty()
+            && a.class() == b.class()
+    };
+    response.qr()
+        && response.id() == query.id()
+        && response.questions.len() == query.questions.len()
+        && response.questions.iter().zip(query.questions.iter()).all(|(a, b)| same(a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener, UdpSocket};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::answer::Answer;
+    use crate::header::Rcode;
+    use crate::message::{Message, MessageBuilder};
+    use crate::question::Question;
+    use crate::header::Header;
+    use crate::upstream::{forward, is_response_to, State, Upstreams};
+
+    /// A fake resolver that counts queries and answers them while `answer`
+    /// says so, staying silent otherwise.
+    fn resolver(answer: impl Fn(usize) -> Option<Rcode> + Send + 'static) -> (SocketAddr, Arc<AtomicUsize>) {
+        resolver_on("127.0.0.1:0", answer)
+    }
+
+    fn resolver_on(bind: &str, answer: impl Fn(usize) -> Option<Rcode> + Send + 'static) -> (SocketAddr, Arc<AtomicUsize>) {
+        let udp = UdpSocket::bind(bind).unwrap();
+        let addr = udp.local_addr().unwrap();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let counter = seen.clone();
+        thread::spawn(move || loop {
+            let mut buf = [0; 512];
+            let (n, client) = udp.recv_from(&mut buf).unwrap();
+            let Some(rcode) = answer(counter.fetch_add(1, Ordering::SeqCst)) else {
+                continue
+            };
+            let query = Message::deserialize(&buf[..n]).unwrap();
+            let response = MessageBuilder::response_to(&query)
+                .set_rcode(rcode)
+                .add_answer(Answer::new("a.example", 1, 1, 60, 0x7f000001))
+                .finish();
+            udp.send_to(&response.serialize(), client).unwrap();
+        });
+        (addr, seen)
+    }
+
+    fn soon() -> Instant {
+        Instant::now() + Duration::from_secs(10)
+    }
+
+    fn query() -> Message {
+        MessageBuilder::new().set_id(9).add_question(Question::from_domain_name("a.example")).finish()
+    }
+
+    #[test]
+    fn fails_over_and_skips_dead_servers() {
+        let (silent, silent_seen) = resolver(|_| None);
+        let (broken, broken_seen) = resolver(|_| Some(Rcode::ServFail));
+        let (good, good_seen) = resolver(|_| Some(Rcode::NoError));
+        let upstreams = Upstreams::new(vec![silent, broken, good])
+            .with_timeout(Duration::from_millis(100))
+            .with_health(1, Duration::from_secs(60));
+
+        let response = upstreams.query(&query(), soon()).unwrap();
+        assert_eq!((response.id(), response.rcode()), (9, Rcode::NoError));
+        upstreams.query(&query(), soon()).unwrap();
+        assert_eq!(silent_seen.load(Ordering::SeqCst), 1);
+        assert_eq!(broken_seen.load(Ordering::SeqCst), 1);
+        assert_eq!(good_seen.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn dead_servers_are_probed_and_revived() {
+        // silent for the first query, fine afterwards
+        let (flaky, flaky_seen) = resolver(|n| (n > 0).then_some(Rcode::NoError));
+        let upstreams = Upstreams::new(vec![flaky])
+            .with_timeout(Duration::from_millis(100))
+            .with_attempts(1)
+            .with_health(1, Duration::ZERO);
+        let state = || upstreams.servers[0].state(Instant::now(), 1, Duration::from_secs(60));
+
+        assert!(upstreams.query(&query(), soon()).is_err());
+        assert_eq!(state(), State::Dead);
+        // with a zero probe interval the very next query is a probe
+        upstreams.query(&query(), soon()).unwrap();
+        assert_eq!(state(), State::Live);
+        assert_eq!(flaky_seen.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn dead_primary_is_revived_while_the_backup_answers() {
+        // silent for the first query, fine afterwards
+        let (primary, primary_seen) = resolver(|n| (n > 0).then_some(Rcode::NoError));
+        let (backup, backup_seen) = resolver(|_| Some(Rcode::NoError));
+        let upstreams = Upstreams::new(vec![primary, backup])
+            .with_timeout(Duration::from_millis(100))
+            .with_health(1, Duration::from_millis(200));
+
+        upstreams.query(&query(), soon()).unwrap();
+        upstreams.query(&query(), soon()).unwrap();
+        assert_eq!((primary_seen.load(Ordering::SeqCst), backup_seen.load(Ordering::SeqCst)), (1, 2));
+
+        thread::sleep(Duration::from_millis(250));
+        upstreams.query(&query(), soon()).unwrap();
+        assert_eq!(primary_seen.load(Ordering::SeqCst), 2);
+        upstreams.query(&query(), soon()).unwrap();
+        assert_eq!((primary_seen.load(Ordering::SeqCst), backup_seen.load(Ordering::SeqCst)), (3, 2));
+    }
+
+    #[test]
+    fn forwards_to_ipv6_servers() {
+        let (v6, v6_seen) = resolver_on("[::1]:0", |_| Some(Rcode::NoError));
+        let upstreams = Upstreams::new(vec![v6]).with_timeout(Duration::from_secs(1));
+        assert_eq!(upstreams.query(&query(), soon()).unwrap().answers.len(), 1);
+        assert_eq!(v6_seen.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn responses_must_repeat_the_question_label_by_label() {
+        let upper = MessageBuilder::new().set_id(9).add_question(Question::from_domain_name("A.Example")).finish();
+        assert!(is_response_to(&MessageBuilder::response_to(&upper).finish(), &query()));
+        // the single label "a.example" only prints like a.example
+        let dotted = MessageBuilder::new().set_id(9).add_question(Question::new(b"\x09a.example\0", 1, 1)).finish();
+        assert!(!is_response_to(&MessageBuilder::response_to(&dotted).finish(), &query()));
+    }
+
+    #[test]
+    fn all_failures_are_an_error() {
+        let (silent, _) = resolver(|_| None);
+        let upstreams = Upstreams::new(vec![silent]).with_timeout(Duration::from_millis(50)).with_attempts(2);
+        let err = upstreams.query(&query(), soon()).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("all upstream attempts failed: upstream 127.0.0.1:"));
+    }
+
+    #[test]
+    fn tcp_retries_only_get_the_time_left() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        // connections sit in the backlog and never get an answer
+        let _tcp = TcpListener::bind(addr).unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (n, client) = udp.recv_from(&mut buf).unwrap();
+            let query = Message::deserialize(&buf[..n]).unwrap();
+            thread::sleep(Duration::from_millis(200));
+            let header = Header { id: query.id(), qr: true, tc: true, ..Default::default() };
+            let truncated = MessageBuilder::new().set_header(header).add_questions(query.questions).finish();
+            udp.send_to(&truncated.serialize(), client).unwrap();
+        });
+
+        let query = MessageBuilder::new().set_qr(false).add_question(Question::from_domain_name("big.example")).finish();
+        let start = Instant::now();
+        assert!(forward(&query, addr, start + Duration::from_millis(400)).is_err());
+        assert!(start.elapsed() < Duration::from_millis(550), "took {:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,544 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use anyhow::bail;
+use crate::answer::{Answer, Data};
+use crate::dnssec::{self, Denial};
+use crate::edns::Edns;
+use crate::encoding::from_hex;
+use crate::header::Rcode;
+use crate::message::{Class, Message, MessageBuilder, Ty};
+use crate::question::Question;
+use crate::zone::{is_subdomain, key, load_records, parent};
+
+/// The root zone's KSK-2017, as IANA publishes it in root-anchors.xml.
+const ROOT_ANCHOR: (u16, u8, u8, &str) = (20326, 8, 2, "E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D");
+/// Longest we trust validated keys (or their absence) without asking again.
+const MAX_KEY_TTL: u32 = 60 * 60;
+/// How long a failure to validate a zone's keys is remembered, so a broken
+/// zone doesn't cost a full chain walk on every query.
+const BOGUS_TTL: Duration = Duration::from_secs(60);
+
+/// Validates answers with DNSSEC (RFC 4035 section 5), building the chain
+/// of trust down from the configured trust anchors.
+///
+/// Secure answers get AD set; answers that should be signed but whose
+/// signatures or denial proofs don't check out are bogus and become
+/// SERVFAIL. Names under no anchor, or below an unsigned delegation, are
+/// insecure and passed through as they are.
+pub struct Validator {
+    /// DS or DNSKEY records for the zones we trust without proof.
+    anchors: Vec<Answer>,
+    /// What each name we walked past on the way down from an anchor turned
+    /// out to be.
+    cuts: Mutex<HashMap<String, Cached>>,
+}
+
+struct Cached {
+    cut: Cut,
+    expires: Instant,
+}
+
+#[derive(Debug, Clone)]
+enum Cut {
+    /// A signed zone starts here; these are its validated zone keys.
+    Secure(Vec<Answer>),
+    /// The parent proved there is no DS here: everything below is unsigned.
+    Insecure,
+    /// No zone cut at this name.
+    None,
+    Bogus(String),
+}
+
+/// The zone a name is in, as far as validation is concerned.
+enum Trust {
+    Secure { zone: String, keys: Vec<Answer> },
+    Insecure,
+    Bogus(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Security {
+    Secure,
+    Insecure,
+    Bogus(String),
+}
+
+/// Sends a query on towards the rest of the DNS.
+pub type Resolve<'a> = &'a dyn Fn(&Message) -> anyhow::Result<Message>;
+
+impl Default for Validator {
+    fn default() -> Self {
+        let (key_tag, algorithm, digest_type, digest) = ROOT_ANCHOR;
+        let digest = from_hex(digest).expect("root anchor digest").into();
+        let anchor = Data::DS { key_tag, algorithm, digest_type, digest };
+        Self::new(vec![Answer::with_data(".", Class::IN, 0, anchor)])
+    }
+}
+
+impl Validator {
+    pub fn new(anchors: Vec<Answer>) -> Self {
+        Self { anchors, cuts: Mutex::default() }
+    }
+
+    /// Reads trust anchors from a master file of DS and DNSKEY records.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let anchors = load_records(path)?
+            .into_iter()
+            .filter(|r| matches!(r.ty(), Ty::DS | Ty::DNSKEY))
+            .collect::<Vec<_>>();
+        if anchors.is_empty() {
+            bail!("{} has no DS or DNSKEY records", path.display())
+        }
+        Ok(Self::new(anchors))
+    }
+
+    /// Answers a single-question query through `resolve`, asking for
+    /// signatures but not for upstream's own validation, and validates the
+    /// response.
+    pub fn resolve(&self, query: &Message, resolve: Resolve) -> anyhow::Result<Message> {
+        let [question] = &query.questions[..] else {
+            bail!("expected exactly one question")
+        };
+        let mut edns = query.edns().cloned().unwrap_or_default();
+        edns.set_dnssec_ok(true);
+        let upstream = MessageBuilder::from(query.clone()).set_cd(true).set_edns(edns).finish();
+        let response = resolve(&upstream)?;
+        let secure = match self.validate(&response, question, resolve) {
+            Security::Secure => true,
+            Security::Insecure => false,
+            Security::Bogus(reason) => {
+                warn!("Bogus answer for {}. {}: {}", question.domain(), question.ty(), reason);
+                return Ok(MessageBuilder::response_to(query).set_ra(true).set_rcode(Rcode::ServFail).finish())
+            }
+        };
+        let mut builder = MessageBuilder::from(response).set_cd(query.cd()).set_ad(secure);
+        if query.edns().is_none() {
+            builder = builder.clear_edns();
+        }
+        Ok(builder.finish())
+    }
+
+    /// Checks every signed RRset of `response`, and for negative answers
+    /// (also at the end of a CNAME chain) the proof of nonexistence.
+    pub fn validate(&self, response: &Message, question: &Question, resolve: Resolve) -> Security {
+        if !matches!(response.rcode(), Rcode::NoError | Rcode::NXDomain) {
+            return Security::Insecure
+        }
+        let now = dnssec::now();
+        let authorities = response
+            .authorities
+            .iter()
+            .filter(|r| matches!(r.ty(), Ty::SOA | Ty::NSEC | Ty::NSEC3 | Ty::RRSIG))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut security = Security::Secure;
+        for (rrset, signatures) in rrsets(&response.answers).iter().chain(rrsets(&authorities).iter()) {
+            security = security.and(self.check_rrset(rrset, signatures, &authorities, now, resolve));
+        }
+
+        let qtype = question.ty();
+        let mut name = key(&question.domain());
+        for _ in 0..response.answers.len() {
+            let cname = response.answers.iter().find_map(|a| match a.data() {
+                Data::CNAME(target) if key(&a.domain()) == name && qtype != Ty::CNAME => Some(key(&target.to_string())),
+                _ => None,
+            });
+            match cname {
+                Some(target) => name = target,
+                None => break,
+            }
+        }
+        let answered = response
+            .answers
+            .iter()
+            .any(|a| key(&a.domain()) == name && (a.ty() == qtype || (qtype == Ty::ANY && a.ty() != Ty::RRSIG)));
+        if answered {
+            return security
+        }
+        let zone_of = if qtype == Ty::DS { parent(&name).to_string() } else { name.clone() };
+        let proof = match self.trust(&zone_of, resolve) {
+            Trust::Secure { zone, .. } => authorities
+                .iter()
+                .filter(|r| matches!(r.ty(), Ty::NSEC | Ty::NSEC3) && is_subdomain(&key(&r.domain()), &zone))
+                .cloned()
+                .collect::<Vec<_>>(),
+            Trust::Insecure => return security.and(Security::Insecure),
+            Trust::Bogus(reason) => return Security::Bogus(reason),
+        };
+        let denial = match response.rcode() {
+            Rcode::NXDomain => dnssec::prove_nxdomain(&name, &proof),
+            _ => dnssec::prove_nodata(&name, qtype, &proof),
+        };
+        security.and(match denial {
+            Denial::Proven => Security::Secure,
+            Denial::Insecure => Security::Insecure,
+            Denial::Unproven => Security::Bogus(format!("no proof that {}. {} doesn't exist", name, qtype)),
+        })
+    }
+
+    /// Verifies one RRset against the keys of the zone it belongs to, plus
+    /// the proof that the name didn't exist if it came from a wildcard.
+    fn check_rrset(&self, rrset: &[Answer], signatures: &[Answer], proof: &[Answer], now: u32, resolve: Resolve) -> Security {
+        let (owner, ty) = (key(&rrset[0].domain()), rrset[0].ty());
+        // DS records are the parent's, as are NSEC records at a cut proving
+        // their absence; NSEC3 owners are hashes just below their zone
+        let delegated = matches!(ty, Ty::DS | Ty::NSEC3) || (ty == Ty::NSEC && dnssec::is_delegation(&owner, rrset));
+        let signer_zone = if delegated { parent(&owner).to_string() } else { owner.clone() };
+        let (zone, keys) = match self.trust(&signer_zone, resolve) {
+            Trust::Secure { zone, keys } => (zone, keys),
+            Trust::Insecure => return Security::Insecure,
+            Trust::Bogus(reason) => return Security::Bogus(reason),
+        };
+        let valid = signatures.iter().find(|sig| {
+            matches!(sig.data(), Data::RRSIG { signer, .. } if key(&signer.to_string()) == zone)
+                && dnssec::verify_rrset(rrset, sig, &keys, now)
+        });
+        let Some(Data::RRSIG { labels, .. }) = valid.map(Answer::data) else {
+            return Security::Bogus(format!("no valid signature by {}. over {}. {}", zone, owner, ty))
+        };
+        if (*labels as usize) < dnssec::label_count(&owner) {
+            let proof = proof.iter().filter(|r| matches!(r.ty(), Ty::NSEC | Ty::NSEC3)).cloned().collect::<Vec<_>>();
+            return match dnssec::prove_wildcard(&owner, *labels as usize, &proof) {
+                Denial::Proven => Security::Secure,
+                Denial::Insecure => Security::Insecure,
+                Denial::Unproven => Security::Bogus(format!("wildcard answer for {}. without proof", owner)),
+            }
+        }
+        Security::Secure
+    }
+
+    /// Walks from the closest trust anchor down to `name`, one label at a
+    /// time, looking for the zone cuts in between and validating the keys
+    /// of every signed zone on the way.
+    fn trust(&self, name: &str, resolve: Resolve) -> Trust {
+        let name = key(name);
+        let Some(anchor) = self
+            .anchors
+            .iter()
+            .map(|a| key(&a.domain()))
+            .filter(|zone| is_subdomain(&name, zone))
+            .max_by_key(|zone| zone.len())
+        else {
+            return Trust::Insecure
+        };
+        let (mut zone, mut keys) = match self.cut(&anchor, None, resolve) {
+            Cut::Secure(keys) => (anchor.clone(), keys),
+            Cut::Insecure | Cut::None => return Trust::Insecure,
+            Cut::Bogus(reason) => return Trust::Bogus(reason),
+        };
+        let below = name.strip_suffix(&anchor).unwrap_or(&name).trim_end_matches('.');
+        let labels = match below {
+            "" => vec![],
+            below => below.split('.').collect::<Vec<_>>(),
+        };
+        for i in (0..labels.len()).rev() {
+            let child = match anchor.as_str() {
+                "" => labels[i..].join("."),
+                anchor => format!("{}.{}", labels[i..].join("."), anchor),
+            };
+            match self.cut(&child, Some((&zone, &keys)), resolve) {
+                Cut::Secure(child_keys) => (zone, keys) = (child, child_keys),
+                Cut::Insecure => return Trust::Insecure,
+                Cut::None => {}
+                Cut::Bogus(reason) => return Trust::Bogus(reason),
+            }
+        }
+        Trust::Secure { zone, keys }
+    }
+
+    /// Finds out whether a zone starts at `name`, from the cache if we can:
+    /// with a `parent` zone by asking for DS records, and at an anchor by
+    /// matching its DNSKEYs against the anchor.
+    fn cut(&self, name: &str, parent: Option<(&str, &[Answer])>, resolve: Resolve) -> Cut {
+        let now = Instant::now();
+        let cuts = self.cuts.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(cached) = cuts.get(name).filter(|c| c.expires > now) {
+            return cached.cut.clone()
+        }
+        drop(cuts);
+        let (cut, ttl) = match parent {
+            None => {
+                let anchors = self.anchors.iter().filter(|a| key(&a.domain()) == name).collect::<Vec<_>>();
+                self.zone_keys(name, &anchors, resolve)
+            }
+            Some((parent, keys)) => self.delegation(name, parent, keys, resolve),
+        };
+        let ttl = match cut {
+            Cut::Bogus(_) => BOGUS_TTL,
+            _ => Duration::from_secs(ttl.min(MAX_KEY_TTL) as u64),
+        };
+        let cached = Cached { cut: cut.clone(), expires: now + ttl };
+        self.cuts.lock().unwrap_or_else(PoisonError::into_inner).insert(name.to_string(), cached);
+        cut
+    }
+
+    /// Asks `parent` for the DS records of `name`: their presence makes it a
+    /// signed zone, proven absence with NS an unsigned one.
+    fn delegation(&self, name: &str, parent: &str, keys: &[Answer], resolve: Resolve) -> (Cut, u32) {
+        let response = match fetch(name, Ty::DS, resolve) {
+            Ok(response) if matches!(response.rcode(), Rcode::NoError | Rcode::NXDomain) => response,
+            Ok(response) => return (Cut::Bogus(format!("DS query for {}. answered {}", name, response.rcode())), 0),
+            Err(e) => return (Cut::Bogus(format!("DS query for {}. failed: {:#}", name, e)), 0),
+        };
+        let now = dnssec::now();
+        let signed_by_parent = |rrset: &[Answer], signatures: &[Answer]| {
+            signatures.iter().any(|sig| {
+                matches!(sig.data(), Data::RRSIG { signer, .. } if key(&signer.to_string()) == parent)
+                    && dnssec::verify_rrset(rrset, sig, keys, now)
+            })
+        };
+        let answers = rrsets(&response.answers);
+        if let Some((ds, signatures)) = answers.iter().find(|(rrset, _)| rrset[0].ty() == Ty::DS && key(&rrset[0].domain()) == name) {
+            if !signed_by_parent(ds, signatures) {
+                return (Cut::Bogus(format!("DS records of {}. are not signed by {}.", name, parent)), 0)
+            }
+            return self.zone_keys(name, &ds.iter().collect::<Vec<_>>(), resolve)
+        }
+        if answers.iter().any(|(rrset, _)| rrset[0].ty() == Ty::CNAME) {
+            // an alias is never a zone cut
+            return (Cut::None, ttl_of(&response.answers))
+        }
+        let proof = rrsets(&response.authorities)
+            .into_iter()
+            .filter(|(rrset, signatures)| matches!(rrset[0].ty(), Ty::NSEC | Ty::NSEC3) && signed_by_parent(rrset, signatures))
+            .flat_map(|(rrset, _)| rrset)
+            .collect::<Vec<_>>();
+        let ttl = ttl_of(&response.authorities);
+        let denial = match response.rcode() {
+            Rcode::NXDomain => dnssec::prove_nxdomain(name, &proof),
+            _ => dnssec::prove_nodata(name, Ty::DS, &proof),
+        };
+        match denial {
+            Denial::Proven if dnssec::is_delegation(name, &proof) => (Cut::Insecure, ttl),
+            Denial::Proven => (Cut::None, ttl),
+            Denial::Insecure => (Cut::Insecure, ttl),
+            Denial::Unproven => (Cut::Bogus(format!("no proof that {}. has no DS records", name)), 0),
+        }
+    }
+
+    /// Fetches the DNSKEY RRset of `zone` and accepts it if one of the keys
+    /// the `trusted` DS or DNSKEY records point at has signed it.
+    fn zone_keys(&self, zone: &str, trusted: &[&Answer], resolve: Resolve) -> (Cut, u32) {
+        let supported = trusted.iter().any(|t| match t.data() {
+            Data::DS { algorithm, digest_type, .. } => {
+                dnssec::is_supported(*algorithm) && matches!(*digest_type, dnssec::SHA1 | dnssec::SHA256 | dnssec::SHA384)
+            }
+            Data::DNSKEY { algorithm, .. } => dnssec::is_supported(*algorithm),
+            _ => false,
+        });
+        if !supported {
+            // RFC 4035 section 5.2: treat the zone as unsigned
+            return (Cut::Insecure, MAX_KEY_TTL)
+        }
+        let response = match fetch(zone, Ty::DNSKEY, resolve) {
+            Ok(response) => response,
+            Err(e) => return (Cut::Bogus(format!("DNSKEY query for {}. failed: {:#}", zone, e)), 0),
+        };
+        let Some((dnskeys, signatures)) = rrsets(&response.answers)
+            .into_iter()
+            .find(|(rrset, _)| rrset[0].ty() == Ty::DNSKEY && key(&rrset[0].domain()) == zone)
+        else {
+            return (Cut::Bogus(format!("{}. has no DNSKEY records", zone)), 0)
+        };
+        let entry = dnskeys
+            .iter()
+            .filter(|k| dnssec::is_zone_key(k))
+            .filter(|k| trusted.iter().any(|t| dnssec::ds_matches(t.data(), k) || t.data() == k.data()))
+            .cloned()
+            .collect::<Vec<_>>();
+        let now = dnssec::now();
+        let signed = signatures.iter().any(|sig| {
+            matches!(sig.data(), Data::RRSIG { signer, .. } if key(&signer.to_string()) == zone)
+                && dnssec::verify_rrset(&dnskeys, sig, &entry, now)
+        });
+        if !signed {
+            return (Cut::Bogus(format!("DNSKEY records of {}. are not signed by a trusted key", zone)), 0)
+        }
+        let ttl = ttl_of(&dnskeys);
+        (Cut::Secure(dnskeys.into_iter().filter(dnssec::is_zone_key).collect()), ttl)
+    }
+}
+
+impl Security {
+    /// The combined verdict: bogus if either part is, secure if both are.
+    fn and(self, other: Security) -> Security {
+        match (self, other) {
+            (Security::Bogus(reason), _) | (_, Security::Bogus(reason)) => Security::Bogus(reason),
+            (Security::Secure, Security::Secure) => Security::Secure,
+            _ => Security::Insecure,
+        }
+    }
+}
+
+/// Asks for `name`/`ty` with signatures and without upstream validation.
+fn fetch(name: &str, ty: Ty, resolve: Resolve) -> anyhow::Result<Message> {
+    let mut edns = Edns::default();
+    edns.set_dnssec_ok(true);
+    let query = MessageBuilder::new()
+        .set_id(rand::random())
+        .set_qr(false)
+        .set_rd(true)
+        .set_cd(true)
+        .add_question(Question::with_type(name, ty, Class::IN))
+        .set_edns(edns)
+        .finish();
+    resolve(&query)
+}
+
+/// Groups records into RRsets, each with the RRSIGs covering it.
+fn rrsets(records: &[Answer]) -> Vec<(Vec<Answer>, Vec<Answer>)> {
+    let mut sets: Vec<(Vec<Answer>, Vec<Answer>)> = vec![];
+    for record in records.iter().filter(|r| r.ty() != Ty::RRSIG) {
+        match sets.iter_mut().find(|(rrset, _)| rrset[0].rrset_key() == record.rrset_key()) {
+            Some((rrset, _)) => rrset.push(record.clone()),
+            None => sets.push((vec![record.clone()], vec![])),
+        }
+    }
+    for sig in records.iter().filter(|r| r.ty() == Ty::RRSIG) {
+        let Data::RRSIG { type_covered, .. } = sig.data() else {
+            continue
+        };
+        let owner = sig.rrset_key().0;
+        if let Some((_, signatures)) = sets.iter_mut().find(|(rrset, _)| rrset[0].ty() == *type_covered && rrset[0].rrset_key().0 == owner) {
+            signatures.push(sig.clone());
+        }
+    }
+    sets
+}
+
+fn ttl_of(records: &[Answer]) -> u32 {
+    records.iter().map(Answer::ttl).min().unwrap_or(0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use crate::edns::Edns;
+    use crate::header::Rcode;
+    use crate::message::{Class, Message, MessageBuilder, Ty};
+    use crate::pool::ThreadPool;
+    use crate::question::Question;
+    use crate::server::Handler;
+    use crate::validator::Validator;
+    use crate::zone::{Catalog, Zone};
+
+    /// Signed with pyca/cryptography: the root with RSA/SHA-256 and NSEC,
+    /// example. with ECDSA P-256 and NSEC3, ed.example. below it with
+    /// Ed25519, and bogus. with P-384 but an A record changed after signing.
+    fn testdata() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/testdata/dnssec")
+    }
+
+    /// One authoritative server for all test zones, plus the unsigned
+    /// insecure. that the root delegates to without DS.
+    fn authority() -> Handler {
+        let mut zones = ["root", "example", "ed.example", "bogus"]
+            .iter()
+            .map(|name| Zone::load(&testdata().join(format!("{}.zone", name)), None).unwrap())
+            .collect::<Vec<_>>();
+        zones.push(Zone::parse("$TTL 3600\n@ SOA ns hostmaster 1 2 3 4 300\n@ NS ns\nwww A 192.0.2.40\n", "insecure").unwrap());
+        Handler::new(None, Arc::new(ThreadPool::new("resolve", 1, 1))).with_catalog(Catalog::new(zones).unwrap())
+    }
+
+    fn ask(validator: &Validator, authority: &Handler, name: &str, ty: Ty) -> Message {
+        let mut edns = Edns::default();
+        edns.set_dnssec_ok(true);
+        let query = MessageBuilder::new()
+            .set_qr(false)
+            .set_rd(true)
+            .add_question(Question::with_type(name, ty, Class::IN))
+            .set_edns(edns)
+            .finish();
+        // the DS and DNSKEY queries must look like queries to real resolvers
+        let resolve = |m: &Message| {
+            assert!(!m.qr(), "{} {} was sent with QR set", m.questions[0].domain(), m.questions[0].ty());
+            Ok(authority.handle(&m.clone().serialize()))
+        };
+        validator.resolve(&query, &resolve).unwrap()
+    }
+
+    fn validator() -> Validator {
+        Validator::from_file(&testdata().join("anchor")).unwrap()
+    }
+
+    #[test]
+    fn secure_answers_are_authenticated() {
+        let (validator, authority) = (validator(), authority());
+        let response = ask(&validator, &authority, "www.example", Ty::A);
+        assert!(response.ad() && !response.cd());
+        assert_eq!(response.answers[0].data().to_string(), "192.0.2.10");
+
+        let response = ask(&validator, &authority, "alias.example", Ty::A);
+        assert!(response.ad());
+        assert_eq!(response.answers.iter().filter(|a| a.ty() != Ty::RRSIG).count(), 2);
+
+        // P-256 delegates to Ed25519 through an NSEC3-signed DS
+        let response = ask(&validator, &authority, "www.ed.example", Ty::A);
+        assert!(response.ad());
+        assert_eq!(response.answers[0].data().to_string(), "192.0.2.20");
+        assert!(ask(&validator, &authority, "example", Ty::DNSKEY).ad());
+    }
+
+    #[test]
+    fn denials_of_existence_are_authenticated() {
+        let (validator, authority) = (validator(), authority());
+        for (name, ty, rcode) in [
+            // NSEC3: no name, no type, an empty non-terminal
+            ("missing.example", Ty::A, Rcode::NXDomain),
+            ("www.example", Ty::AAAA, Rcode::NoError),
+            ("a.example", Ty::A, Rcode::NoError),
+            // NSEC, in the root and below a DS
+            ("nothing", Ty::A, Rcode::NXDomain),
+            ("missing.ed.example", Ty::A, Rcode::NXDomain),
+            ("www.ed.example", Ty::TXT, Rcode::NoError),
+            // the root proves that insecure. is unsigned
+            ("insecure", Ty::DS, Rcode::NoError),
+        ] {
+            let response = ask(&validator, &authority, name, ty);
+            assert_eq!(response.rcode(), rcode, "{} {}", name, ty);
+            assert!(response.ad(), "{} {}", name, ty);
+            assert!(response.answers.is_empty(), "{} {}", name, ty);
+        }
+    }
+
+    #[test]
+    fn insecure_and_bogus_answers() {
+        let (validator, authority) = (validator(), authority());
+        let response = ask(&validator, &authority, "www.insecure", Ty::A);
+        assert_eq!((response.rcode(), response.ad()), (Rcode::NoError, false));
+        assert_eq!(response.answers[0].data().to_string(), "192.0.2.40");
+
+        let response = ask(&validator, &authority, "www.bogus", Ty::A);
+        assert_eq!((response.rcode(), response.answers.len()), (Rcode::ServFail, 0));
+
+        // an answer from a signed zone that lost its signatures on the way
+        let stripped = |m: &Message| {
+            let mut response = authority.handle(&m.clone().serialize());
+            if m.questions[0].ty() == Ty::A {
+                response.retain(|r| r.ty() != Ty::RRSIG);
+            }
+            Ok(response)
+        };
+        let query = MessageBuilder::new().add_question(Question::from_domain_name("www.example")).finish();
+        let response = validator.resolve(&query, &stripped).unwrap();
+        assert_eq!(response.rcode(), Rcode::ServFail);
+        // and an NXDOMAIN without its proof
+        let unproven = |m: &Message| {
+            let mut response = authority.handle(&m.clone().serialize());
+            if m.questions[0].ty() == Ty::A {
+                response.retain(|r| r.ty() != Ty::NSEC3);
+            }
+            Ok(response)
+        };
+        let query = MessageBuilder::new().add_question(Question::from_domain_name("missing.example")).finish();
+        assert_eq!(validator.resolve(&query, &unproven).unwrap().rcode(), Rcode::ServFail);
+
+        // without the right anchor nothing can be validated
+        let response = ask(&Validator::default(), &authority, "www.example", Ty::A);
+        assert_eq!(response.rcode(), Rcode::ServFail);
+    }
+}
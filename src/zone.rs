@@ -0,0 +1,1092 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock, Weak};
+use std::thread;
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context};
+use bytes::Bytes;
+use crate::answer::{Answer, Data};
+use crate::dnssec::{self, denial_covers, denial_matches, wildcard};
+use crate::encoding::{from_base32hex, from_base64, from_hex, parse_timestamp};
+use crate::header::Rcode;
+use crate::message::{Class, Labels, Ty};
+use crate::signer::{Signer, SigningKey};
+
+/// Longest CNAME chain followed, inside one zone or while recursing,
+/// before giving up.
+pub(crate) const MAX_CNAME_CHAIN: usize = 8;
+/// Nesting limit for `$INCLUDE`, which also stops include cycles.
+const MAX_INCLUDE_DEPTH: usize = 8;
+/// How often signed zones are checked for signatures due for a refresh.
+const RESIGN_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A zone file given on the command line as `[origin=]path`, or as a
+/// `[[zone]]` of the config file. Without an origin the zone is rooted at
+/// the owner of its SOA record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneSpec {
+    pub origin: Option<String>,
+    pub path: PathBuf,
+}
+
+impl FromStr for ZoneSpec {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.split_once('=') {
+            Some((origin, path)) => Self { origin: Some(origin.to_string()), path: path.into() },
+            None => Self { origin: None, path: s.into() },
+        })
+    }
+}
+
+/// The zones we are authoritative for.
+#[derive(Debug, Default)]
+pub struct Catalog {
+    /// Each zone is replaced whole when it is re-signed, so lookups never
+    /// see a half-signed zone.
+    zones: RwLock<Vec<Arc<Zone>>>,
+}
+
+impl Catalog {
+    pub fn new(zones: Vec<Zone>) -> anyhow::Result<Self> {
+        for (i, zone) in zones.iter().enumerate() {
+            if zones[..i].iter().any(|z| z.origin == zone.origin) {
+                bail!("zone {} is loaded twice", zone.apex())
+            }
+        }
+        Ok(Self { zones: RwLock::new(zones.into_iter().map(Arc::new).collect()) })
+    }
+
+    pub fn load(specs: &[ZoneSpec]) -> anyhow::Result<Self> {
+        let zones = specs
+            .iter()
+            .map(|spec| Zone::load(&spec.path, spec.origin.as_deref()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Self::new(zones)
+    }
+
+    /// Signs every zone that owns some of `keys` with those keys. A key for
+    /// a zone we don't serve is an error.
+    pub fn sign(self, keys: Vec<SigningKey>, validity: Duration) -> anyhow::Result<Self> {
+        let mut zones = self.zones.into_inner().unwrap();
+        let mut keys = keys;
+        for zone in zones.iter_mut() {
+            let (own, others): (Vec<_>, Vec<_>) = keys.into_iter().partition(|k| k.owner() == zone.origin);
+            keys = others;
+            if !own.is_empty() {
+                *zone = Arc::new(zone.sign(Arc::new(Signer::new(own, validity)), dnssec::now())?);
+            }
+        }
+        if let Some(key) = keys.first() {
+            bail!("key {} is for {}., which is not one of our zones", key.tag(), key.owner())
+        }
+        Ok(Self { zones: RwLock::new(zones) })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zones.read().unwrap_or_else(PoisonError::into_inner).is_empty()
+    }
+
+    /// The most specific zone containing `name`.
+    pub fn find(&self, name: &str) -> Option<Arc<Zone>> {
+        let zones = self.zones.read().unwrap_or_else(PoisonError::into_inner);
+        zones.iter().filter(|z| z.contains(name)).max_by_key(|z| z.origin.len()).cloned()
+    }
+
+    /// The DS records the parents of the zones we sign should publish.
+    pub fn ds_records(&self) -> Vec<Answer> {
+        let zones = self.zones.read().unwrap_or_else(PoisonError::into_inner);
+        zones.iter().flat_map(|z| z.signer.iter().flat_map(|s| s.ds_records())).collect()
+    }
+
+    /// Re-signs the zones whose signatures are due for a refresh.
+    pub fn refresh_signatures(&self, now: u32) {
+        let zones = self.zones.read().unwrap_or_else(PoisonError::into_inner);
+        let resigned = zones.iter().filter_map(|z| z.resign(now)).collect::<Vec<_>>();
+        drop(zones);
+        for zone in resigned {
+            match zone {
+                Ok(zone) => {
+                    let mut zones = self.zones.write().unwrap_or_else(PoisonError::into_inner);
+                    if let Some(old) = zones.iter_mut().find(|z| z.origin == zone.origin) {
+                        *old = Arc::new(zone);
+                    }
+                }
+                Err(e) => error!("Failed to re-sign: {:#}", e),
+            }
+        }
+    }
+
+    /// Keeps the signatures of the zones we sign fresh, until the catalog
+    /// is dropped for a reloaded one.
+    pub fn keep_signed(catalog: Weak<Self>) {
+        loop {
+            thread::sleep(RESIGN_CHECK_INTERVAL);
+            let Some(catalog) = catalog.upgrade() else {
+                return
+            };
+            catalog.refresh_signatures(dnssec::now());
+        }
+    }
+}
+
+/// An authoritative zone loaded from an RFC 1035 master file.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    /// Lowercased apex without the trailing dot; empty for the root zone.
+    origin: String,
+    soa: Answer,
+    /// Records by lowercased owner name.
+    records: BTreeMap<String, Vec<Answer>>,
+    /// Set for zones we sign ourselves.
+    signer: Option<Arc<Signer>>,
+    /// When our signatures are due for a refresh.
+    resign_at: u32,
+}
+
+/// Everything a zone has to say about one question.
+#[derive(Debug)]
+pub struct ZoneAnswer {
+    pub rcode: Rcode,
+    pub authoritative: bool,
+    pub answers: Vec<Answer>,
+    pub authorities: Vec<Answer>,
+    pub additionals: Vec<Answer>,
+}
+
+impl Zone {
+    pub fn load(path: &Path, origin: Option<&str>) -> anyhow::Result<Self> {
+        let origin = origin.map(|o| absolute(o, "")).transpose()?;
+        let mut loader = Loader::default();
+        loader.load_file(path, origin.clone().unwrap_or_default(), 0)?;
+        Self::from_records(loader.records, origin).with_context(|| format!("{}", path.display()))
+    }
+
+    /// Parses master-file text; `$INCLUDE` paths are relative to the
+    /// working directory.
+    #[allow(dead_code)]
+    pub fn parse(text: &str, origin: &str) -> anyhow::Result<Self> {
+        let origin = absolute(origin, "")?;
+        let mut loader = Loader::default();
+        loader.load_text(text, origin.clone(), Path::new("."), 0)?;
+        Self::from_records(loader.records, Some(origin))
+    }
+
+    fn from_records(records: Vec<Answer>, origin: Option<String>) -> anyhow::Result<Self> {
+        let soas = records.iter().filter(|r| r.ty() == Ty::SOA).collect::<Vec<_>>();
+        let soa = match soas[..] {
+            [soa] => soa.clone(),
+            [] => bail!("zone has no SOA record"),
+            _ => bail!("zone has {} SOA records", soas.len()),
+        };
+        let apex = key(&soa.domain());
+        if let Some(origin) = origin.filter(|o| key(o) != apex) {
+            bail!("SOA owner {}. is not the zone origin {}.", soa.domain(), origin)
+        }
+        let mut by_name = BTreeMap::<String, Vec<Answer>>::new();
+        for record in records {
+            let name = key(&record.domain());
+            if !is_subdomain(&name, &apex) {
+                bail!("{}. is outside zone {}.", record.domain(), apex)
+            }
+            if record.class() != soa.class() {
+                bail!("{}. has class {}, the zone is {}", record.domain(), record.class(), soa.class())
+            }
+            by_name.entry(name).or_default().push(record);
+        }
+        Ok(Self { origin: apex, soa, records: by_name, signer: None, resign_at: 0 })
+    }
+
+    /// The zone signed by `signer`, in place of any signatures and NSEC or
+    /// NSEC3 records it had.
+    pub fn sign(&self, signer: Arc<Signer>, now: u32) -> anyhow::Result<Self> {
+        let records = self.records.values().flatten().cloned().collect();
+        let signed = signer.sign(&self.origin, records, now).with_context(|| format!("signing {}", self.apex()))?;
+        let mut zone = Self::from_records(signed, Some(self.origin.clone()))?;
+        zone.resign_at = signer.refresh_at(now);
+        zone.signer = Some(signer);
+        Ok(zone)
+    }
+
+    /// The zone signed afresh, if we sign it and the signatures are due.
+    fn resign(&self, now: u32) -> Option<anyhow::Result<Self>> {
+        let signer = self.signer.as_ref().filter(|_| now.wrapping_sub(self.resign_at) as i32 >= 0)?;
+        Some(self.sign(signer.clone(), now))
+    }
+
+    /// Apex in presentation format, with the trailing dot.
+    pub fn apex(&self) -> String {
+        format!("{}.", self.origin)
+    }
+
+    pub fn class(&self) -> Class {
+        self.soa.class()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        is_subdomain(&key(name), &self.origin)
+    }
+
+    /// Answers `qname`/`qtype`, which must be inside this zone: records with
+    /// AA set, a referral at a zone cut, or NXDOMAIN/NODATA with the SOA.
+    /// With `dnssec` (the client set DO) the RRSIGs of a signed zone come
+    /// along, and so do the NSEC or NSEC3 records that prove a denial.
+    pub fn lookup(&self, qname: &str, qtype: Ty, dnssec: bool) -> ZoneAnswer {
+        let mut response = ZoneAnswer {
+            rcode: Rcode::NoError,
+            authoritative: true,
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        };
+        let mut name = key(qname);
+        for _ in 0..=MAX_CNAME_CHAIN {
+            match self.step(&name, qtype, dnssec, &mut response) {
+                Some(target) if self.contains(&target)
+                    && !response.answers.iter().any(|a| key(&a.domain()) == target) => name = target,
+                _ => break,
+            }
+        }
+        response
+    }
+
+    /// Resolves one name of a CNAME chain, returning the alias target to
+    /// continue with if there is one.
+    fn step(&self, name: &str, qtype: Ty, dnssec: bool, response: &mut ZoneAnswer) -> Option<String> {
+        // the DS records at a cut belong to this side of it
+        if let Some(cut) = self.delegation(name).filter(|cut| qtype != Ty::DS || cut != name) {
+            let ns = self.rrset(&cut, Ty::NS);
+            response.authoritative = !response.answers.is_empty();
+            self.add_addresses(&ns, &mut response.additionals);
+            response.authorities.extend(ns);
+            if dnssec {
+                match self.rrset(&cut, Ty::DS) {
+                    ds if ds.is_empty() => response.authorities.extend(self.denial(&cut, false)),
+                    ds => response.authorities.extend(self.signed(ds)),
+                }
+            }
+            return None
+        }
+        let Some(records) = self.records.get(name) else {
+            let nxdomain = !self.has_descendants(name);
+            if nxdomain {
+                let encloser = self.closest_encloser(name);
+                if self.records.contains_key(&wildcard(encloser)) {
+                    return self.expand(name, encloser, qtype, dnssec, response)
+                }
+                response.rcode = Rcode::NXDomain;
+            }
+            self.add_negative(name, nxdomain, dnssec, response);
+            return None
+        };
+        self.answer_from(name, records, qtype, dnssec, response)
+    }
+
+    /// Answers from the records `name` owns: those of the type asked for,
+    /// a CNAME to follow, or NODATA.
+    fn answer_from(&self, name: &str, records: &[Answer], qtype: Ty, dnssec: bool, response: &mut ZoneAnswer) -> Option<String> {
+        let matching = records
+            .iter()
+            .filter(|r| qtype == Ty::ANY || r.ty() == qtype)
+            .cloned()
+            .collect::<Vec<_>>();
+        if !matching.is_empty() {
+            self.add_addresses(&matching, &mut response.additionals);
+            match dnssec && qtype != Ty::ANY && qtype != Ty::RRSIG {
+                true => response.answers.extend(self.signed(matching)),
+                false => response.answers.extend(matching),
+            }
+            return None
+        }
+        if let Some(cname) = records.iter().find(|r| r.ty() == Ty::CNAME) {
+            match dnssec {
+                true => response.answers.extend(self.signed(vec![cname.clone()])),
+                false => response.answers.push(cname.clone()),
+            }
+            return match cname.data() {
+                Data::CNAME(target) => Some(key(&target.to_string())),
+                _ => None,
+            }
+        }
+        self.add_negative(name, false, dnssec, response);
+        None
+    }
+
+    /// Answers `name`, which doesn't exist, from the wildcard below its
+    /// closest encloser (RFC 4592): the wildcard's records with `name` as
+    /// their owner, and for DNSSEC clients the proof that `name` itself
+    /// doesn't exist, which is what lets a validator accept the expansion.
+    fn expand(&self, name: &str, encloser: &str, qtype: Ty, dnssec: bool, response: &mut ZoneAnswer) -> Option<String> {
+        let source = wildcard(encloser);
+        let mut expanded = ZoneAnswer {
+            rcode: Rcode::NoError,
+            authoritative: true,
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        };
+        let target = self.answer_from(&source, &self.records[&source], qtype, dnssec, &mut expanded);
+        let owner = Labels::from_domain(name);
+        for record in expanded.answers.iter_mut() {
+            record.set_name(owner.clone());
+        }
+        if dnssec {
+            for record in self.wildcard_proof(name, encloser, expanded.answers.is_empty()) {
+                if !expanded.authorities.contains(&record) {
+                    expanded.authorities.push(record);
+                }
+            }
+        }
+        response.answers.extend(expanded.answers);
+        response.authorities.extend(expanded.authorities);
+        response.additionals.extend(expanded.additionals);
+        target
+    }
+
+    /// What a wildcard answer for `name` needs besides the wildcard's own
+    /// records: the NSEC covering `name`, or the NSEC3 covering the name
+    /// just below the closest encloser, plus for NODATA (`nodata`) the
+    /// NSEC3 matching the encloser itself.
+    fn wildcard_proof(&self, name: &str, encloser: &str, nodata: bool) -> Vec<Answer> {
+        let chain = self
+            .records
+            .values()
+            .flatten()
+            .filter(|r| matches!(r.ty(), Ty::NSEC | Ty::NSEC3))
+            .collect::<Vec<_>>();
+        let mut proof: Vec<&Answer> = vec![];
+        if chain.iter().all(|r| r.ty() == Ty::NSEC) {
+            proof.extend(chain.iter().find(|r| denial_covers(r, name)));
+        } else {
+            let mut next_closer = name;
+            while parent(next_closer) != encloser {
+                next_closer = parent(next_closer);
+            }
+            proof.extend(chain.iter().find(|r| denial_covers(r, next_closer)));
+            if nodata {
+                proof.extend(chain.iter().find(|r| denial_matches(r, encloser)));
+            }
+        }
+        proof.into_iter().flat_map(|record| self.signed(vec![record.clone()])).collect()
+    }
+
+    /// The deepest name at or above `name` that exists, if only as an empty
+    /// non-terminal; the apex at the latest.
+    fn closest_encloser<'a>(&self, name: &'a str) -> &'a str {
+        let mut encloser = name;
+        while !self.records.contains_key(encloser) && !self.has_descendants(encloser) {
+            encloser = parent(encloser);
+        }
+        encloser
+    }
+
+    /// The authority section of NXDOMAIN or NODATA: the SOA, and for DNSSEC
+    /// clients the signed proof.
+    fn add_negative(&self, name: &str, nxdomain: bool, dnssec: bool, response: &mut ZoneAnswer) {
+        match dnssec {
+            true => {
+                response.authorities.extend(self.signed(vec![self.negative_soa()]));
+                response.authorities.extend(self.denial(name, nxdomain));
+            }
+            false => response.authorities.push(self.negative_soa()),
+        }
+    }
+
+    /// `rrset` followed by the RRSIGs covering it, if the zone is signed.
+    fn signed(&self, rrset: Vec<Answer>) -> Vec<Answer> {
+        let Some(first) = rrset.first() else {
+            return rrset
+        };
+        let (owner, ty) = (key(&first.domain()), first.ty());
+        let signatures = self
+            .rrset(&owner, Ty::RRSIG)
+            .into_iter()
+            .filter(|sig| matches!(sig.data(), Data::RRSIG { type_covered, .. } if *type_covered == ty));
+        let mut signed = rrset.clone();
+        signed.extend(signatures);
+        signed
+    }
+
+    /// The NSEC or NSEC3 records, with signatures, showing that `name` has
+    /// no data of the type asked for, or (`nxdomain`) doesn't exist and
+    /// there is no wildcard to synthesize it from.
+    fn denial(&self, name: &str, nxdomain: bool) -> Vec<Answer> {
+        let chain = self
+            .records
+            .values()
+            .flatten()
+            .filter(|r| matches!(r.ty(), Ty::NSEC | Ty::NSEC3))
+            .collect::<Vec<_>>();
+        let matching = |name: &str| chain.iter().find(|r| denial_matches(r, name));
+        let covering = |name: &str| chain.iter().find(|r| denial_covers(r, name));
+        let mut proof = vec![];
+        if let Some(record) = matching(name).filter(|_| !nxdomain) {
+            proof.push(record);
+        } else if chain.iter().all(|r| r.ty() == Ty::NSEC) {
+            proof.extend(covering(name));
+            if nxdomain {
+                proof.extend(covering(&wildcard(self.closest_encloser(name))));
+            }
+        } else {
+            // closest encloser proof: the deepest ancestor with an NSEC3,
+            // and the one covering the name just below it
+            let mut next_closer = name;
+            while next_closer.len() > self.origin.len() {
+                let encloser = parent(next_closer);
+                if let Some(record) = matching(encloser) {
+                    proof.push(record);
+                    proof.extend(covering(next_closer));
+                    if nxdomain {
+                        proof.extend(covering(&wildcard(encloser)));
+                    }
+                    break
+                }
+                next_closer = encloser;
+            }
+        }
+        let mut records = vec![];
+        for record in proof {
+            if !records.contains(*record) {
+                records.extend(self.signed(vec![(*record).clone()]));
+            }
+        }
+        records
+    }
+
+    /// The topmost zone cut at or above `name`, below the apex.
+    fn delegation(&self, name: &str) -> Option<String> {
+        let mut cuts = vec![];
+        let mut current = name;
+        while current.len() > self.origin.len() {
+            cuts.push(current);
+            current = parent(current);
+        }
+        cuts.into_iter()
+            .rev()
+            .find(|cut| !self.rrset(cut, Ty::NS).is_empty())
+            .map(str::to_string)
+    }
+
+    fn rrset(&self, name: &str, ty: Ty) -> Vec<Answer> {
+        self.records
+            .get(name)
+            .map(|records| records.iter().filter(|r| r.ty() == ty).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// An empty non-terminal exists even though it owns no records.
+    fn has_descendants(&self, name: &str) -> bool {
+        let suffix = format!(".{}", name);
+        self.records.keys().any(|k| k.ends_with(&suffix))
+    }
+
+    /// Adds in-zone A/AAAA records for the hosts named by NS, MX and SRV
+    /// records, which spares the client a follow-up query (and is the only
+    /// way to reach name servers below a zone cut).
+    fn add_addresses(&self, records: &[Answer], additionals: &mut Vec<Answer>) {
+        for record in records {
+            let host = match record.data() {
+                Data::NS(host) | Data::MX { exchange: host, .. } | Data::SRV { target: host, .. } => key(&host.to_string()),
+                _ => continue,
+            };
+            if !self.contains(&host) {
+                continue
+            }
+            for address in self.rrset(&host, Ty::A).into_iter().chain(self.rrset(&host, Ty::AAAA)) {
+                if !additionals.contains(&address) {
+                    additionals.push(address);
+                }
+            }
+        }
+    }
+
+    /// The SOA for the authority section of a negative answer, whose TTL is
+    /// capped by the SOA minimum (RFC 2308 section 3).
+    fn negative_soa(&self) -> Answer {
+        let mut soa = self.soa.clone();
+        if let Data::SOA { minimum, .. } = self.soa.data() {
+            soa.set_ttl(self.soa.ttl().min(*minimum));
+        }
+        soa
+    }
+}
+
+/// Reads a master file without treating it as a zone, e.g. root hints,
+/// which have no SOA.
+pub fn load_records(path: &Path) -> anyhow::Result<Vec<Answer>> {
+    let mut loader = Loader::default();
+    loader.load_file(path, String::new(), 0)?;
+    Ok(loader.records)
+}
+
+/// Parses master-file text that isn't a zone, such as a BIND `.key` file,
+/// whose records may come without a TTL.
+pub fn parse_records(text: &str, default_ttl: u32) -> anyhow::Result<Vec<Answer>> {
+    let mut loader = Loader { default_ttl: Some(default_ttl), ..Loader::default() };
+    loader.load_text(text, String::new(), Path::new("."), 0)?;
+    Ok(loader.records)
+}
+
+/// Master file state carried from one entry to the next.
+#[derive(Default)]
+struct Loader {
+    records: Vec<Answer>,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_owner: Option<String>,
+    last_class: Option<Class>,
+}
+
+impl Loader {
+    fn load_file(&mut self, path: &Path, origin: String, depth: usize) -> anyhow::Result<()> {
+        let text = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        self.load_text(&text, origin, dir, depth).with_context(|| format!("{}", path.display()))
+    }
+
+    /// `origin` is local to the file: an `$ORIGIN` here does not leak back
+    /// into the file that included it.
+    fn load_text(&mut self, text: &str, mut origin: String, dir: &Path, depth: usize) -> anyhow::Result<()> {
+        for entry in entries(text)? {
+            self.load_entry(&entry, &mut origin, dir, depth)
+                .with_context(|| format!("line {}", entry.line))?;
+        }
+        Ok(())
+    }
+
+    fn load_entry(&mut self, entry: &Entry, origin: &mut String, dir: &Path, depth: usize) -> anyhow::Result<()> {
+        let directive = entry.tokens[0].to_ascii_uppercase();
+        let args = entry.tokens[1..].iter().map(String::as_str).collect::<Vec<_>>();
+        match directive.as_str() {
+            "$ORIGIN" => {
+                let [name] = fields(&args)?;
+                *origin = absolute(name, origin)?;
+            }
+            "$TTL" => {
+                let [ttl] = fields(&args)?;
+                self.default_ttl = Some(parse_ttl(ttl).ok_or_else(|| anyhow!("bad TTL {:?}", ttl))?);
+            }
+            "$INCLUDE" => {
+                let (path, include_origin) = match args[..] {
+                    [path] => (path, origin.clone()),
+                    [path, name] => (path, absolute(name, origin)?),
+                    _ => bail!("$INCLUDE takes a file name and an optional origin"),
+                };
+                if depth >= MAX_INCLUDE_DEPTH {
+                    bail!("$INCLUDE nested more than {} deep", MAX_INCLUDE_DEPTH)
+                }
+                self.load_file(&dir.join(path), include_origin, depth + 1)?;
+            }
+            d if d.starts_with('$') => bail!("unknown directive {}", d),
+            _ => self.load_record(entry, origin)?,
+        }
+        Ok(())
+    }
+
+    /// `[owner] [ttl] [class] type rdata`, where TTL and class may come in
+    /// either order and a blank owner repeats the previous one.
+    fn load_record(&mut self, entry: &Entry, origin: &str) -> anyhow::Result<()> {
+        let mut tokens = entry.tokens.iter().map(String::as_str);
+        let owner = match entry.continued {
+            true => self.last_owner.clone().ok_or_else(|| anyhow!("first record has no owner name"))?,
+            false => absolute(tokens.next().unwrap_or_default(), origin)?,
+        };
+        let (mut ttl, mut class) = (None, None);
+        let ty = loop {
+            let token = tokens.next().ok_or_else(|| anyhow!("record for {} has no type", owner))?;
+            if ttl.is_none() {
+                if let Some(t) = parse_ttl(token) {
+                    ttl = Some(t);
+                    continue
+                }
+            }
+            if class.is_none() {
+                if let Ok(c) = token.parse::<Class>() {
+                    class = Some(c);
+                    continue
+                }
+            }
+            break token.parse::<Ty>()?
+        };
+        let data = parse_rdata(ty, &tokens.collect::<Vec<_>>(), origin).with_context(|| format!("{} {}", owner, ty))?;
+        if ttl.is_some() {
+            self.last_ttl = ttl;
+        }
+        let ttl = ttl
+            .or(self.default_ttl)
+            .or(self.last_ttl)
+            .ok_or_else(|| anyhow!("no TTL for {} and no $TTL in effect", owner))?;
+        let class = class.or(self.last_class).unwrap_or(Class::IN);
+        self.records.push(Answer::with_type(&owner, ty, class, ttl, data));
+        self.last_owner = Some(owner);
+        self.last_class = Some(class);
+        Ok(())
+    }
+}
+
+/// One logical master file entry, possibly spanning lines in parentheses.
+struct Entry {
+    line: usize,
+    /// Started with whitespace, so the owner is the previous entry's.
+    continued: bool,
+    /// Tokens with quotes removed and escapes left in place.
+    tokens: Vec<String>,
+}
+
+fn entries(text: &str) -> anyhow::Result<Vec<Entry>> {
+    let mut entries = vec![];
+    let mut tokens = vec![];
+    let mut token: Option<String> = None;
+    let (mut line, mut start, mut depth) = (1, 1, 0usize);
+    let mut continued = false;
+    let mut at_line_start = true;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if at_line_start && depth == 0 && tokens.is_empty() {
+            start = line;
+            continued = c == ' ' || c == '\t';
+        }
+        at_line_start = false;
+        match c {
+            ';' => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '"' => {
+                tokens.extend(token.take());
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            quoted.push('\\');
+                            quoted.extend(chars.next());
+                        }
+                        Some('\n') | None => bail!("line {}: unterminated string", line),
+                        Some(c) => quoted.push(c),
+                    }
+                }
+                tokens.push(quoted);
+            }
+            '\\' => {
+                let token = token.get_or_insert_with(String::new);
+                token.push('\\');
+                token.extend(chars.next());
+            }
+            '(' | ')' | '\n' => {
+                tokens.extend(token.take());
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth = depth.checked_sub(1).ok_or_else(|| anyhow!("line {}: unbalanced ')'", line))?,
+                    _ => {
+                        if depth == 0 && !tokens.is_empty() {
+                            entries.push(Entry { line: start, continued, tokens: std::mem::take(&mut tokens) });
+                        }
+                        line += 1;
+                        at_line_start = true;
+                    }
+                }
+            }
+            c if c.is_whitespace() => tokens.extend(token.take()),
+            c => token.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if depth > 0 {
+        bail!("line {}: unbalanced '('", start)
+    }
+    tokens.extend(token);
+    if !tokens.is_empty() {
+        entries.push(Entry { line: start, continued, tokens });
+    }
+    Ok(entries)
+}
+
+fn parse_rdata(ty: Ty, tokens: &[&str], origin: &str) -> anyhow::Result<Data> {
+    if tokens.first() == Some(&"\\#") {
+        return Data::from_generic(&ty, &tokens.join(" "))
+    }
+    let name = |s: &str| absolute(s, origin).map(|n| Labels::from_domain(&n));
+    Ok(match ty {
+        Ty::A => {
+            let [address] = fields(tokens)?;
+            Data::A(address.parse::<Ipv4Addr>()?.into())
+        }
+        Ty::AAAA => {
+            let [address] = fields(tokens)?;
+            Data::AAAA(address.parse::<Ipv6Addr>()?.into())
+        }
+        Ty::NS => Data::NS(name(fields::<1>(tokens)?[0])?),
+        Ty::CNAME => Data::CNAME(name(fields::<1>(tokens)?[0])?),
+        Ty::PTR => Data::PTR(name(fields::<1>(tokens)?[0])?),
+        Ty::MX => {
+            let [preference, exchange] = fields(tokens)?;
+            Data::MX { preference: preference.parse()?, exchange: name(exchange)? }
+        }
+        Ty::TXT => {
+            if tokens.is_empty() {
+                bail!("TXT needs at least one string")
+            }
+            Data::TXT(tokens.iter().map(|s| character_string(s)).collect::<anyhow::Result<_>>()?)
+        }
+        Ty::SOA => {
+            let [mname, rname, serial, refresh, retry, expire, minimum] = fields(tokens)?;
+            let interval = |s: &str| parse_ttl(s).ok_or_else(|| anyhow!("bad interval {:?}", s));
+            Data::SOA {
+                mname: name(mname)?,
+                rname: name(rname)?,
+                serial: serial.parse()?,
+                refresh: interval(refresh)?,
+                retry: interval(retry)?,
+                expire: interval(expire)?,
+                minimum: interval(minimum)?,
+            }
+        }
+        Ty::SRV => {
+            let [priority, weight, port, target] = fields(tokens)?;
+            Data::SRV { priority: priority.parse()?, weight: weight.parse()?, port: port.parse()?, target: name(target)? }
+        }
+        Ty::CAA => {
+            let [flags, tag, value] = fields(tokens)?;
+            if tag.is_empty() || !tag.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                bail!("bad CAA tag {:?}", tag)
+            }
+            Data::CAA { flags: flags.parse()?, tag: Bytes::copy_from_slice(tag.as_bytes()), value: unescape(value)?.into() }
+        }
+        Ty::DNSKEY => {
+            let ([flags, protocol, algorithm], key) = leading(tokens)?;
+            Data::DNSKEY {
+                flags: flags.parse()?,
+                protocol: protocol.parse()?,
+                algorithm: algorithm.parse()?,
+                public_key: from_base64(&key.concat())?.into(),
+            }
+        }
+        Ty::DS => {
+            let ([key_tag, algorithm, digest_type], digest) = leading(tokens)?;
+            Data::DS {
+                key_tag: key_tag.parse()?,
+                algorithm: algorithm.parse()?,
+                digest_type: digest_type.parse()?,
+                digest: from_hex(&digest.concat())?.into(),
+            }
+        }
+        Ty::RRSIG => {
+            let ([type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer], signature) = leading(tokens)?;
+            Data::RRSIG {
+                type_covered: type_covered.parse()?,
+                algorithm: algorithm.parse()?,
+                labels: labels.parse()?,
+                original_ttl: original_ttl.parse()?,
+                expiration: parse_timestamp(expiration)?,
+                inception: parse_timestamp(inception)?,
+                key_tag: key_tag.parse()?,
+                signer: name(signer)?,
+                signature: from_base64(&signature.concat())?.into(),
+            }
+        }
+        Ty::NSEC => {
+            let ([next], types) = leading(tokens)?;
+            Data::NSEC { next: name(next)?, types: types.iter().map(|t| t.parse()).collect::<anyhow::Result<_>>()? }
+        }
+        Ty::NSEC3 => {
+            let ([hash_algorithm, flags, iterations, salt, next_hashed], types) = leading(tokens)?;
+            Data::NSEC3 {
+                hash_algorithm: hash_algorithm.parse()?,
+                flags: flags.parse()?,
+                iterations: iterations.parse()?,
+                salt: if salt == "-" { Bytes::new() } else { from_hex(salt)?.into() },
+                next_hashed: from_base32hex(next_hashed)?.into(),
+                types: types.iter().map(|t| t.parse()).collect::<anyhow::Result<_>>()?,
+            }
+        }
+        Ty::NSEC3PARAM => {
+            let [hash_algorithm, flags, iterations, salt] = fields(tokens)?;
+            Data::NSEC3PARAM {
+                hash_algorithm: hash_algorithm.parse()?,
+                flags: flags.parse()?,
+                iterations: iterations.parse()?,
+                salt: if salt == "-" { Bytes::new() } else { from_hex(salt)?.into() },
+            }
+        }
+        _ => bail!("{} records must be written in the generic \\# form", ty),
+    })
+}
+
+fn fields<'a, const N: usize>(tokens: &[&'a str]) -> anyhow::Result<[&'a str; N]> {
+    <[&str; N]>::try_from(tokens).map_err(|_| anyhow!("expected {} fields, found {}", N, tokens.len()))
+}
+
+/// The first `N` tokens and the rest, for types that end in a free-form
+/// field such as a base64 key or a list of types (which may be empty).
+fn leading<'a, 'b, const N: usize>(tokens: &'b [&'a str]) -> anyhow::Result<([&'a str; N], &'b [&'a str])> {
+    if tokens.len() < N {
+        bail!("expected at least {} fields, found {}", N, tokens.len())
+    }
+    Ok((tokens[..N].try_into().unwrap(), &tokens[N..]))
+}
+
+fn character_string(s: &str) -> anyhow::Result<Bytes> {
+    let bytes = unescape(s)?;
+    if bytes.len() > 255 {
+        bail!("character string is {} octets long, the limit is 255", bytes.len())
+    }
+    Ok(bytes.into())
+}
+
+/// Resolves `\X` and `\DDD` escapes.
+fn unescape(s: &str) -> anyhow::Result<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue
+        }
+        match bytes.get(i + 1..i + 4).filter(|d| d.iter().all(u8::is_ascii_digit)) {
+            Some(digits) => {
+                let value: u16 = std::str::from_utf8(digits)?.parse()?;
+                out.push(u8::try_from(value).map_err(|_| anyhow!("escape \\{} is out of range", value))?);
+                i += 4;
+            }
+            None => {
+                out.push(*bytes.get(i + 1).ok_or_else(|| anyhow!("dangling escape in {:?}", s))?);
+                i += 2;
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// A TTL in seconds, or in BIND's unit form such as `1h30m` or `2W`.
+fn parse_ttl(s: &str) -> Option<u32> {
+    if s.bytes().all(|b| b.is_ascii_digit()) {
+        return s.parse().ok()
+    }
+    let mut total = 0u32;
+    let mut number: Option<u32> = None;
+    for c in s.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = Some(number.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+            continue
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        total = total.checked_add(number.take()?.checked_mul(unit)?)?;
+    }
+    number.is_none().then_some(total)
+}
+
+/// Makes `name` fully qualified relative to `origin`; the result has no
+/// trailing dot, so the root is the empty string.
+fn absolute(name: &str, origin: &str) -> anyhow::Result<String> {
+    if name.contains('\\') {
+        bail!("escapes in domain names are not supported: {}", name)
+    }
+    let name = match name.strip_suffix('.') {
+        _ if name == "@" => origin.to_string(),
+        Some(absolute) => absolute.to_string(),
+        None if origin.is_empty() => name.to_string(),
+        None => format!("{}.{}", name, origin),
+    };
+    if !name.is_empty() {
+        if name.split('.').any(|label| label.is_empty() || label.len() > 63) {
+            bail!("bad domain name {:?}", name)
+        }
+        if name.len() + 2 > 255 {
+            bail!("domain name {:?} is longer than 255 octets", name)
+        }
+    }
+    Ok(name)
+}
+
+/// Canonical form for comparing names: lowercase, no trailing dot.
+pub(crate) fn key(name: &str) -> String {
+    name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase()
+}
+
+/// Whether `name` is `parent` or below it; both in `key` form.
+pub(crate) fn is_subdomain(name: &str, parent: &str) -> bool {
+    parent.is_empty()
+        || name == parent
+        || name.strip_suffix(parent).and_then(|rest| rest.strip_suffix('.')).is_some_and(|label| !escapes_last(label))
+}
+
+/// `name` without its first label; the root is its own parent. Dots escaped
+/// inside a label don't separate labels.
+pub(crate) fn parent(name: &str) -> &str {
+    let mut escaped = false;
+    for (i, c) in name.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '.' if !escaped => return &name[i + 1..],
+            _ => escaped = false,
+        }
+    }
+    ""
+}
+
+/// Whether `s` ends in an unpaired backslash, i.e. escapes what follows it.
+fn escapes_last(s: &str) -> bool {
+    s.bytes().rev().take_while(|&b| b == b'\\').count() % 2 == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::answer::Data;
+    use crate::header::Rcode;
+    use crate::message::{Class, Ty};
+    use crate::zone::{is_subdomain, parent, parse_ttl, Catalog, Zone};
+
+    const EXAMPLE: &str = r#"
+$ORIGIN example.com.
+$TTL 1h
+@       IN  SOA ns1 hostmaster (
+                2024010101 ; serial
+                2h 15m 2w
+                300 )
+        IN  NS  ns1
+        IN  MX  10 mail
+ns1     IN  A   192.0.2.1
+mail    600 IN A 192.0.2.2
+        IN  AAAA 2001:db8::2
+www     CNAME mail
+txt     TXT "hello world" "semi\;colon" plain
+a.b.deep A 192.0.2.3
+sub     NS  ns.sub
+ns.sub  A   192.0.2.53
+_sip._tcp SRV 0 5 5060 mail
+odd     TYPE65280 \# 2 abcd
+"#;
+
+    fn example() -> Zone {
+        Zone::parse(EXAMPLE, "example.com").unwrap()
+    }
+
+    #[test]
+    fn master_file_syntax() {
+        let zone = example();
+        assert_eq!(zone.apex(), "example.com.");
+        let soa = &zone.soa;
+        assert_eq!(soa.ttl(), 3600);
+        assert_eq!(
+            soa.data().to_string(),
+            "ns1.example.com. hostmaster.example.com. 2024010101 7200 900 1209600 300"
+        );
+        let mail = &zone.records["mail.example.com"];
+        assert_eq!(mail.len(), 2);
+        assert_eq!((mail[0].ttl(), mail[1].ttl()), (600, 3600));
+        assert_eq!(mail[1].data().to_string(), "2001:db8::2");
+        let txt = &zone.records["txt.example.com"][0];
+        assert_eq!(txt.data().to_string(), "\"hello world\" \"semi;colon\" \"plain\"");
+        let odd = &zone.records["odd.example.com"][0];
+        assert_eq!((odd.ty(), odd.class()), (Ty::Unknown(65280), Class::IN));
+        assert_eq!(parse_ttl("1w2d3h4m5s"), Some(788645));
+        assert_eq!(parse_ttl("5m3"), None);
+    }
+
+    #[test]
+    fn include_keeps_its_own_origin() {
+        let dir = std::env::temp_dir().join(format!("zone-include-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("hosts.inc"), "$ORIGIN other.example.com.\nhost A 192.0.2.9\n").unwrap();
+        fs::write(
+            dir.join("example.zone"),
+            "$TTL 60\n@ SOA ns1 hostmaster 1 2 3 4 5\n$INCLUDE hosts.inc lab\nafter A 192.0.2.10\n",
+        )
+        .unwrap();
+        let zone = Zone::load(&dir.join("example.zone"), Some("example.com.")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(zone.records.contains_key("host.other.example.com"));
+        assert!(zone.records.contains_key("after.example.com"));
+
+        let err = Zone::parse("$TTL 60\n@ SOA ns1 hostmaster 1 2 3 4 5\nwww A 300.0.0.1\n", "example.com").unwrap_err();
+        assert_eq!(format!("{:#}", err), "line 3: www.example.com A: invalid IPv4 address syntax");
+        assert!(Zone::parse("$TTL 60\nwww.example.org. A 192.0.2.1\n@ SOA ns1 hm 1 2 3 4 5\n", "example.com").is_err());
+    }
+
+    #[test]
+    fn positive_and_negative_answers() {
+        let zone = example();
+        let found = zone.lookup("MAIL.example.com", Ty::A, false);
+        assert!(found.authoritative);
+        assert_eq!((found.rcode, found.answers.len()), (Rcode::NoError, 1));
+
+        let mx = zone.lookup("example.com", Ty::MX, false);
+        assert_eq!(mx.additionals.len(), 2);
+
+        let cname = zone.lookup("www.example.com", Ty::AAAA, false);
+        assert_eq!(cname.answers.iter().map(|a| a.ty()).collect::<Vec<_>>(), [Ty::CNAME, Ty::AAAA]);
+
+        let nodata = zone.lookup("ns1.example.com", Ty::AAAA, false);
+        assert_eq!((nodata.rcode, nodata.answers.len()), (Rcode::NoError, 0));
+        assert_eq!(nodata.authorities[0].ttl(), 300);
+
+        let empty_non_terminal = zone.lookup("b.deep.example.com", Ty::A, false);
+        assert_eq!(empty_non_terminal.rcode, Rcode::NoError);
+
+        let nxdomain = zone.lookup("nope.example.com", Ty::A, false);
+        assert_eq!(nxdomain.rcode, Rcode::NXDomain);
+        assert_eq!(nxdomain.authorities[0].ty(), Ty::SOA);
+    }
+
+    #[test]
+    fn escaped_dots_do_not_separate_labels() {
+        assert_eq!(parent("www\\.example"), "");
+        assert_eq!(parent("a\\\\.example"), "example");
+        assert!(!is_subdomain("www\\.example", "example"));
+        assert!(is_subdomain("a\\\\.example", "example"));
+        let nxdomain = example().lookup("www\\.example.com", Ty::A, false);
+        assert_eq!((nxdomain.rcode, nxdomain.answers.len()), (Rcode::NXDomain, 0));
+    }
+
+    #[test]
+    fn wildcards_are_expanded() {
+        let zone = Zone::parse(
+            "$TTL 60\n@ SOA ns hm 1 2 3 4 5\n*.wild A 192.0.2.4\nhost.wild AAAA ::1\n*.alias CNAME www\nwww A 192.0.2.1\n",
+            "example",
+        ).unwrap();
+        let found = zone.lookup("a.b.WILD.example", Ty::A, false);
+        assert_eq!((found.rcode, found.answers.len()), (Rcode::NoError, 1));
+        assert_eq!(found.answers[0].to_string(), "a.b.wild.example. 60 IN A 192.0.2.4");
+
+        let nodata = zone.lookup("a.wild.example", Ty::AAAA, false);
+        assert_eq!((nodata.rcode, nodata.answers.len(), nodata.authorities[0].ty()), (Rcode::NoError, 0, Ty::SOA));
+        // names that exist are never synthesized
+        let existing = zone.lookup("host.wild.example", Ty::A, false);
+        assert_eq!((existing.rcode, existing.answers.len()), (Rcode::NoError, 0));
+
+        let alias = zone.lookup("a.alias.example", Ty::A, false);
+        let chain = alias.answers.iter().map(|a| (a.domain(), a.ty())).collect::<Vec<_>>();
+        assert_eq!(chain, [("a.alias.example".to_string(), Ty::CNAME), ("www.example".to_string(), Ty::A)]);
+        assert_eq!(zone.lookup("nowhere.example", Ty::A, false).rcode, Rcode::NXDomain);
+    }
+
+    #[test]
+    fn referrals_carry_glue() {
+        let zone = example();
+        let referral = zone.lookup("www.sub.example.com", Ty::A, false);
+        assert!(!referral.authoritative);
+        assert!(referral.answers.is_empty());
+        assert_eq!(referral.authorities[0].data(), &Data::NS(crate::message::Labels::from_domain("ns.sub.example.com")));
+        assert_eq!(referral.additionals[0].domain(), "ns.sub.example.com");
+
+        let catalog = Catalog::new(vec![zone.clone(), Zone::parse("@ 60 SOA ns hm 1 2 3 4 5", "sub.example.com").unwrap()]).unwrap();
+        assert_eq!(catalog.find("www.sub.example.com").unwrap().apex(), "sub.example.com.");
+        assert_eq!(catalog.find("example.com.").unwrap().apex(), "example.com.");
+        assert!(catalog.find("example.org").is_none());
+        assert!(Catalog::new(vec![zone.clone(), zone]).is_err());
+    }
+}